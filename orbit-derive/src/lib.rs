@@ -0,0 +1,307 @@
+//! `#[derive(Orbit)]` for webrust models.
+//!
+//! #[derive(Debug, Orbit)]
+//! #[orbit(table = "users", soft_deletes, timestamps = false, connection = "reporting")]
+//! pub struct User {
+//!     pub id: i64,
+//!     pub email: String,
+//!     #[orbit(hidden)]
+//!     pub password_hash: String,
+//! }
+//!
+//! Generates `impl Orbit`, `impl sqlx::FromRow`, `impl serde::Serialize` (without
+//! the hidden fields; opt out with `#[orbit(serialize = false)]`) and one typed
+//! column constant per field (`User::EMAIL`). The generated code refers to
+//! `crate::orbit` and `crate::database`, so it is meant to be used inside the webrust crate.
+//!
+//! `#[orbit(observable)]` fires the model's `Observable::observers()` on writes.
+
+use heck::{ToShoutySnakeCase, ToSnakeCase};
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, LitBool, LitStr};
+
+#[proc_macro_derive(Orbit, attributes(orbit))]
+pub fn derive_orbit(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+/// Struct level `#[orbit(...)]` options
+struct Model {
+    table: Option<String>,
+    connection: Option<String>,
+    timestamps: bool,
+    soft_deletes: bool,
+    serialize: bool,
+    observable: bool,
+}
+
+/// Field level `#[orbit(...)]` options
+#[derive(Default)]
+struct Column {
+    primary_key: bool,
+    hidden: bool,
+    skip: bool,
+    fillable: bool,
+    guarded: bool,
+    cast: Option<LitStr>,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(&input.generics, "Orbit models cannot be generic"));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(syn::Error::new_spanned(name, "Orbit models need named fields")),
+        },
+        _ => return Err(syn::Error::new_spanned(name, "Orbit can only be derived for structs")),
+    };
+
+    let model = parse_model(&input)?;
+    // Same convention as `make:model`: "BlogPost" -> "blog_posts"
+    let table = model.table.unwrap_or_else(|| format!("{}s", name.to_string().to_snake_case()));
+    let connection = match &model.connection {
+        Some(connection) => quote!(::core::option::Option::Some(#connection)),
+        None => quote!(::core::option::Option::None),
+    };
+    let timestamps = model.timestamps;
+    let soft_deletes = model.soft_deletes;
+
+    let mut primary_key: Option<&Ident> = None;
+    let mut hidden = Vec::new();
+    let mut fillable = Vec::new();
+    let mut guarded = Vec::new();
+    let mut casts = Vec::new();
+    let mut decoded = Vec::new();
+    let mut serialized = Vec::new();
+    let mut constants = Vec::new();
+
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named field");
+        let ty = &field.ty;
+        let options = parse_column(field)?;
+        let column = ident.to_string().trim_start_matches("r#").to_string();
+
+        if !options.hidden {
+            serialized.push(quote!(state.serialize_field(#column, &self.#ident)?;));
+        }
+        if options.skip {
+            decoded.push(quote!(#ident: ::core::default::Default::default()));
+            continue;
+        }
+        if options.primary_key {
+            if primary_key.is_some() {
+                return Err(syn::Error::new_spanned(ident, "only one field can be #[orbit(primary_key)]"));
+            }
+            primary_key = Some(ident);
+        }
+        if options.hidden {
+            hidden.push(column.clone());
+        }
+        if options.fillable {
+            fillable.push(column.clone());
+        }
+        if options.guarded {
+            guarded.push(column.clone());
+        }
+
+        let constant = Ident::new(&column.to_shouty_snake_case(), ident.span());
+        constants.push(quote! {
+            pub const #constant: crate::orbit::Column<Self, #ty> = crate::orbit::Column::new(#column);
+        });
+        match &options.cast {
+            Some(cast) => {
+                let cast = cast_expr(cast, ty)?;
+                decoded.push(quote!(#ident: crate::orbit::casts::decode(row, #column, #cast)?));
+                casts.push(quote!((#column, #cast)));
+            }
+            None => decoded.push(quote!(#ident: row.try_get(#column)?)),
+        }
+    }
+
+    let primary_key = match primary_key {
+        Some(ident) => ident.clone(),
+        None => fields
+            .iter()
+            .filter_map(|field| field.ident.as_ref())
+            .find(|ident| *ident == "id")
+            .cloned()
+            .ok_or_else(|| syn::Error::new(Span::call_site(), "no `id` field; mark the key with #[orbit(primary_key)]"))?,
+    };
+    let primary_key_column = primary_key.to_string();
+    let columns_doc = format!("Columns of the `{}` table", table);
+    let observers = match model.observable {
+        true => quote! {
+            fn observers() -> ::std::vec::Vec<::std::boxed::Box<dyn crate::models::Observer>> {
+                <Self as crate::models::Observable>::observers()
+            }
+        },
+        false => quote!(),
+    };
+    let attributes = match model.serialize {
+        true => quote! {
+            fn attributes(&self) -> ::serde_json::Value {
+                crate::orbit::Orbit::to_json(self)
+            }
+        },
+        false => quote!(),
+    };
+    let serialize = match model.serialize {
+        true => {
+            let struct_name = name.to_string();
+            let count = serialized.len();
+            quote! {
+                impl ::serde::Serialize for #name {
+                    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> ::core::result::Result<S::Ok, S::Error> {
+                        use ::serde::ser::SerializeStruct;
+                        let mut state = serializer.serialize_struct(#struct_name, #count)?;
+                        #(#serialized)*
+                        state.end()
+                    }
+                }
+            }
+        }
+        false => quote!(),
+    };
+
+    Ok(quote! {
+        impl crate::orbit::Orbit for #name {
+            fn table_name() -> &'static str {
+                #table
+            }
+
+            fn primary_key() -> &'static str {
+                #primary_key_column
+            }
+
+            fn connection() -> ::core::option::Option<&'static str> {
+                #connection
+            }
+
+            const TIMESTAMPS: bool = #timestamps;
+            const SOFT_DELETES: bool = #soft_deletes;
+
+            fn hidden() -> &'static [&'static str] {
+                &[#(#hidden),*]
+            }
+
+            fn fillable() -> &'static [&'static str] {
+                &[#(#fillable),*]
+            }
+
+            fn guarded() -> &'static [&'static str] {
+                &[#(#guarded),*]
+            }
+
+            fn casts() -> ::std::vec::Vec<(&'static str, crate::orbit::Cast)> {
+                ::std::vec![#(#casts),*]
+            }
+
+            #observers
+            #attributes
+
+            fn id(&self) -> i64 {
+                ::core::convert::Into::<i64>::into(::core::clone::Clone::clone(&self.#primary_key))
+            }
+        }
+
+        impl<'r> ::sqlx::FromRow<'r, crate::database::DbRow> for #name {
+            fn from_row(row: &'r crate::database::DbRow) -> ::core::result::Result<Self, ::sqlx::Error> {
+                use ::sqlx::Row;
+                ::core::result::Result::Ok(Self {
+                    #(#decoded,)*
+                })
+            }
+        }
+
+        #serialize
+
+        #[doc = #columns_doc]
+        #[allow(dead_code)]
+        impl #name {
+            #(#constants)*
+        }
+    })
+}
+
+fn parse_model(input: &DeriveInput) -> syn::Result<Model> {
+    let mut model = Model { table: None, connection: None, timestamps: true, soft_deletes: false, serialize: true, observable: false };
+
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("orbit")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("table") {
+                model.table = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("connection") {
+                model.connection = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("timestamps") {
+                model.timestamps = flag(&meta)?;
+            } else if meta.path.is_ident("soft_deletes") {
+                model.soft_deletes = flag(&meta)?;
+            } else if meta.path.is_ident("serialize") {
+                model.serialize = flag(&meta)?;
+            } else if meta.path.is_ident("observable") {
+                model.observable = flag(&meta)?;
+            } else {
+                return Err(meta.error("expected `table`, `connection`, `timestamps`, `soft_deletes`, `serialize` or `observable`"));
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(model)
+}
+
+fn parse_column(field: &syn::Field) -> syn::Result<Column> {
+    let mut column = Column::default();
+
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("orbit")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("primary_key") {
+                column.primary_key = true;
+            } else if meta.path.is_ident("hidden") {
+                column.hidden = true;
+            } else if meta.path.is_ident("skip") {
+                column.skip = true;
+            } else if meta.path.is_ident("fillable") {
+                column.fillable = true;
+            } else if meta.path.is_ident("guarded") {
+                column.guarded = true;
+            } else if meta.path.is_ident("cast") {
+                column.cast = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error("expected `primary_key`, `hidden`, `skip`, `fillable`, `guarded` or `cast`"));
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(column)
+}
+
+/// `#[orbit(cast = "enum")]` checks written values by deserializing them into the field type
+fn cast_expr(cast: &LitStr, ty: &syn::Type) -> syn::Result<proc_macro2::TokenStream> {
+    Ok(match cast.value().as_str() {
+        "json" => quote!(crate::orbit::Cast::Json),
+        "bool" | "boolean" => quote!(crate::orbit::Cast::Bool),
+        "datetime" => quote!(crate::orbit::Cast::DateTime),
+        "encrypted" => quote!(crate::orbit::Cast::Encrypted),
+        "enum" => quote! {
+            crate::orbit::Cast::Enum(|value| ::serde_json::from_value::<#ty>(::core::clone::Clone::clone(value)).is_ok())
+        },
+        _ => return Err(syn::Error::new_spanned(cast, "expected \"json\", \"bool\", \"datetime\", \"enum\" or \"encrypted\"")),
+    })
+}
+
+/// `soft_deletes` alone means true; `timestamps = false` sets it explicitly
+fn flag(meta: &syn::meta::ParseNestedMeta) -> syn::Result<bool> {
+    match meta.input.peek(syn::Token![=]) {
+        true => Ok(meta.value()?.parse::<LitBool>()?.value()),
+        false => Ok(true),
+    }
+}
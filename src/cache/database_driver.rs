@@ -0,0 +1,300 @@
+use super::{CacheDriver, CacheError};
+use crate::config::cache::CacheConfig;
+use crate::database::{DatabaseManager, DbPool};
+use crate::orbit::Grammar;
+use crate::orbit::schema::Schema;
+use async_trait::async_trait;
+use sqlx::Row;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `expiration` of items stored forever
+const FOREVER: i64 = i64::MAX;
+
+/// How often `increment` retries when other writers keep changing the value
+const INCREMENT_ATTEMPTS: usize = 100;
+
+/// Stores items in a `key` / `value` / `expiration` table (see `rune cache:table`),
+/// so every app server sharing the database shares the cache and its locks
+#[derive(Clone)]
+pub struct DatabaseCache {
+    db: DatabaseManager,
+    connection: Option<String>,
+    table: String,
+}
+
+impl DatabaseCache {
+    /// A cache on `table` of a connection (the default one when None)
+    pub fn new(db: DatabaseManager, connection: Option<&str>, table: &str) -> Self {
+        Self {
+            db,
+            connection: connection.map(str::to_string),
+            table: table.to_string(),
+        }
+    }
+
+    /// A cache on the `CACHE_TABLE` of the `CACHE_DB_CONNECTION` connection
+    pub fn from_config(db: DatabaseManager, config: &CacheConfig) -> Self {
+        Self::new(db, config.database_connection.as_deref(), &config.database_table)
+    }
+
+    fn pool(&self) -> Result<(&DbPool, Grammar), CacheError> {
+        let pool = self
+            .db
+            .connection(self.connection.as_deref())
+            .ok_or_else(|| CacheError::Other("No database connection for the cache".to_string()))?;
+        Ok((pool, Grammar::of(pool)))
+    }
+
+    fn now() -> i64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+    }
+
+    fn expiration(seconds: u64) -> i64 {
+        Self::now().saturating_add(seconds.min(i64::MAX as u64) as i64)
+    }
+
+    /// The value of a live item; an expired one is deleted. Expiry is checked
+    /// in SQL, since the `any` driver reads SQLite integers as 32 bits.
+    async fn find(&self, key: &str) -> Result<Option<String>, CacheError> {
+        let (pool, grammar) = self.pool()?;
+        // A text flag rather than a number or NULL, which not every driver decodes alike
+        let sql = grammar.parameterize(&format!(
+            "SELECT {}, CASE WHEN {} > ? THEN 'live' ELSE 'expired' END AS {} FROM {} WHERE {} = ?",
+            grammar.wrap("value"),
+            grammar.wrap("expiration"),
+            grammar.wrap("state"),
+            grammar.wrap_table(&self.table),
+            grammar.wrap("key"),
+        ));
+        let Some(row) = sqlx::query(&sql).bind(Self::now()).bind(key).fetch_optional(pool).await? else {
+            return Ok(None);
+        };
+        if row.try_get::<String, _>("state")? == "live" {
+            return Ok(Some(row.try_get("value")?));
+        }
+        self.delete_expired(Some(key)).await?;
+        Ok(None)
+    }
+
+    /// Insert or overwrite an item
+    async fn store(&self, key: &str, value: &str, expiration: i64) -> Result<(), CacheError> {
+        let (pool, grammar) = self.pool()?;
+        let columns = ["key".to_string(), "value".to_string(), "expiration".to_string()];
+        let sql = grammar.compile_upsert(&self.table, &columns, 1, &columns[..1], &columns[1..]);
+        sqlx::query(&sql).bind(key).bind(value).bind(expiration).execute(pool).await?;
+        Ok(())
+    }
+
+    /// Insert an item unless the key is taken (expired or not); true when inserted
+    async fn insert(&self, key: &str, value: &str, expiration: i64) -> Result<bool, CacheError> {
+        let (pool, grammar) = self.pool()?;
+        let columns = ["key".to_string(), "value".to_string(), "expiration".to_string()];
+        let sql = grammar.compile_upsert(&self.table, &columns, 1, &columns[..1], &[]);
+        let result = sqlx::query(&sql).bind(key).bind(value).bind(expiration).execute(pool).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Remove the expired items (of one key, or all of them)
+    async fn delete_expired(&self, key: Option<&str>) -> Result<u64, CacheError> {
+        let (pool, grammar) = self.pool()?;
+        let mut sql = format!("DELETE FROM {} WHERE {} <= ?", grammar.wrap_table(&self.table), grammar.wrap("expiration"));
+        if key.is_some() {
+            sql.push_str(&format!(" AND {} = ?", grammar.wrap("key")));
+        }
+        let sql = grammar.parameterize(&sql);
+        let mut query = sqlx::query(&sql).bind(Self::now());
+        if let Some(key) = key {
+            query = query.bind(key);
+        }
+        Ok(query.execute(pool).await?.rows_affected())
+    }
+
+    /// Delete expired items, returning how many there were. Expired items are
+    /// otherwise only removed when read, so run this now and then (e.g. from the scheduler).
+    pub async fn prune_expired(&self) -> Result<u64, CacheError> {
+        self.delete_expired(None).await
+    }
+}
+
+/// Record the cache table on `schema`
+pub fn create_table(schema: &Schema, table: &str) {
+    schema.create(table, |table| {
+        table.string("key");
+        table.text("value");
+        table.big_integer("expiration").index();
+        table.primary(&["key"]);
+    });
+}
+
+#[async_trait]
+impl CacheDriver for DatabaseCache {
+    async fn get(&self, key: &str) -> Result<Option<String>, CacheError> {
+        self.find(key).await
+    }
+
+    async fn put(&self, key: &str, value: &str, seconds: u64) -> Result<(), CacheError> {
+        self.store(key, value, Self::expiration(seconds)).await
+    }
+
+    async fn add(&self, key: &str, value: &str, seconds: u64) -> Result<bool, CacheError> {
+        let expiration = Self::expiration(seconds);
+        if self.insert(key, value, expiration).await? {
+            return Ok(true);
+        }
+
+        // The key exists; take it over only if it has expired. One statement,
+        // so of several servers racing for an expired key only one succeeds.
+        let (pool, grammar) = self.pool()?;
+        let sql = grammar.parameterize(&format!(
+            "UPDATE {} SET {} = ?, {} = ? WHERE {} = ? AND {} <= ?",
+            grammar.wrap_table(&self.table),
+            grammar.wrap("value"),
+            grammar.wrap("expiration"),
+            grammar.wrap("key"),
+            grammar.wrap("expiration"),
+        ));
+        let result = sqlx::query(&sql)
+            .bind(value)
+            .bind(expiration)
+            .bind(key)
+            .bind(Self::now())
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn forever(&self, key: &str, value: &str) -> Result<(), CacheError> {
+        self.store(key, value, FOREVER).await
+    }
+
+    async fn increment(&self, key: &str, by: i64) -> Result<i64, CacheError> {
+        let (pool, grammar) = self.pool()?;
+        let sql = grammar.parameterize(&format!(
+            "UPDATE {} SET {} = ? WHERE {} = ? AND {} = ? AND {} > ?",
+            grammar.wrap_table(&self.table),
+            grammar.wrap("value"),
+            grammar.wrap("key"),
+            grammar.wrap("value"),
+            grammar.wrap("expiration"),
+        ));
+
+        // Compare-and-swap: the update only lands if nobody changed the value since we read it
+        for _ in 0..INCREMENT_ATTEMPTS {
+            match self.find(key).await? {
+                Some(current) => {
+                    let value = super::incremented(key, &current, by)?;
+                    let result = sqlx::query(&sql)
+                        .bind(value.to_string())
+                        .bind(key)
+                        .bind(&current)
+                        .bind(Self::now())
+                        .execute(pool)
+                        .await?;
+                    if result.rows_affected() > 0 {
+                        return Ok(value);
+                    }
+                }
+                None => {
+                    if self.add(key, &by.to_string(), u64::MAX).await? {
+                        return Ok(by);
+                    }
+                }
+            }
+        }
+        Err(CacheError::Other(format!("Cache item '{}' kept changing while incrementing it", key)))
+    }
+
+    async fn forget(&self, key: &str) -> Result<(), CacheError> {
+        let (pool, grammar) = self.pool()?;
+        let sql = grammar.compile_delete(&self.table, "key");
+        sqlx::query(&sql).bind(key).execute(pool).await?;
+        Ok(())
+    }
+
+    async fn forget_if(&self, key: &str, value: &str) -> Result<bool, CacheError> {
+        let (pool, grammar) = self.pool()?;
+        let sql = grammar.parameterize(&format!(
+            "DELETE FROM {} WHERE {} = ? AND {} = ? AND {} > ?",
+            grammar.wrap_table(&self.table),
+            grammar.wrap("key"),
+            grammar.wrap("value"),
+            grammar.wrap("expiration"),
+        ));
+        let result = sqlx::query(&sql).bind(key).bind(value).bind(Self::now()).execute(pool).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn flush(&self) -> Result<(), CacheError> {
+        let (pool, grammar) = self.pool()?;
+        let sql = format!("DELETE FROM {}", grammar.wrap_table(&self.table));
+        sqlx::query(&sql).execute(pool).await?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::cache::Cache;
+    use crate::support::testing::memory_database;
+
+    async fn cache() -> DatabaseCache {
+        let db = memory_database().await;
+        let schema = Schema::for_grammar(Grammar::Sqlite);
+        create_table(&schema, "cache");
+        for sql in schema.statements().unwrap() {
+            sqlx::query(&sql).execute(db.default_connection().unwrap()).await.unwrap();
+        }
+        DatabaseCache::new(db, None, "cache")
+    }
+
+    #[tokio::test]
+    async fn test_items_round_trip_and_expire() {
+        let driver = cache().await;
+        let cache = Cache::Database(driver.clone());
+        cache.put("name", "webrust", 60).await.unwrap();
+        cache.put("name", "orbit", 60).await.unwrap();
+        assert_eq!(cache.get("name").await.unwrap().as_deref(), Some("orbit"));
+
+        cache.forever("config", "on").await.unwrap();
+        cache.put("old", "1", 0).await.unwrap();
+        assert_eq!(cache.get("old").await.unwrap(), None);
+        // Reading the expired item deleted it
+        assert_eq!(driver.prune_expired().await.unwrap(), 0);
+        assert_eq!(cache.increment("hits", 2).await.unwrap(), 2);
+        assert_eq!(cache.decrement("hits", 5).await.unwrap(), -3);
+        assert!(cache.increment("name", 1).await.is_err());
+
+        cache.forget("name").await.unwrap();
+        assert_eq!(cache.get("name").await.unwrap(), None);
+        cache.tags(&["settings"]).put("theme", "dark", 60).await.unwrap();
+        assert_eq!(cache.tags(&["settings"]).get("theme").await.unwrap().as_deref(), Some("dark"));
+        cache.tags(&["settings"]).flush().await.unwrap();
+        assert_eq!(cache.tags(&["settings"]).get("theme").await.unwrap(), None);
+        assert_eq!(cache.get("config").await.unwrap().as_deref(), Some("on"));
+        cache.flush().await.unwrap();
+        assert_eq!(cache.get("config").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_add_only_wins_once_and_takes_over_expired_items() {
+        let driver = cache().await;
+        let cache = Cache::Database(driver.clone());
+        assert!(cache.add("job", "a", 60).await.unwrap());
+        assert!(!cache.add("job", "b", 60).await.unwrap());
+        assert_eq!(cache.get("job").await.unwrap().as_deref(), Some("a"));
+
+        cache.put("stale", "a", 0).await.unwrap();
+        cache.put("swept", "a", 0).await.unwrap();
+        assert!(cache.add("stale", "b", 60).await.unwrap());
+        assert_eq!(cache.get("stale").await.unwrap().as_deref(), Some("b"));
+        assert_eq!(driver.prune_expired().await.unwrap(), 1);
+
+        // Locks are shared through the table
+        let lock = cache.lock("reports", 10);
+        assert!(lock.get().await.unwrap());
+        assert!(!cache.lock("reports", 10).get().await.unwrap());
+        assert!(!cache.lock("reports", 10).release().await.unwrap());
+        assert!(lock.release().await.unwrap());
+    }
+}
@@ -1,10 +1,16 @@
 use super::{CacheDriver, CacheError};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::fs;
+use tokio::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// `expires_at` of items stored forever
+const FOREVER: u64 = u64::MAX;
+
 #[derive(Serialize, Deserialize)]
 struct FileCacheItem {
     expires_at: u64,
@@ -14,6 +20,8 @@ struct FileCacheItem {
 #[derive(Clone)]
 pub struct FileCache {
     directory: PathBuf,
+    /// Serializes read-modify-write operations (increment, forget_if) within the process
+    writes: Arc<Mutex<()>>,
 }
 
 impl FileCache {
@@ -22,7 +30,7 @@ impl FileCache {
         if !directory.exists() {
             std::fs::create_dir_all(&directory).unwrap_or_default();
         }
-        Self { directory }
+        Self { directory, writes: Arc::new(Mutex::new(())) }
     }
 
     fn get_path(&self, key: &str) -> PathBuf {
@@ -37,52 +45,108 @@ impl FileCache {
             .unwrap()
             .as_secs()
     }
-}
 
-#[async_trait]
-impl CacheDriver for FileCache {
-    async fn get(&self, key: &str) -> Result<Option<String>, CacheError> {
-        let path = self.get_path(key);
-        if !path.exists() {
-            return Ok(None);
-        }
+    fn expires_at(seconds: u64) -> u64 {
+        Self::now().saturating_add(seconds)
+    }
 
-        let content = fs::read_to_string(&path).await.map_err(CacheError::Io)?;
+    /// The live item stored at `path`, removing it when it has expired
+    async fn read(path: &Path) -> Result<Option<FileCacheItem>, CacheError> {
+        let content = match fs::read_to_string(path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(CacheError::Io(e)),
+        };
         let item: FileCacheItem = serde_json::from_str(&content).map_err(CacheError::Serialization)?;
 
         if item.expires_at < Self::now() {
             let _ = fs::remove_file(path).await;
             return Ok(None);
         }
+        Ok(Some(item))
+    }
 
-        Ok(Some(item.value))
+    /// Write the item to a temporary file first, so readers never see half of it
+    async fn write(&self, path: &Path, item: &FileCacheItem) -> Result<PathBuf, CacheError> {
+        let content = serde_json::to_string(item).map_err(CacheError::Serialization)?;
+        let temp = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4().simple()));
+        fs::write(&temp, content).await.map_err(CacheError::Io)?;
+        Ok(temp)
     }
 
-    async fn put(&self, key: &str, value: &str, seconds: u64) -> Result<(), CacheError> {
+    async fn store(&self, key: &str, value: &str, expires_at: u64) -> Result<(), CacheError> {
         let path = self.get_path(key);
-        let item = FileCacheItem {
-            expires_at: Self::now() + seconds,
-            value: value.to_string(),
-        };
-        let content = serde_json::to_string(&item).map_err(CacheError::Serialization)?;
-        fs::write(path, content).await.map_err(CacheError::Io)?;
+        let temp = self.write(&path, &FileCacheItem { expires_at, value: value.to_string() }).await?;
+        fs::rename(&temp, path).await.map_err(CacheError::Io)?;
         Ok(())
     }
+}
+
+#[async_trait]
+impl CacheDriver for FileCache {
+    async fn get(&self, key: &str) -> Result<Option<String>, CacheError> {
+        Ok(Self::read(&self.get_path(key)).await?.map(|item| item.value))
+    }
+
+    async fn put(&self, key: &str, value: &str, seconds: u64) -> Result<(), CacheError> {
+        self.store(key, value, Self::expires_at(seconds)).await
+    }
 
     async fn add(&self, key: &str, value: &str, seconds: u64) -> Result<bool, CacheError> {
-        if self.get(key).await?.is_some() {
+        let path = self.get_path(key);
+        // Expired items are removed by the read, leaving the path free
+        if Self::read(&path).await?.is_some() {
             return Ok(false);
         }
-        self.put(key, value, seconds).await?;
-        Ok(true)
+
+        // Linking fails when the path exists, so only one of several racing
+        // processes (sharing the directory) gets to create the item
+        let item = FileCacheItem { expires_at: Self::expires_at(seconds), value: value.to_string() };
+        let temp = self.write(&path, &item).await?;
+        let linked = fs::hard_link(&temp, &path).await;
+        let _ = fs::remove_file(&temp).await;
+        match linked {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(CacheError::Io(e)),
+        }
+    }
+
+    async fn forever(&self, key: &str, value: &str) -> Result<(), CacheError> {
+        self.store(key, value, FOREVER).await
+    }
+
+    async fn increment(&self, key: &str, by: i64) -> Result<i64, CacheError> {
+        let _guard = self.writes.lock().await;
+        match Self::read(&self.get_path(key)).await? {
+            Some(item) => {
+                let value = super::incremented(key, &item.value, by)?;
+                self.store(key, &value.to_string(), item.expires_at).await?;
+                Ok(value)
+            }
+            None => {
+                self.store(key, &by.to_string(), FOREVER).await?;
+                Ok(by)
+            }
+        }
     }
 
     async fn forget(&self, key: &str) -> Result<(), CacheError> {
-        let path = self.get_path(key);
-        if path.exists() {
-            fs::remove_file(path).await.map_err(CacheError::Io)?;
+        match fs::remove_file(self.get_path(key)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(CacheError::Io(e)),
+            _ => Ok(()),
+        }
+    }
+
+    async fn forget_if(&self, key: &str, value: &str) -> Result<bool, CacheError> {
+        let _guard = self.writes.lock().await;
+        match Self::read(&self.get_path(key)).await? {
+            Some(item) if item.value == value => {
+                self.forget(key).await?;
+                Ok(true)
+            }
+            _ => Ok(false),
         }
-        Ok(())
     }
 
     async fn flush(&self) -> Result<(), CacheError> {
@@ -0,0 +1,62 @@
+use super::{Cache, CacheError};
+use std::time::{Duration, Instant};
+
+/// How long `block` waits between attempts
+const RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+/// An expiring lock held in the cache. Only its owner token can release it,
+/// so a job that overran the TTL can't free a lock someone else now holds.
+pub struct Lock {
+    cache: Cache,
+    name: String,
+    /// Where it's stored: `lock:{name}`, apart from cached values named the same
+    key: String,
+    seconds: u64,
+    owner: String,
+}
+
+impl Lock {
+    pub(crate) fn new(cache: Cache, name: &str, seconds: u64, owner: String) -> Self {
+        Self {
+            cache,
+            name: name.to_string(),
+            key: format!("lock:{}", name),
+            seconds,
+            owner,
+        }
+    }
+
+    /// Try to acquire the lock once
+    pub async fn get(&self) -> Result<bool, CacheError> {
+        self.cache.driver().add(&self.key, &self.owner, self.seconds).await
+    }
+
+    /// Wait up to `timeout` for the lock, retrying every 250ms
+    pub async fn block(&self, timeout: Duration) -> Result<(), CacheError> {
+        let started = Instant::now();
+        loop {
+            if self.get().await? {
+                return Ok(());
+            }
+            if started.elapsed() >= timeout {
+                return Err(CacheError::LockTimeout(self.name.clone()));
+            }
+            tokio::time::sleep(RETRY_INTERVAL.min(timeout.saturating_sub(started.elapsed()))).await;
+        }
+    }
+
+    /// Release the lock if this owner still holds it
+    pub async fn release(&self) -> Result<bool, CacheError> {
+        self.cache.driver().forget_if(&self.key, &self.owner).await
+    }
+
+    /// Release the lock whoever holds it
+    pub async fn force_release(&self) -> Result<(), CacheError> {
+        self.cache.forget(&self.key).await
+    }
+
+    /// The token identifying this owner, for `Cache::restore_lock`
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+}
@@ -1,78 +1,334 @@
-use super::{CacheDriver, CacheError};
+use super::{CacheDriver, CacheError, CacheStats};
+use crate::config::cache::CacheConfig;
 use async_trait::async_trait;
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Weak};
+use tokio::sync::Mutex;
 use std::time::{Instant, Duration};
 
 struct CacheItem {
     value: String,
-    expires_at: Instant,
+    /// None for items stored forever
+    expires_at: Option<Instant>,
+    /// When the item was last read or written, on the store's clock
+    used: u64,
+}
+
+impl CacheItem {
+    fn alive(&self) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > Instant::now())
+    }
+
+    /// What the item counts for against `max_bytes`
+    fn size(key: &str, value: &str) -> usize {
+        key.len() + value.len()
+    }
+}
+
+/// The items plus their least-recently-used order
+struct Store {
+    items: HashMap<String, CacheItem>,
+    /// `used` -> key, oldest first
+    order: BTreeMap<u64, String>,
+    clock: u64,
+    bytes: usize,
+    max_entries: Option<usize>,
+    max_bytes: Option<usize>,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+    expirations: u64,
+}
+
+impl Store {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// The live item, marked as just used. Expired items are dropped on the way.
+    fn get(&mut self, key: &str) -> Option<&mut CacheItem> {
+        if !self.items.get(key)?.alive() {
+            self.remove(key);
+            self.expirations += 1;
+            return None;
+        }
+
+        let used = self.tick();
+        let item = self.items.get_mut(key)?;
+        self.order.remove(&item.used);
+        self.order.insert(used, key.to_string());
+        item.used = used;
+        Some(item)
+    }
+
+    fn insert(&mut self, key: &str, value: &str, expires_at: Option<Instant>) {
+        self.remove(key);
+
+        // An item that could never fit would only empty the cache
+        if self.max_bytes.is_some_and(|max| CacheItem::size(key, value) > max) {
+            self.evictions += 1;
+            return;
+        }
+
+        let used = self.tick();
+        self.bytes += CacheItem::size(key, value);
+        self.order.insert(used, key.to_string());
+        self.items.insert(key.to_string(), CacheItem { value: value.to_string(), expires_at, used });
+        self.evict();
+    }
+
+    fn remove(&mut self, key: &str) -> Option<CacheItem> {
+        let item = self.items.remove(key)?;
+        self.order.remove(&item.used);
+        self.bytes -= CacheItem::size(key, &item.value);
+        Some(item)
+    }
+
+    /// Drop the least recently used items until the limits hold again
+    fn evict(&mut self) {
+        while self.max_entries.is_some_and(|max| self.items.len() > max)
+            || self.max_bytes.is_some_and(|max| self.bytes > max)
+        {
+            let Some((_, key)) = self.order.pop_first() else { break };
+            self.remove(&key);
+            self.evictions += 1;
+        }
+    }
+
+    /// Remove every expired item
+    fn sweep(&mut self) -> usize {
+        let expired: Vec<String> = self.items.iter()
+            .filter(|(_, item)| !item.alive())
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            self.remove(key);
+        }
+        self.expirations += expired.len() as u64;
+        expired.len()
+    }
+
+    fn clear(&mut self) {
+        self.items.clear();
+        self.order.clear();
+        self.bytes = 0;
+    }
 }
 
 #[derive(Clone)]
 pub struct MemoryCache {
-    store: Arc<RwLock<HashMap<String, CacheItem>>>,
+    store: Arc<Mutex<Store>>,
+}
+
+impl Default for MemoryCache {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MemoryCache {
+    /// An unbounded cache; expired items are only removed when read
     pub fn new() -> Self {
+        Self::with_limits(None, None)
+    }
+
+    /// A cache that evicts its least recently used items beyond `max_entries`
+    /// items or `max_bytes` of keys and values
+    pub fn with_limits(max_entries: Option<usize>, max_bytes: Option<usize>) -> Self {
         Self {
-            store: Arc::new(RwLock::new(HashMap::new())),
+            store: Arc::new(Mutex::new(Store {
+                items: HashMap::new(),
+                order: BTreeMap::new(),
+                clock: 0,
+                bytes: 0,
+                max_entries,
+                max_bytes,
+                hits: 0,
+                misses: 0,
+                evictions: 0,
+                expirations: 0,
+            })),
+        }
+    }
+
+    /// A cache with the `CACHE_MEMORY_*` limits, sweeping expired items in the background
+    pub fn from_config(config: &CacheConfig) -> Self {
+        let cache = Self::with_limits(config.memory_max_entries, config.memory_max_bytes);
+        if config.memory_sweep_interval > 0 {
+            cache.spawn_sweeper(Duration::from_secs(config.memory_sweep_interval));
+        }
+        cache
+    }
+
+    /// Remove expired items every `every`. The task stops once the cache
+    /// (every clone of it) has been dropped.
+    pub fn spawn_sweeper(&self, every: Duration) -> tokio::task::JoinHandle<()> {
+        let store: Weak<Mutex<Store>> = Arc::downgrade(&self.store);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(store) = store.upgrade() else { break };
+                let swept = store.lock().await.sweep();
+                if swept > 0 {
+                    tracing::debug!("Memory cache swept {} expired item(s)", swept);
+                }
+            }
+        })
+    }
+
+    /// Remove expired items now, returning how many there were
+    pub async fn sweep(&self) -> usize {
+        self.store.lock().await.sweep()
+    }
+
+    pub async fn stats(&self) -> CacheStats {
+        let store = self.store.lock().await;
+        CacheStats {
+            hits: store.hits,
+            misses: store.misses,
+            evictions: store.evictions,
+            expirations: store.expirations,
+            entries: store.items.len() as u64,
+            bytes: store.bytes as u64,
         }
     }
 }
 
+fn expires_in(seconds: u64) -> Option<Instant> {
+    Some(Instant::now() + Duration::from_secs(seconds))
+}
+
 #[async_trait]
 impl CacheDriver for MemoryCache {
     async fn get(&self, key: &str) -> Result<Option<String>, CacheError> {
-        let store = self.store.read().await;
-        if let Some(item) = store.get(key) {
-            if item.expires_at > Instant::now() {
-                return Ok(Some(item.value.clone()));
+        let mut store = self.store.lock().await;
+        match store.get(key).map(|item| item.value.clone()) {
+            Some(value) => {
+                store.hits += 1;
+                Ok(Some(value))
+            }
+            None => {
+                store.misses += 1;
+                Ok(None)
             }
         }
-        Ok(None)
     }
 
     async fn put(&self, key: &str, value: &str, seconds: u64) -> Result<(), CacheError> {
-        let mut store = self.store.write().await;
-        store.insert(
-            key.to_string(),
-            CacheItem {
-                value: value.to_string(),
-                expires_at: Instant::now() + Duration::from_secs(seconds),
-            },
-        );
+        let mut store = self.store.lock().await;
+        store.insert(key, value, expires_in(seconds));
         Ok(())
     }
 
     async fn add(&self, key: &str, value: &str, seconds: u64) -> Result<bool, CacheError> {
-        let mut store = self.store.write().await;
-        if let Some(item) = store.get(key) {
-            if item.expires_at > Instant::now() {
-                return Ok(false);
-            }
+        let mut store = self.store.lock().await;
+        if store.get(key).is_some() {
+            return Ok(false);
         }
-        store.insert(
-            key.to_string(),
-            CacheItem {
-                value: value.to_string(),
-                expires_at: Instant::now() + Duration::from_secs(seconds),
-            },
-        );
+        store.insert(key, value, expires_in(seconds));
         Ok(true)
     }
 
+    async fn forever(&self, key: &str, value: &str) -> Result<(), CacheError> {
+        let mut store = self.store.lock().await;
+        store.insert(key, value, None);
+        Ok(())
+    }
+
+    async fn increment(&self, key: &str, by: i64) -> Result<i64, CacheError> {
+        let mut store = self.store.lock().await;
+        let (value, expires_at) = match store.get(key) {
+            Some(item) => (super::incremented(key, &item.value, by)?, item.expires_at),
+            None => (by, None),
+        };
+        store.insert(key, &value.to_string(), expires_at);
+        Ok(value)
+    }
+
     async fn forget(&self, key: &str) -> Result<(), CacheError> {
-        let mut store = self.store.write().await;
+        let mut store = self.store.lock().await;
         store.remove(key);
         Ok(())
     }
 
+    async fn forget_if(&self, key: &str, value: &str) -> Result<bool, CacheError> {
+        let mut store = self.store.lock().await;
+        match store.get(key) {
+            Some(item) if item.value == value => {
+                store.remove(key);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
     async fn flush(&self) -> Result<(), CacheError> {
-        let mut store = self.store.write().await;
+        let mut store = self.store.lock().await;
         store.clear();
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_least_recently_used_items_are_evicted() {
+        let cache = MemoryCache::with_limits(Some(2), None);
+        cache.put("a", "1", 60).await.unwrap();
+        cache.put("b", "2", 60).await.unwrap();
+        // Reading "a" makes "b" the least recently used
+        assert!(cache.get("a").await.unwrap().is_some());
+        cache.put("c", "3", 60).await.unwrap();
+
+        assert_eq!(cache.get("b").await.unwrap(), None);
+        assert_eq!(cache.get("a").await.unwrap().as_deref(), Some("1"));
+        assert_eq!(cache.get("c").await.unwrap().as_deref(), Some("3"));
+
+        // Keys and values count against max_bytes; oversized items are never stored
+        let cache = MemoryCache::with_limits(None, Some(10));
+        cache.put("k1", "aaaa", 60).await.unwrap();
+        cache.put("k2", "bbbb", 60).await.unwrap();
+        assert_eq!(cache.get("k1").await.unwrap(), None);
+        cache.put("big", "0123456789", 60).await.unwrap();
+        assert_eq!(cache.get("big").await.unwrap(), None);
+        assert_eq!(cache.get("k2").await.unwrap().as_deref(), Some("bbbb"));
+
+        let stats = cache.stats().await;
+        assert_eq!((stats.evictions, stats.entries, stats.bytes), (2, 1, 6));
+    }
+
+    #[tokio::test]
+    async fn test_expired_items_are_removed_and_counted() {
+        let cache = MemoryCache::new();
+        cache.put("gone", "1", 0).await.unwrap();
+        cache.put("swept", "2", 0).await.unwrap();
+        cache.forever("kept", "3").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        assert_eq!(cache.get("gone").await.unwrap(), None);
+        assert_eq!(cache.get("kept").await.unwrap().as_deref(), Some("3"));
+        assert_eq!(cache.stats().await.entries, 2);
+        assert_eq!(cache.sweep().await, 1);
+
+        let stats = cache.stats().await;
+        assert_eq!(stats, CacheStats { hits: 1, misses: 1, evictions: 0, expirations: 2, entries: 1, bytes: 5 });
+    }
+
+    #[tokio::test]
+    async fn test_sweeper_runs_in_the_background() {
+        let cache = MemoryCache::new();
+        let sweeper = cache.spawn_sweeper(Duration::from_millis(10));
+        cache.put("a", "1", 0).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(cache.stats().await.expirations, 1);
+
+        // Dropping the cache stops the task
+        drop(cache);
+        tokio::time::timeout(Duration::from_secs(1), sweeper).await.unwrap().unwrap();
+    }
+}
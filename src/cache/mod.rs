@@ -5,10 +5,19 @@ use thiserror::Error;
 pub mod redis_driver;
 pub mod file_driver;
 pub mod memory_driver;
+pub mod tiered_driver;
+pub mod database_driver;
+pub mod tagged;
+pub mod lock;
+mod stampede;
 
 pub use redis_driver::RedisCache;
 pub use file_driver::FileCache;
 pub use memory_driver::MemoryCache;
+pub use tiered_driver::TieredCache;
+pub use database_driver::DatabaseCache;
+pub use tagged::TaggedCache;
+pub use lock::Lock;
 
 #[derive(Error, Debug)]
 pub enum CacheError {
@@ -16,19 +25,80 @@ pub enum CacheError {
     Redis(#[from] redis::RedisError),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
+    #[error("Timed out waiting for lock: {0}")]
+    LockTimeout(String),
     #[error("Other error: {0}")]
     Other(String),
 }
 
+/// Counters kept by drivers that can report them (the memory driver)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Items dropped to stay within the size limits
+    pub evictions: u64,
+    /// Expired items removed on read or by the sweeper
+    pub expirations: u64,
+    pub entries: u64,
+    pub bytes: u64,
+}
+
 #[async_trait]
 pub trait CacheDriver: Send + Sync {
     async fn get(&self, key: &str) -> Result<Option<String>, CacheError>;
     async fn put(&self, key: &str, value: &str, seconds: u64) -> Result<(), CacheError>;
     async fn add(&self, key: &str, value: &str, seconds: u64) -> Result<bool, CacheError>;
+    /// Store without an expiry
+    async fn forever(&self, key: &str, value: &str) -> Result<(), CacheError>;
+    /// Add `by` (which may be negative) to an integer item and return the new value.
+    /// A missing item starts at 0 and is kept forever; an existing one keeps its expiry.
+    async fn increment(&self, key: &str, by: i64) -> Result<i64, CacheError>;
     async fn forget(&self, key: &str) -> Result<(), CacheError>;
+    /// Forget the item only if it still holds `value`, in one step (used to release locks)
+    async fn forget_if(&self, key: &str, value: &str) -> Result<bool, CacheError>;
     async fn flush(&self) -> Result<(), CacheError>;
+
+    async fn pull(&self, key: &str) -> Result<Option<String>, CacheError> {
+        let value = self.get(key).await?;
+        if value.is_some() {
+            self.forget(key).await?;
+        }
+        Ok(value)
+    }
+
+    async fn many(&self, keys: &[&str]) -> Result<Vec<Option<String>>, CacheError> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(self.get(key).await?);
+        }
+        Ok(values)
+    }
+
+    async fn put_many(&self, items: &[(&str, &str)], seconds: u64) -> Result<(), CacheError> {
+        for (key, value) in items {
+            self.put(key, value, seconds).await?;
+        }
+        Ok(())
+    }
+}
+
+/// The integer held by a counter item
+fn parse_counter(key: &str, value: &str) -> Result<i64, CacheError> {
+    value
+        .parse()
+        .map_err(|_| CacheError::Other(format!("Cache item '{}' is not an integer", key)))
+}
+
+/// A counter item's value after adding `by`; overflowing is an error, as on Redis
+fn incremented(key: &str, value: &str, by: i64) -> Result<i64, CacheError> {
+    parse_counter(key, value)?
+        .checked_add(by)
+        .ok_or_else(|| CacheError::Other(format!("Incrementing cache item '{}' would overflow", key)))
 }
 
 #[derive(Clone)]
@@ -36,31 +106,37 @@ pub enum Cache {
     Redis(RedisCache),
     File(FileCache),
     Memory(MemoryCache),
+    /// MemoryCache in front of Redis, invalidated across nodes over pub/sub
+    Tiered(TieredCache),
+    /// A table shared by every app server on the database
+    Database(DatabaseCache),
 }
 
 impl Cache {
-    pub async fn get(&self, key: &str) -> Result<Option<String>, CacheError> {
+    fn driver(&self) -> &dyn CacheDriver {
         match self {
-            Cache::Redis(c) => c.get(key).await,
-            Cache::File(c) => c.get(key).await,
-            Cache::Memory(c) => c.get(key).await,
+            Cache::Redis(c) => c,
+            Cache::File(c) => c,
+            Cache::Memory(c) => c,
+            Cache::Tiered(c) => c,
+            Cache::Database(c) => c,
         }
     }
 
+    pub async fn get(&self, key: &str) -> Result<Option<String>, CacheError> {
+        self.driver().get(key).await
+    }
+
     pub async fn put(&self, key: &str, value: &str, seconds: u64) -> Result<(), CacheError> {
-        match self {
-            Cache::Redis(c) => c.put(key, value, seconds).await,
-            Cache::File(c) => c.put(key, value, seconds).await,
-            Cache::Memory(c) => c.put(key, value, seconds).await,
-        }
+        self.driver().put(key, value, seconds).await
     }
 
     pub async fn add(&self, key: &str, value: &str, seconds: u64) -> Result<bool, CacheError> {
-        match self {
-            Cache::Redis(c) => c.add(key, value, seconds).await,
-            Cache::File(c) => c.add(key, value, seconds).await,
-            Cache::Memory(c) => c.add(key, value, seconds).await,
-        }
+        self.driver().add(key, value, seconds).await
+    }
+
+    pub async fn forever(&self, key: &str, value: &str) -> Result<(), CacheError> {
+        self.driver().forever(key, value).await
     }
 
     pub async fn has(&self, key: &str) -> Result<bool, CacheError> {
@@ -70,22 +146,71 @@ impl Cache {
         }
     }
 
+    /// Get an item and forget it
+    pub async fn pull(&self, key: &str) -> Result<Option<String>, CacheError> {
+        self.driver().pull(key).await
+    }
+
+    /// Get several items at once, in the order of `keys`
+    pub async fn many(&self, keys: &[&str]) -> Result<Vec<Option<String>>, CacheError> {
+        self.driver().many(keys).await
+    }
+
+    pub async fn put_many(&self, items: &[(&str, &str)], seconds: u64) -> Result<(), CacheError> {
+        self.driver().put_many(items, seconds).await
+    }
+
+    /// Atomically add to an integer item, returning the new value
+    pub async fn increment(&self, key: &str, by: i64) -> Result<i64, CacheError> {
+        self.driver().increment(key, by).await
+    }
+
+    pub async fn decrement(&self, key: &str, by: i64) -> Result<i64, CacheError> {
+        self.driver().increment(key, -by).await
+    }
+
     pub async fn forget(&self, key: &str) -> Result<(), CacheError> {
-        match self {
-            Cache::Redis(c) => c.forget(key).await,
-            Cache::File(c) => c.forget(key).await,
-            Cache::Memory(c) => c.forget(key).await,
-        }
+        self.driver().forget(key).await
     }
 
     pub async fn flush(&self) -> Result<(), CacheError> {
+        self.driver().flush().await
+    }
+
+    /// Hit, miss and eviction counters, for drivers that keep them
+    pub async fn stats(&self) -> Option<CacheStats> {
         match self {
-            Cache::Redis(c) => c.flush().await,
-            Cache::File(c) => c.flush().await,
-            Cache::Memory(c) => c.flush().await,
+            Cache::Memory(c) => Some(c.stats().await),
+            Cache::Tiered(c) => Some(c.local().stats().await),
+            _ => None,
         }
     }
 
+    /// A view of the cache whose items can be flushed together by tag
+    ///
+    /// cache.tags(&["users"]).put("user:1", "...", 60).await?;
+    /// cache.tags(&["users"]).flush().await?;
+    pub fn tags(&self, tags: &[&str]) -> TaggedCache {
+        TaggedCache::new(self.clone(), tags)
+    }
+
+    /// A lock on `name` that expires after `seconds`, owned by a fresh random token
+    ///
+    /// let lock = cache.lock("reports", 10);
+    /// if lock.get().await? {
+    ///     // ...
+    ///     lock.release().await?;
+    /// }
+    pub fn lock(&self, name: &str, seconds: u64) -> Lock {
+        Lock::new(self.clone(), name, seconds, uuid::Uuid::new_v4().to_string())
+    }
+
+    /// A lock handle for an owner token from elsewhere (e.g. another process),
+    /// so it can be released there
+    pub fn restore_lock(&self, name: &str, owner: &str) -> Lock {
+        Lock::new(self.clone(), name, 0, owner.to_string())
+    }
+
     // --- Helper methods with Serde ---
 
     pub async fn get_json<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, CacheError> {
@@ -118,3 +243,164 @@ impl Cache {
         Ok(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn caches() -> Vec<Cache> {
+        let dir = std::env::temp_dir().join(format!("webrust-cache-{}", uuid::Uuid::new_v4().simple()));
+        vec![
+            Cache::Memory(MemoryCache::new()),
+            Cache::File(FileCache::new(dir.to_str().unwrap())),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_counters_and_bulk_operations() {
+        for cache in caches() {
+            assert_eq!(cache.increment("hits", 1).await.unwrap(), 1);
+            assert_eq!(cache.increment("hits", 5).await.unwrap(), 6);
+            assert_eq!(cache.decrement("hits", 2).await.unwrap(), 4);
+            cache.put("name", "webrust", 60).await.unwrap();
+            assert!(cache.increment("name", 1).await.is_err());
+            cache.put("max", &i64::MAX.to_string(), 60).await.unwrap();
+            assert!(cache.increment("max", 1).await.is_err());
+
+            cache.forever("config", "on").await.unwrap();
+            assert_eq!(cache.pull("config").await.unwrap().as_deref(), Some("on"));
+            assert_eq!(cache.pull("config").await.unwrap(), None);
+
+            cache.put_many(&[("a", "1"), ("b", "2")], 60).await.unwrap();
+            let values = cache.many(&["a", "missing", "b"]).await.unwrap();
+            assert_eq!(values, vec![Some("1".to_string()), None, Some("2".to_string())]);
+            cache.flush().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tags_flush_only_their_items() {
+        for cache in caches() {
+            cache.tags(&["users"]).put("user:1", "taylor", 60).await.unwrap();
+            cache.tags(&["users", "admins"]).put("user:2", "abigail", 60).await.unwrap();
+            cache.tags(&["posts"]).put("post:1", "hello", 60).await.unwrap();
+            cache.put("user:1", "untagged", 60).await.unwrap();
+
+            assert_eq!(cache.tags(&["users"]).get("user:1").await.unwrap().as_deref(), Some("taylor"));
+            assert_eq!(cache.tags(&["posts"]).get("user:1").await.unwrap(), None);
+
+            cache.tags(&["admins"]).flush().await.unwrap();
+            assert_eq!(cache.tags(&["users", "admins"]).get("user:2").await.unwrap(), None);
+            assert!(cache.tags(&["users"]).has("user:1").await.unwrap());
+
+            cache.tags(&["users"]).flush().await.unwrap();
+            assert_eq!(cache.tags(&["users"]).get("user:1").await.unwrap(), None);
+            assert_eq!(cache.tags(&["posts"]).get("post:1").await.unwrap().as_deref(), Some("hello"));
+            assert_eq!(cache.get("user:1").await.unwrap().as_deref(), Some("untagged"));
+            cache.flush().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_locks_belong_to_their_owner() {
+        for cache in caches() {
+            let first = cache.lock("reports", 10);
+            let second = cache.lock("reports", 10);
+            assert!(first.get().await.unwrap());
+            assert!(!second.get().await.unwrap());
+
+            // Cached values of the same name are kept apart
+            cache.put("reports", "cached", 10).await.unwrap();
+            cache.forget("reports").await.unwrap();
+            assert!(!second.get().await.unwrap());
+
+            // Only the owner (or a restored handle of it) can release
+            assert!(!second.release().await.unwrap());
+            assert!(cache.restore_lock("reports", first.owner()).release().await.unwrap());
+            assert!(second.get().await.unwrap());
+
+            let err = first.block(Duration::from_millis(300)).await.unwrap_err();
+            assert!(matches!(err, CacheError::LockTimeout(name) if name == "reports"));
+
+            let waiter = {
+                let cache = cache.clone();
+                tokio::spawn(async move { cache.lock("reports", 10).block(Duration::from_secs(5)).await })
+            };
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            second.release().await.unwrap();
+            waiter.await.unwrap().unwrap();
+            cache.flush().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_remember_locked_runs_the_callback_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let cache = Cache::Memory(MemoryCache::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut callers = Vec::new();
+        for _ in 0..10 {
+            let (cache, calls) = (cache.clone(), calls.clone());
+            callers.push(tokio::spawn(async move {
+                cache.remember_locked("report", 60, || async move {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    42
+                }).await.unwrap()
+            }));
+        }
+        for caller in callers {
+            assert_eq!(caller.await.unwrap(), 42);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_flexible_serves_stale_values_while_refreshing() {
+        let cache = Cache::Memory(MemoryCache::new());
+        assert_eq!(cache.flexible("total", 0, 60, || async { 1 }).await.unwrap(), 1);
+
+        // Past its fresh period: the old value comes back and a refresh starts
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let stale = cache.flexible("total", 0, 60, || async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            2
+        }).await.unwrap();
+        assert_eq!(stale, 1);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(cache.flexible("total", 3600, 60, || async { 3 }).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_remember_early_recomputes_before_expiry() {
+        let cache = Cache::Memory(MemoryCache::new());
+        let slow = || async {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            1
+        };
+        assert_eq!(cache.remember_early("feed", 60, 1.0, slow).await.unwrap(), 1);
+
+        // Without a head start it is served until it expires...
+        assert_eq!(cache.remember_early("feed", 60, 0.0, || async { 2 }).await.unwrap(), 1);
+        // ...and a large beta recomputes it well ahead of time
+        assert_eq!(cache.remember_early("feed", 60, 1e9, || async { 3 }).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_stamped_reads_treat_plain_values_as_misses() {
+        let cache = Cache::Memory(MemoryCache::new());
+        cache.remember("shared", 60, || async { 1 }).await.unwrap();
+        assert_eq!(cache.flexible("shared", 60, 60, || async { 2 }).await.unwrap(), 2);
+
+        cache.put_json("shared", &1, 60).await.unwrap();
+        assert_eq!(cache.remember_early("shared", 60, 1.0, || async { 3 }).await.unwrap(), 3);
+
+        // No fresh or stale period still stores the value for a second
+        assert_eq!(cache.flexible("brief", 0, 0, || async { 4 }).await.unwrap(), 4);
+        assert!(cache.has("brief").await.unwrap());
+    }
+}
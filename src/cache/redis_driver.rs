@@ -4,6 +4,14 @@ use redis::{aio::MultiplexedConnection, AsyncCommands, Client};
 use tokio::sync::Mutex;
 use std::sync::Arc;
 
+/// Delete a key only while it still holds the given value (the owner of a lock)
+const FORGET_IF: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
 #[derive(Clone)]
 pub struct RedisCache {
     client: Client,
@@ -19,6 +27,33 @@ impl RedisCache {
             connection: Arc::new(Mutex::new(connection)),
         })
     }
+
+    /// The client, for opening connections of its own (e.g. pub/sub)
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// The values of `keys` with the milliseconds each has left to live, read in one
+    /// transaction. The remaining time is negative for a key without an expiry.
+    pub async fn many_with_ttl(&self, keys: &[&str]) -> Result<Vec<(Option<String>, i64)>, CacheError> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for key in keys {
+            pipe.get(*key).pttl(*key);
+        }
+        let mut conn = self.connection.lock().await;
+        let values: Vec<(Option<String>, i64)> = pipe.query_async(&mut *conn).await?;
+        Ok(values)
+    }
+
+    pub async fn publish(&self, channel: &str, message: &str) -> Result<(), CacheError> {
+        let mut conn = self.connection.lock().await;
+        let _: () = conn.publish(channel, message).await?;
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -49,12 +84,58 @@ impl CacheDriver for RedisCache {
         Ok(result.is_some())
     }
 
+    async fn forever(&self, key: &str, value: &str) -> Result<(), CacheError> {
+        let mut conn = self.connection.lock().await;
+        let _: () = conn.set(key, value).await?;
+        Ok(())
+    }
+
+    async fn increment(&self, key: &str, by: i64) -> Result<i64, CacheError> {
+        let mut conn = self.connection.lock().await;
+        // INCRBY keeps the key's TTL and starts missing keys at 0
+        let value: i64 = conn.incr(key, by).await?;
+        Ok(value)
+    }
+
+    async fn pull(&self, key: &str) -> Result<Option<String>, CacheError> {
+        let mut conn = self.connection.lock().await;
+        let val: Option<String> = conn.get_del(key).await?;
+        Ok(val)
+    }
+
+    async fn many(&self, keys: &[&str]) -> Result<Vec<Option<String>>, CacheError> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut conn = self.connection.lock().await;
+        // MGET always answers with a list, even for a single key
+        let values: Vec<Option<String>> = redis::cmd("MGET").arg(keys).query_async(&mut *conn).await?;
+        Ok(values)
+    }
+
+    async fn put_many(&self, items: &[(&str, &str)], seconds: u64) -> Result<(), CacheError> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (key, value) in items {
+            pipe.set_ex(*key, *value, seconds).ignore();
+        }
+        let mut conn = self.connection.lock().await;
+        let _: () = pipe.query_async(&mut *conn).await?;
+        Ok(())
+    }
+
     async fn forget(&self, key: &str) -> Result<(), CacheError> {
         let mut conn = self.connection.lock().await;
         let _: () = conn.del(key).await?;
         Ok(())
     }
 
+    async fn forget_if(&self, key: &str, value: &str) -> Result<bool, CacheError> {
+        let mut conn = self.connection.lock().await;
+        let deleted: i64 = redis::Script::new(FORGET_IF).key(key).arg(value).invoke_async(&mut *conn).await?;
+        Ok(deleted > 0)
+    }
+
     async fn flush(&self) -> Result<(), CacheError> {
         let mut conn = self.connection.lock().await;
         let _: () = redis::cmd("FLUSHDB").query_async(&mut *conn).await?;
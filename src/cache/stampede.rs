@@ -0,0 +1,181 @@
+use super::{Cache, CacheError, Lock};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::future::Future;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long a recomputation may hold its key's lock, and how long others wait for it
+const LOCK_SECONDS: u64 = 30;
+
+/// A value stored by `flexible` and `remember_early`, with what they need to
+/// decide when to recompute it
+#[derive(Serialize, Deserialize)]
+struct Stamped<T> {
+    value: T,
+    /// Unix time (seconds) it was computed at
+    created: f64,
+    /// Seconds the computation took
+    delta: f64,
+    /// Unix time (seconds) it stops being served
+    expires: f64,
+}
+
+fn now() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()
+}
+
+impl Cache {
+    fn stampede_lock(&self, key: &str) -> Lock {
+        self.lock(&format!("stampede:{}", key), LOCK_SECONDS)
+    }
+
+    /// Wait for the key's lock. On timeout the caller goes ahead without it,
+    /// rather than failing because a recomputation is slow.
+    async fn wait_for(&self, lock: &Lock) -> Result<bool, CacheError> {
+        match lock.block(Duration::from_secs(LOCK_SECONDS)).await {
+            Ok(()) => Ok(true),
+            Err(CacheError::LockTimeout(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// `remember`, but only one caller at a time runs the callback for a missing key;
+    /// the others wait for its result instead of all recomputing it.
+    pub async fn remember_locked<T, F, Fut>(&self, key: &str, seconds: u64, callback: F) -> Result<T, CacheError>
+    where
+        T: Serialize + DeserializeOwned + Send + Sync + Clone,
+        F: FnOnce() -> Fut + Send,
+        Fut: Future<Output = T> + Send,
+    {
+        if let Some(val) = self.get_json::<T>(key).await? {
+            return Ok(val);
+        }
+
+        let lock = self.stampede_lock(key);
+        let locked = self.wait_for(&lock).await?;
+        // Whoever held the lock has most likely stored it by now
+        if let Some(val) = self.get_json::<T>(key).await? {
+            if locked {
+                lock.release().await?;
+            }
+            return Ok(val);
+        }
+
+        let value = callback().await;
+        self.put_json(key, &value, seconds).await?;
+        if locked {
+            lock.release().await?;
+        }
+        Ok(value)
+    }
+
+    /// Stale-while-revalidate: the value is fresh for `fresh` seconds, then
+    /// served stale for up to `stale` more while one background task recomputes it.
+    /// Only a missing (or fully expired) value makes the caller wait.
+    ///
+    /// let stats = cache.flexible("stats", 60, 300, || async { expensive().await }).await?;
+    pub async fn flexible<T, F, Fut>(&self, key: &str, fresh: u64, stale: u64, callback: F) -> Result<T, CacheError>
+    where
+        T: Serialize + DeserializeOwned + Send + Sync + Clone + 'static,
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = T> + Send + 'static,
+    {
+        if let Some(stamped) = self.get_stamped::<T>(key).await? {
+            if now() - stamped.created > fresh as f64 {
+                let lock = self.stampede_lock(key);
+                if lock.get().await? {
+                    let cache = self.clone();
+                    let key = key.to_string();
+                    tokio::spawn(async move {
+                        if let Err(e) = cache.refresh(&key, fresh + stale, callback).await {
+                            tracing::warn!("Failed to refresh cache key '{}': {}", key, e);
+                        }
+                        let _ = lock.release().await;
+                    });
+                }
+            }
+            return Ok(stamped.value);
+        }
+
+        let lock = self.stampede_lock(key);
+        let locked = self.wait_for(&lock).await?;
+        let value = match self.get_stamped::<T>(key).await? {
+            Some(stamped) => stamped.value,
+            None => self.refresh(key, fresh + stale, callback).await?,
+        };
+        if locked {
+            lock.release().await?;
+        }
+        Ok(value)
+    }
+
+    /// `remember` with probabilistic early expiration (XFetch): as the expiry
+    /// nears, each read has a growing chance to recompute the value ahead of
+    /// time, so it rarely expires under load. The chance grows with how long
+    /// the callback took and with `beta` (1.0 is a good default; higher
+    /// recomputes earlier). Other readers keep getting the cached value meanwhile.
+    pub async fn remember_early<T, F, Fut>(&self, key: &str, seconds: u64, beta: f64, callback: F) -> Result<T, CacheError>
+    where
+        T: Serialize + DeserializeOwned + Send + Sync + Clone,
+        F: FnOnce() -> Fut + Send,
+        Fut: Future<Output = T> + Send,
+    {
+        if let Some(stamped) = self.get_stamped::<T>(key).await? {
+            // -ln(x) for x in (0, 1] is an exponentially distributed head start
+            let head_start = stamped.delta * beta * -(1.0 - rand::random::<f64>()).ln();
+            if now() + head_start < stamped.expires {
+                return Ok(stamped.value);
+            }
+
+            // Recompute early only if nobody else already is
+            let lock = self.stampede_lock(key);
+            if !lock.get().await? {
+                return Ok(stamped.value);
+            }
+            let value = self.refresh(key, seconds, callback).await;
+            lock.release().await?;
+            return value;
+        }
+
+        let lock = self.stampede_lock(key);
+        let locked = self.wait_for(&lock).await?;
+        let value = match self.get_stamped::<T>(key).await? {
+            Some(stamped) => Ok(stamped.value),
+            None => self.refresh(key, seconds, callback).await,
+        };
+        if locked {
+            lock.release().await?;
+        }
+        value
+    }
+
+    /// The stamped value under `key`. A value that isn't stamped, such as one
+    /// `remember` wrote under the same key, is a miss and gets recomputed.
+    async fn get_stamped<T: DeserializeOwned>(&self, key: &str) -> Result<Option<Stamped<T>>, CacheError> {
+        match self.get_json::<Stamped<T>>(key).await {
+            Err(CacheError::Serialization(_)) => Ok(None),
+            result => result,
+        }
+    }
+
+    /// Run the callback and store its result, stamped, for `seconds` (at least one;
+    /// Redis rejects an expiry of zero)
+    async fn refresh<T, F, Fut>(&self, key: &str, seconds: u64, callback: F) -> Result<T, CacheError>
+    where
+        T: Serialize + Send + Sync,
+        F: FnOnce() -> Fut + Send,
+        Fut: Future<Output = T> + Send,
+    {
+        let seconds = seconds.max(1);
+        let started = Instant::now();
+        let value = callback().await;
+        let created = now();
+        let stamped = Stamped {
+            value,
+            created,
+            delta: started.elapsed().as_secs_f64(),
+            expires: created + seconds as f64,
+        };
+        self.put_json(key, &stamped, seconds).await?;
+        Ok(stamped.value)
+    }
+}
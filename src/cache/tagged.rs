@@ -0,0 +1,127 @@
+use super::{Cache, CacheError};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Items stored under a set of tags.
+///
+/// Each tag has a random version kept in the cache forever, and item keys are
+/// prefixed with a hash of their tags' versions. Flushing a tag gives it a new
+/// version, so every item stored under it stops being found; the old entries
+/// are left to expire on their own. Items are only found through the same tags
+/// they were stored with.
+#[derive(Clone)]
+pub struct TaggedCache {
+    cache: Cache,
+    tags: Vec<String>,
+}
+
+impl TaggedCache {
+    pub(crate) fn new(cache: Cache, tags: &[&str]) -> Self {
+        Self {
+            cache,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        }
+    }
+
+    fn version_key(tag: &str) -> String {
+        format!("tag:{}:key", tag)
+    }
+
+    async fn version(&self, tag: &str) -> Result<String, CacheError> {
+        let key = Self::version_key(tag);
+        if let Some(version) = self.cache.get(&key).await? {
+            return Ok(version);
+        }
+
+        let version = uuid::Uuid::new_v4().simple().to_string();
+        self.cache.forever(&key, &version).await?;
+        Ok(version)
+    }
+
+    async fn key(&self, key: &str) -> Result<String, CacheError> {
+        let mut versions = Vec::with_capacity(self.tags.len());
+        for tag in &self.tags {
+            versions.push(self.version(tag).await?);
+        }
+        Ok(format!("{:x}:{}", md5::compute(versions.join("|")), key))
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<String>, CacheError> {
+        self.cache.get(&self.key(key).await?).await
+    }
+
+    pub async fn put(&self, key: &str, value: &str, seconds: u64) -> Result<(), CacheError> {
+        self.cache.put(&self.key(key).await?, value, seconds).await
+    }
+
+    pub async fn add(&self, key: &str, value: &str, seconds: u64) -> Result<bool, CacheError> {
+        self.cache.add(&self.key(key).await?, value, seconds).await
+    }
+
+    pub async fn forever(&self, key: &str, value: &str) -> Result<(), CacheError> {
+        self.cache.forever(&self.key(key).await?, value).await
+    }
+
+    pub async fn has(&self, key: &str) -> Result<bool, CacheError> {
+        self.cache.has(&self.key(key).await?).await
+    }
+
+    pub async fn pull(&self, key: &str) -> Result<Option<String>, CacheError> {
+        self.cache.pull(&self.key(key).await?).await
+    }
+
+    pub async fn many(&self, keys: &[&str]) -> Result<Vec<Option<String>>, CacheError> {
+        let mut tagged = Vec::with_capacity(keys.len());
+        for key in keys {
+            tagged.push(self.key(key).await?);
+        }
+        let tagged: Vec<&str> = tagged.iter().map(String::as_str).collect();
+        self.cache.many(&tagged).await
+    }
+
+    pub async fn put_many(&self, items: &[(&str, &str)], seconds: u64) -> Result<(), CacheError> {
+        let mut tagged = Vec::with_capacity(items.len());
+        for (key, value) in items {
+            tagged.push((self.key(key).await?, *value));
+        }
+        let tagged: Vec<(&str, &str)> = tagged.iter().map(|(key, value)| (key.as_str(), *value)).collect();
+        self.cache.put_many(&tagged, seconds).await
+    }
+
+    pub async fn increment(&self, key: &str, by: i64) -> Result<i64, CacheError> {
+        self.cache.increment(&self.key(key).await?, by).await
+    }
+
+    pub async fn decrement(&self, key: &str, by: i64) -> Result<i64, CacheError> {
+        self.cache.decrement(&self.key(key).await?, by).await
+    }
+
+    pub async fn forget(&self, key: &str) -> Result<(), CacheError> {
+        self.cache.forget(&self.key(key).await?).await
+    }
+
+    /// Forget every item stored under any of these tags
+    pub async fn flush(&self) -> Result<(), CacheError> {
+        for tag in &self.tags {
+            let version = uuid::Uuid::new_v4().simple().to_string();
+            self.cache.forever(&Self::version_key(tag), &version).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn get_json<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, CacheError> {
+        self.cache.get_json(&self.key(key).await?).await
+    }
+
+    pub async fn put_json<T: Serialize + Send + Sync>(&self, key: &str, value: &T, seconds: u64) -> Result<(), CacheError> {
+        self.cache.put_json(&self.key(key).await?, value, seconds).await
+    }
+
+    pub async fn remember<T, F, Fut>(&self, key: &str, seconds: u64, callback: F) -> Result<T, CacheError>
+    where
+        T: Serialize + DeserializeOwned + Send + Sync + Clone,
+        F: FnOnce() -> Fut + Send,
+        Fut: std::future::Future<Output = T> + Send,
+    {
+        self.cache.remember(&self.key(key).await?, seconds, callback).await
+    }
+}
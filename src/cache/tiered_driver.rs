@@ -0,0 +1,311 @@
+use super::{CacheDriver, CacheError, MemoryCache, RedisCache};
+use crate::config::cache::CacheConfig;
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Sent over pub/sub when a node changes or removes keys, so the other nodes
+/// drop their local copies
+#[derive(Serialize, Deserialize)]
+struct Invalidation {
+    /// The node that sent it (which has already updated its own copy)
+    node: String,
+    #[serde(default)]
+    keys: Vec<String>,
+    #[serde(default)]
+    flush: bool,
+}
+
+/// Reads through a local MemoryCache, kept for `local_ttl` seconds (never longer
+/// than the value has left in Redis), to Redis. Writes go to both, and every change is broadcast on `channel` so the other
+/// nodes evict their local copy.
+#[derive(Clone)]
+pub struct TieredCache {
+    local: MemoryCache,
+    remote: RedisCache,
+    local_ttl: u64,
+    channel: String,
+    node: String,
+    /// Bumped on every local eviction, so a Redis read that raced one doesn't
+    /// put the value it replaced back into the local tier
+    epoch: Arc<AtomicU64>,
+    /// Dropped with the last clone, which stops the listener
+    _listening: Arc<oneshot::Sender<()>>,
+}
+
+impl TieredCache {
+    /// Front `remote` with `local`, and start listening for other nodes' invalidations
+    pub fn new(local: MemoryCache, remote: RedisCache, local_ttl: u64, channel: &str) -> Self {
+        let (listening, stopped) = oneshot::channel();
+        let cache = Self {
+            local,
+            remote,
+            local_ttl,
+            channel: channel.to_string(),
+            node: uuid::Uuid::new_v4().to_string(),
+            epoch: Arc::new(AtomicU64::new(0)),
+            _listening: Arc::new(listening),
+        };
+        cache.spawn_listener(stopped);
+        cache
+    }
+
+    /// A tiered cache from the `CACHE_TIERED_*` and `CACHE_MEMORY_*` settings
+    pub async fn connect(redis_url: &str, config: &CacheConfig) -> Result<Self, CacheError> {
+        let remote = RedisCache::new(redis_url).await?;
+        let local = MemoryCache::from_config(config);
+        Ok(Self::new(local, remote, config.tiered_local_ttl, &config.tiered_channel))
+    }
+
+    /// The local tier, e.g. for its stats
+    pub fn local(&self) -> &MemoryCache {
+        &self.local
+    }
+
+    /// Apply the other nodes' invalidations until `stopped` fires, i.e. every
+    /// clone of the cache has been dropped
+    fn spawn_listener(&self, mut stopped: oneshot::Receiver<()>) {
+        let client = self.remote.client().clone();
+        let (channel, node, local, epoch) = (self.channel.clone(), self.node.clone(), self.local.clone(), self.epoch.clone());
+        tokio::spawn(async move {
+            loop {
+                let listen = async {
+                    let mut pubsub = client.get_async_pubsub().await?;
+                    pubsub.subscribe(&channel).await?;
+                    // Invalidations sent while we weren't subscribed are lost
+                    epoch.fetch_add(1, Ordering::SeqCst);
+                    local.flush().await?;
+
+                    let mut messages = pubsub.into_on_message();
+                    while let Some(message) = messages.next().await {
+                        let applied = match message.get_payload::<String>() {
+                            Ok(payload) => apply(&local, &epoch, &node, &payload).await,
+                            Err(e) => Err(e.into()),
+                        };
+                        // One bad message mustn't stop the invalidations after it
+                        if let Err(e) = applied {
+                            tracing::warn!("⚠️  Skipped a cache invalidation message: {}", e);
+                        }
+                    }
+                    Ok::<(), CacheError>(())
+                };
+
+                tokio::select! {
+                    result = listen => {
+                        if let Err(e) = result {
+                            tracing::warn!("⚠️  Cache invalidation listener failed: {}. Reconnecting.", e);
+                        }
+                    }
+                    _ = &mut stopped => break,
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+                    _ = &mut stopped => break,
+                }
+            }
+        });
+    }
+
+    async fn broadcast(&self, keys: &[&str], flush: bool) -> Result<(), CacheError> {
+        let message = Invalidation {
+            node: self.node.clone(),
+            keys: keys.iter().map(|key| key.to_string()).collect(),
+            flush,
+        };
+        self.remote.publish(&self.channel, &serde_json::to_string(&message)?).await
+    }
+
+    /// Keep a value read from Redis locally, for no longer than it has left there.
+    /// Skipped when local copies were evicted since `epoch` was read, before the
+    /// Redis read: the value may be the one the eviction was for.
+    async fn fill(&self, key: &str, value: &str, remaining_ms: i64, epoch: u64) -> Result<(), CacheError> {
+        let Some(seconds) = local_seconds(self.local_ttl, remaining_ms) else {
+            return Ok(());
+        };
+        if self.epoch.load(Ordering::SeqCst) != epoch {
+            return Ok(());
+        }
+        self.local.put(key, value, seconds).await?;
+        // An eviction that landed between the check and the put may have missed it
+        if self.epoch.load(Ordering::SeqCst) != epoch {
+            self.local.forget(key).await?;
+        }
+        Ok(())
+    }
+
+    /// Drop the local copies of changed keys, here and on the other nodes
+    async fn invalidate(&self, keys: &[&str]) -> Result<(), CacheError> {
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        for key in keys {
+            self.local.forget(key).await?;
+        }
+        self.broadcast(keys, false).await
+    }
+}
+
+/// How long a value with `remaining_ms` left in Redis (negative: no expiry) may be
+/// kept locally. None when it expires within the second, too soon to keep a copy.
+fn local_seconds(local_ttl: u64, remaining_ms: i64) -> Option<u64> {
+    if remaining_ms < 0 {
+        return Some(local_ttl);
+    }
+    match remaining_ms as u64 / 1000 {
+        0 => None,
+        seconds => Some(local_ttl.min(seconds)),
+    }
+}
+
+/// Evict what an invalidation message names from the local tier, bumping `epoch` first
+async fn apply(local: &MemoryCache, epoch: &AtomicU64, node: &str, payload: &str) -> Result<(), CacheError> {
+    let message: Invalidation = serde_json::from_str(payload)?;
+    if message.node == node {
+        return Ok(());
+    }
+    epoch.fetch_add(1, Ordering::SeqCst);
+    if message.flush {
+        return local.flush().await;
+    }
+    for key in &message.keys {
+        local.forget(key).await?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl CacheDriver for TieredCache {
+    async fn get(&self, key: &str) -> Result<Option<String>, CacheError> {
+        if let Some(value) = self.local.get(key).await? {
+            return Ok(Some(value));
+        }
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        let (value, remaining_ms) = self.remote.many_with_ttl(&[key]).await?.pop().unwrap_or((None, -2));
+        if let Some(value) = &value {
+            self.fill(key, value, remaining_ms, epoch).await?;
+        }
+        Ok(value)
+    }
+
+    async fn put(&self, key: &str, value: &str, seconds: u64) -> Result<(), CacheError> {
+        self.remote.put(key, value, seconds).await?;
+        self.local.put(key, value, self.local_ttl.min(seconds)).await?;
+        self.broadcast(&[key], false).await
+    }
+
+    async fn add(&self, key: &str, value: &str, seconds: u64) -> Result<bool, CacheError> {
+        // Straight to Redis, so only one node can win
+        if !self.remote.add(key, value, seconds).await? {
+            return Ok(false);
+        }
+        self.invalidate(&[key]).await?;
+        Ok(true)
+    }
+
+    async fn forever(&self, key: &str, value: &str) -> Result<(), CacheError> {
+        self.remote.forever(key, value).await?;
+        self.local.put(key, value, self.local_ttl).await?;
+        self.broadcast(&[key], false).await
+    }
+
+    async fn increment(&self, key: &str, by: i64) -> Result<i64, CacheError> {
+        let value = self.remote.increment(key, by).await?;
+        self.invalidate(&[key]).await?;
+        Ok(value)
+    }
+
+    async fn forget(&self, key: &str) -> Result<(), CacheError> {
+        self.remote.forget(key).await?;
+        self.invalidate(&[key]).await
+    }
+
+    async fn forget_if(&self, key: &str, value: &str) -> Result<bool, CacheError> {
+        if !self.remote.forget_if(key, value).await? {
+            return Ok(false);
+        }
+        self.invalidate(&[key]).await?;
+        Ok(true)
+    }
+
+    async fn flush(&self) -> Result<(), CacheError> {
+        self.remote.flush().await?;
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        self.local.flush().await?;
+        self.broadcast(&[], true).await
+    }
+
+    async fn pull(&self, key: &str) -> Result<Option<String>, CacheError> {
+        let value = self.remote.pull(key).await?;
+        self.invalidate(&[key]).await?;
+        Ok(value)
+    }
+
+    async fn many(&self, keys: &[&str]) -> Result<Vec<Option<String>>, CacheError> {
+        let mut values = self.local.many(keys).await?;
+        let missing: Vec<&str> = keys.iter().zip(&values).filter(|(_, value)| value.is_none()).map(|(key, _)| *key).collect();
+        if missing.is_empty() {
+            return Ok(values);
+        }
+
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        let mut fetched = self.remote.many_with_ttl(&missing).await?.into_iter();
+        for (key, value) in keys.iter().zip(values.iter_mut()) {
+            if value.is_none() {
+                let (remote, remaining_ms) = fetched.next().unwrap_or((None, -2));
+                if let Some(remote) = &remote {
+                    self.fill(key, remote, remaining_ms, epoch).await?;
+                }
+                *value = remote;
+            }
+        }
+        Ok(values)
+    }
+
+    async fn put_many(&self, items: &[(&str, &str)], seconds: u64) -> Result<(), CacheError> {
+        self.remote.put_many(items, seconds).await?;
+        self.local.put_many(items, self.local_ttl.min(seconds)).await?;
+        let keys: Vec<&str> = items.iter().map(|(key, _)| *key).collect();
+        self.broadcast(&keys, false).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_invalidations_from_other_nodes_evict_local_copies() {
+        let local = MemoryCache::new();
+        let epoch = AtomicU64::new(0);
+        local.put("a", "1", 60).await.unwrap();
+        local.put("b", "2", 60).await.unwrap();
+        local.put("c", "3", 60).await.unwrap();
+
+        // Our own broadcasts are already applied
+        apply(&local, &epoch, "me", r#"{"node":"me","keys":["a"]}"#).await.unwrap();
+        assert!(local.get("a").await.unwrap().is_some());
+        assert_eq!(epoch.load(Ordering::SeqCst), 0);
+
+        apply(&local, &epoch, "me", r#"{"node":"other","keys":["a","b"]}"#).await.unwrap();
+        assert_eq!(local.get("a").await.unwrap(), None);
+        assert_eq!(local.get("b").await.unwrap(), None);
+        assert!(local.get("c").await.unwrap().is_some());
+        // Reads from Redis that started before the eviction won't fill it back in
+        assert_eq!(epoch.load(Ordering::SeqCst), 1);
+
+        apply(&local, &epoch, "me", r#"{"node":"other","flush":true}"#).await.unwrap();
+        assert_eq!(local.get("c").await.unwrap(), None);
+
+        assert!(apply(&local, &epoch, "me", "not json").await.is_err());
+    }
+
+    #[test]
+    fn test_local_copies_never_outlive_the_remote_value() {
+        assert_eq!(local_seconds(60, -1), Some(60));
+        assert_eq!(local_seconds(60, 3_600_000), Some(60));
+        assert_eq!(local_seconds(60, 5_900), Some(5));
+        assert_eq!(local_seconds(60, 999), None);
+    }
+}
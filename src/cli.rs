@@ -5,6 +5,8 @@ use std::path::Path;
 use clap::{Parser, Subcommand};
 
 use crate::framework;
+use crate::orbit::schema::Schema;
+use crate::orbit::Grammar;
 
 #[derive(Parser, Debug)]
 #[command(name = "webrust")]
@@ -95,8 +97,55 @@ pub enum RuneCommand {
         name: String,
     },
 
+    /// Create a migration for the database cache driver's table
+    #[command(name = "cache:table")]
+    CacheTable,
+
     /// Run database migrations
-    Migrate,
+    Migrate {
+        /// Run each migration in its own batch
+        #[arg(long)]
+        step: bool,
+        /// Print the SQL without running it
+        #[arg(long)]
+        pretend: bool,
+    },
+
+    /// Write the database schema to migrations/schema/ so fresh databases load it instead of replaying every migration
+    #[command(name = "schema:dump")]
+    SchemaDump {
+        /// Delete the migration files the dump covers
+        #[arg(long)]
+        prune: bool,
+    },
+
+    /// Show which migrations have run
+    #[command(name = "migrate:status")]
+    MigrateStatus,
+
+    /// Roll back every migration
+    #[command(name = "migrate:reset")]
+    MigrateReset {
+        /// Print the SQL without running it
+        #[arg(long)]
+        pretend: bool,
+    },
+
+    /// Roll back every migration and run them again
+    #[command(name = "migrate:refresh")]
+    MigrateRefresh {
+        /// Run each migration in its own batch
+        #[arg(long)]
+        step: bool,
+    },
+
+    /// Drop all tables and run every migration
+    #[command(name = "migrate:fresh")]
+    MigrateFresh {
+        /// Run each migration in its own batch
+        #[arg(long)]
+        step: bool,
+    },
 
     /// Seed the database with records
     #[command(name = "db:seed")]
@@ -122,7 +171,14 @@ pub enum RuneCommand {
 
     /// Rollback the last database migration
     #[command(name = "migrate:rollback")]
-    MigrateRollback,
+    MigrateRollback {
+        /// Roll back this many migrations instead of the last batch
+        #[arg(long)]
+        step: Option<usize>,
+        /// Print the SQL without running it
+        #[arg(long)]
+        pretend: bool,
+    },
 
     /// Start the queue worker
     #[command(name = "queue:work")]
@@ -174,7 +230,7 @@ pub async fn run_setup() -> Result<(), Box<dyn std::error::Error>> {
     println!("🔧 Running WebRust setup...");
 
     // Check DB connection (optional)
-    let db_manager = framework::build_database_manager().await;
+    let db_manager = framework::build_database_manager().await?;
 
     if let Some(pool) = db_manager.default_connection() {
         match sqlx::query("SELECT 1").execute(pool).await {
@@ -271,31 +327,19 @@ pub fn make_model(name: &str) -> io::Result<()> {
     } else {
         let contents = format!(
             r#"
-use serde::{{Deserialize, Serialize}};
-use sqlx::FromRow;
-use crate::database::DbPool;
+use serde::Deserialize;
+use crate::orbit::Orbit;
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
+// {struct_name}::all(&db), {struct_name}::find(&db, id), {struct_name}::query()... come from Orbit,
+// which also derives Serialize (without #[orbit(hidden)] fields)
+#[derive(Debug, Deserialize, Orbit)]
+#[orbit(table = "{module_name}s")]
 pub struct {struct_name} {{
     pub id: i64,
     // Add your fields here
     // pub name: String,
-    // pub created_at: chrono::DateTime<chrono::Utc>,
-}}
-
-impl {struct_name} {{
-    pub async fn all(pool: &DbPool) -> Result<Vec<Self>, sqlx::Error> {{
-        sqlx::query_as::<_, Self>("SELECT * FROM {module_name}s")
-            .fetch_all(pool)
-            .await
-    }}
-
-    pub async fn find(pool: &DbPool, id: i64) -> Result<Option<Self>, sqlx::Error> {{
-        sqlx::query_as::<_, Self>("SELECT * FROM {module_name}s WHERE id = ?")
-            .bind(id)
-            .fetch_optional(pool)
-            .await
-    }}
+    // pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+    // pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
 }}
 "#
         );
@@ -975,11 +1019,16 @@ impl Notification for {struct_name} {{
     Ok(())
 }
 
-pub async fn run_migrations() -> Result<(), Box<dyn std::error::Error>> {
+async fn migrator() -> Result<crate::database::migrator::Migrator, Box<dyn std::error::Error>> {
+    let db_manager = framework::build_database_manager().await?;
+    Ok(crate::database::migrator::Migrator::new(db_manager)
+        .with_migrations(crate::database::migrations::registry()))
+}
+
+pub async fn run_migrations(step: bool, pretend: bool) -> Result<(), Box<dyn std::error::Error>> {
     println!("Running migrations...");
 
-    let db_manager = framework::build_database_manager().await;
-    let migrator = crate::database::migrator::Migrator::new(db_manager);
+    let migrator = migrator().await?.step(step).pretend(pretend);
 
     match migrator.run(Path::new("migrations")).await {
         Ok(_) => println!("✅ Migrations completed successfully"),
@@ -989,6 +1038,68 @@ pub async fn run_migrations() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+pub async fn dump_schema(prune: bool) -> Result<(), Box<dyn std::error::Error>> {
+    match migrator().await?.dump(Path::new("migrations"), prune).await {
+        Ok(path) => println!("✅ Database schema dumped to {}", path.display()),
+        Err(e) => println!("❌ Schema dump failed: {}", e),
+    }
+
+    Ok(())
+}
+
+pub async fn migration_status() -> Result<(), Box<dyn std::error::Error>> {
+    let migrator = migrator().await?;
+
+    match migrator.status(Path::new("migrations")).await {
+        Ok(migrations) if migrations.is_empty() => println!("No migrations found."),
+        Ok(migrations) => {
+            println!("{:<10} {:<7} Migration", "Status", "Batch");
+            for migration in migrations {
+                let status = match (migration.batch, migration.modified, migration.missing) {
+                    (_, _, true) => "Missing",
+                    (Some(_), true, _) => "Modified",
+                    (Some(_), false, _) => "Ran",
+                    (None, _, _) => "Pending",
+                };
+                let batch = migration.batch.map(|b| b.to_string()).unwrap_or_default();
+                println!("{:<10} {:<7} {}", status, batch, migration.name);
+            }
+        }
+        Err(e) => println!("❌ Could not read migration status: {}", e),
+    }
+
+    Ok(())
+}
+
+pub async fn reset_migrations(pretend: bool) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Rolling back all migrations...");
+
+    match migrator().await?.pretend(pretend).reset(Path::new("migrations")).await {
+        Ok(_) => println!("✅ Reset completed successfully"),
+        Err(e) => println!("❌ Reset failed: {}", e),
+    }
+
+    Ok(())
+}
+
+pub async fn refresh_migrations(step: bool) -> Result<(), Box<dyn std::error::Error>> {
+    match migrator().await?.step(step).refresh(Path::new("migrations")).await {
+        Ok(_) => println!("✅ Refresh completed successfully"),
+        Err(e) => println!("❌ Refresh failed: {}", e),
+    }
+
+    Ok(())
+}
+
+pub async fn fresh_migrations(step: bool) -> Result<(), Box<dyn std::error::Error>> {
+    match migrator().await?.step(step).fresh(Path::new("migrations")).await {
+        Ok(_) => println!("✅ Fresh migration completed successfully"),
+        Err(e) => println!("❌ Fresh migration failed: {}", e),
+    }
+
+    Ok(())
+}
+
 pub fn make_migration(name: &str) -> io::Result<()> {
     let now = chrono::Utc::now();
     let timestamp = now.format("%Y%m%d%H%M%S").to_string();
@@ -1020,13 +1131,59 @@ DROP TABLE IF EXISTS {table_name};
     Ok(())
 }
 
-pub async fn rollback_migrations() -> Result<(), Box<dyn std::error::Error>> {
+/// Write the migration creating the `database` cache driver's table (CACHE_TABLE).
+/// Migrations run on the default connection, so the SQL is written for its
+/// database; a cache on another connection (CACHE_DB_CONNECTION) needs the table created there.
+pub fn make_cache_table() -> io::Result<()> {
+    let config = crate::config::cache::CacheConfig::default();
+    let table = config.database_table;
+    let timestamp = chrono::Utc::now().format("%Y%m%d%H%M%S").to_string();
+    let file_path = Path::new("migrations").join(format!("{}_create_{}_table.sql", timestamp, table));
+    fs::create_dir_all("migrations")?;
+
+    // The default connection's driver, else its URL scheme, picks the dialect
+    let database = crate::config::database::DatabaseConfig::load().map_err(io::Error::other)?;
+    let grammar = database.connections.get(&database.default)
+        .and_then(|conn| match &conn.driver {
+            Some(driver) => Grammar::from_driver(driver),
+            None => Grammar::from_url(conn.write.first().unwrap_or(&conn.url)),
+        })
+        .unwrap_or_else(Grammar::current);
+    let default = database.default;
+    if let Some(connection) = config.database_connection.filter(|name| *name != default) {
+        println!(
+            "⚠️  CACHE_DB_CONNECTION is '{}', but migrations run on '{}'. Create the table on '{}' yourself.",
+            connection, default, connection
+        );
+    }
+
+    let up = Schema::for_grammar(grammar);
+    crate::cache::database_driver::create_table(&up, &table);
+    let down = Schema::for_grammar(grammar);
+    down.drop_if_exists(&table);
+
+    let contents = format!(
+        "-- Migration: create_{table}_table\n-- --- UP ---\n{}\n\n-- --- DOWN ---\n{}\n",
+        up.to_sql().map_err(io::Error::other)?,
+        down.to_sql().map_err(io::Error::other)?
+    );
+
+    fs::write(&file_path, contents)?;
+    println!("Created migration: {:?}", file_path);
+
+    Ok(())
+}
+
+pub async fn rollback_migrations(step: Option<usize>, pretend: bool) -> Result<(), Box<dyn std::error::Error>> {
     println!("Rolling back migrations...");
 
-    let db_manager = framework::build_database_manager().await;
-    let migrator = crate::database::migrator::Migrator::new(db_manager);
+    let migrator = migrator().await?.pretend(pretend);
+    let result = match step {
+        Some(steps) => migrator.rollback_steps(Path::new("migrations"), steps).await,
+        None => migrator.rollback(Path::new("migrations")).await,
+    };
 
-    match migrator.rollback(Path::new("migrations")).await {
+    match result {
         Ok(_) => println!("✅ Rollback completed successfully"),
         Err(e) => println!("❌ Rollback failed: {}", e),
     }
@@ -0,0 +1,35 @@
+use std::env;
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    pub driver: String, // file, redis, tiered, database, array
+    /// Most items the memory driver keeps before evicting the least recently used
+    pub memory_max_entries: Option<usize>,
+    /// Most bytes of keys and values the memory driver keeps
+    pub memory_max_bytes: Option<usize>,
+    /// Seconds between sweeps of expired items from the memory driver (0 disables it)
+    pub memory_sweep_interval: u64,
+    /// Seconds the tiered driver keeps Redis values in its local memory tier
+    pub tiered_local_ttl: u64,
+    /// Redis pub/sub channel the tiered driver broadcasts invalidations on
+    pub tiered_channel: String,
+    /// Table of the database driver
+    pub database_table: String,
+    /// Connection of the database driver (the default one when unset)
+    pub database_connection: Option<String>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            driver: env::var("CACHE_DRIVER").unwrap_or_else(|_| "file".to_string()),
+            memory_max_entries: env::var("CACHE_MEMORY_MAX_ENTRIES").ok().and_then(|v| v.parse().ok()),
+            memory_max_bytes: env::var("CACHE_MEMORY_MAX_BYTES").ok().and_then(|v| v.parse().ok()),
+            memory_sweep_interval: env::var("CACHE_MEMORY_SWEEP_SECONDS").unwrap_or_else(|_| "60".to_string()).parse().unwrap_or(60),
+            tiered_local_ttl: env::var("CACHE_TIERED_LOCAL_TTL").unwrap_or_else(|_| "5".to_string()).parse().unwrap_or(5),
+            tiered_channel: env::var("CACHE_TIERED_CHANNEL").unwrap_or_else(|_| "webrust:cache:invalidate".to_string()),
+            database_table: env::var("CACHE_TABLE").unwrap_or_else(|_| "cache".to_string()),
+            database_connection: env::var("CACHE_DB_CONNECTION").ok().filter(|name| !name.is_empty()),
+        }
+    }
+}
@@ -1,51 +1,142 @@
+use serde::Deserialize;
 use std::collections::HashMap;
 use std::env;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone)]
+/// Where the connections file is looked for when `DB_CONFIG` isn't set
+const CONFIG_FILES: [&str; 3] = ["config/database.toml", "config/database.yaml", "config/database.yml"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum DatabaseConfigError {
+    #[error("Could not read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Invalid TOML in {0}: {1}")]
+    Toml(PathBuf, toml::de::Error),
+    #[error("Invalid YAML in {0}: {1}")]
+    Yaml(PathBuf, serde_yaml::Error),
+    #[error("Unsupported database config format: {0} (use .toml, .yaml or .yml)")]
+    Format(PathBuf),
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct DatabaseConnectionConfig {
+    /// mysql, postgres or sqlite. Checked against the URL; only the `any` build
+    /// can mix drivers, other builds only connect their own.
+    #[serde(default)]
+    pub driver: Option<String>,
+    /// The primary; may be left out when `write` lists the primaries
+    #[serde(default)]
     pub url: String,
+    #[serde(default = "default_max_connections")]
     pub max_connections: u32,
+    /// Connections the pool keeps open even when idle
+    #[serde(default)]
+    pub min_connections: u32,
+    /// Seconds an unused connection stays in the pool
+    #[serde(default)]
+    pub idle_timeout: Option<u64>,
+    /// Seconds before a connection is closed and replaced, used or not
+    #[serde(default)]
+    pub max_lifetime: Option<u64>,
+    /// Seconds a single statement may run (MySQL SELECTs and PostgreSQL; ignored on SQLite)
+    #[serde(default)]
+    pub statement_timeout: Option<u64>,
+    /// Replica URLs; Orbit SELECTs are spread over them round-robin
+    #[serde(default)]
+    pub read: Vec<String>,
+    /// Primary URLs, tried in order (falls back to `url` when empty)
+    #[serde(default)]
+    pub write: Vec<String>,
+    /// Read from the primary for the rest of a request after it writes
+    #[serde(default)]
+    pub sticky: bool,
+}
+
+impl DatabaseConnectionConfig {
+    /// A connection with the default pool settings
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            driver: None,
+            url: url.into(),
+            max_connections: default_max_connections(),
+            min_connections: 0,
+            idle_timeout: None,
+            max_lifetime: None,
+            statement_timeout: None,
+            read: Vec::new(),
+            write: Vec::new(),
+            sticky: false,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+fn default_max_connections() -> u32 {
+    5
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct DatabaseConfig {
     pub default: String,
     pub connections: HashMap<String, DatabaseConnectionConfig>,
 }
 
-impl Default for DatabaseConfig {
-    fn default() -> Self {
+impl DatabaseConfig {
+    /// Read the connections from a TOML or YAML file (picked by extension).
+    /// `${VAR}` and `${VAR:-default}` are replaced with environment variables first.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, DatabaseConfigError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| DatabaseConfigError::Io(path.to_path_buf(), e))?;
+        let text = interpolate(&text);
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&text).map_err(|e| DatabaseConfigError::Toml(path.to_path_buf(), e)),
+            Some("yaml") | Some("yml") => {
+                serde_yaml::from_str(&text).map_err(|e| DatabaseConfigError::Yaml(path.to_path_buf(), e))
+            }
+            _ => Err(DatabaseConfigError::Format(path.to_path_buf())),
+        }
+    }
+
+    /// The connections file in use: `DB_CONFIG`, or the first of
+    /// `config/database.{toml,yaml,yml}` that exists
+    pub fn file() -> Option<PathBuf> {
+        match env::var("DB_CONFIG") {
+            Ok(path) if !path.is_empty() => Some(PathBuf::from(path)),
+            _ => CONFIG_FILES.iter().map(PathBuf::from).find(|path| path.exists()),
+        }
+    }
+
+    /// The connections file when there is one, otherwise the env preset.
+    /// A broken connections file is an error rather than a reason to use other databases.
+    pub fn load() -> Result<Self, DatabaseConfigError> {
+        match Self::file() {
+            Some(path) => Self::from_file(&path),
+            None => Ok(Self::from_env()),
+        }
+    }
+
+    /// The single connection of the compiled backend, from `DATABASE_URL` and friends
+    fn from_env() -> Self {
         let mut connections = HashMap::new();
 
         // Default connection (from env)
         let default_connection = env::var("DB_CONNECTION").unwrap_or_else(|_| "mysql".to_string());
 
-        // We can add more presets here, but for now let's just map the env vars to the "default" connection
-        // or strictly follow Laravel's style where we have named connections.
+        #[cfg(all(feature = "mysql", not(feature = "any")))]
+        connections.insert("mysql".to_string(), env_connection());
 
-        #[cfg(feature = "mysql")]
-        connections.insert("mysql".to_string(), DatabaseConnectionConfig {
-            url: env::var("DATABASE_URL").unwrap_or_default(),
-            max_connections: env::var("DB_MAX_CONNECTIONS")
-                .unwrap_or_else(|_| "5".to_string())
-                .parse()
-                .unwrap_or(5),
-        });
-
-        #[cfg(feature = "sqlite")]
+        #[cfg(all(feature = "sqlite", not(feature = "any")))]
         connections.insert("sqlite".to_string(), DatabaseConnectionConfig {
-            url: env::var("DB_SQLITE_URL").unwrap_or_else(|_| "sqlite::memory:".to_string()),
             max_connections: 1,
+            ..DatabaseConnectionConfig::new(env::var("DB_SQLITE_URL").unwrap_or_else(|_| "sqlite::memory:".to_string()))
         });
 
-        #[cfg(feature = "postgres")]
-        connections.insert("postgres".to_string(), DatabaseConnectionConfig {
-            url: env::var("DATABASE_URL").unwrap_or_default(),
-            max_connections: env::var("DB_MAX_CONNECTIONS")
-                .unwrap_or_else(|_| "5".to_string())
-                .parse()
-                .unwrap_or(5),
-        });
+        #[cfg(all(feature = "postgres", not(feature = "any")))]
+        connections.insert("postgres".to_string(), env_connection());
+
+        // Any driver: a single connection, named by DB_CONNECTION, whose URL picks the driver
+        #[cfg(feature = "any")]
+        connections.insert(default_connection.clone(), env_connection());
 
         Self {
             default: default_connection,
@@ -53,3 +144,149 @@ impl Default for DatabaseConfig {
         }
     }
 }
+
+impl Default for DatabaseConfig {
+    /// `load`, with the env preset in place of a broken connections file.
+    /// Connecting goes through `load` itself, so the error still stops the app there.
+    fn default() -> Self {
+        Self::load().unwrap_or_else(|e| {
+            tracing::error!("❌ {}", e);
+            Self::from_env()
+        })
+    }
+}
+
+/// Replace `${VAR}` and `${VAR:-default}` with the environment variable.
+/// An unset variable without a default becomes an empty string.
+pub fn interpolate(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find('}') else {
+            out.push_str(&rest[start..]);
+            return out;
+        };
+
+        let expr = &after[..end];
+        let (name, fallback) = match expr.split_once(":-") {
+            Some((name, fallback)) => (name, Some(fallback)),
+            None => (expr, None),
+        };
+        match env::var(name.trim()) {
+            Ok(value) if !value.is_empty() || fallback.is_none() => out.push_str(&value),
+            _ => out.push_str(fallback.unwrap_or_default()),
+        }
+        rest = &after[end + 1..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// The server connection described by DATABASE_URL, DB_MAX_CONNECTIONS and the read/write split vars
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "any"))]
+fn env_connection() -> DatabaseConnectionConfig {
+    DatabaseConnectionConfig {
+        max_connections: env::var("DB_MAX_CONNECTIONS")
+            .unwrap_or_else(|_| "5".to_string())
+            .parse()
+            .unwrap_or(5),
+        read: env_list("DB_READ_URLS"),
+        write: env_list("DB_WRITE_URLS"),
+        sticky: env_flag("DB_STICKY"),
+        ..DatabaseConnectionConfig::new(env::var("DATABASE_URL").unwrap_or_default())
+    }
+}
+
+/// A comma separated list, e.g. DB_READ_URLS=mysql://replica-1/app,mysql://replica-2/app
+fn env_list(key: &str) -> Vec<String> {
+    env::var(key)
+        .unwrap_or_default()
+        .split(',')
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty())
+        .collect()
+}
+
+fn env_flag(key: &str) -> bool {
+    matches!(env::var(key).as_deref(), Ok("true") | Ok("1"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolate() {
+        env::set_var("WEBRUST_TEST_DB_HOST", "db.internal");
+        env::remove_var("WEBRUST_TEST_DB_MISSING");
+
+        assert_eq!(interpolate("mysql://${WEBRUST_TEST_DB_HOST}/app"), "mysql://db.internal/app");
+        assert_eq!(interpolate("${WEBRUST_TEST_DB_MISSING:-10}"), "10");
+        assert_eq!(interpolate("${WEBRUST_TEST_DB_HOST:-other}"), "db.internal");
+        assert_eq!(interpolate("a${WEBRUST_TEST_DB_MISSING}b"), "ab");
+        assert_eq!(interpolate("cost: $5 ${unclosed"), "cost: $5 ${unclosed");
+    }
+
+    #[test]
+    fn test_from_file() {
+        env::set_var("WEBRUST_TEST_ANALYTICS_URL", "postgres://analytics/app");
+        let dir = env::temp_dir().join(format!("webrust-db-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let toml = dir.join("database.toml");
+        std::fs::write(&toml, r#"
+default = "main"
+
+[connections.main]
+url = "sqlite::memory:"
+
+[connections.analytics]
+driver = "postgres"
+url = "${WEBRUST_TEST_ANALYTICS_URL}"
+max_connections = ${WEBRUST_TEST_ANALYTICS_MAX:-20}
+min_connections = 2
+idle_timeout = 600
+statement_timeout = 30
+read = ["postgres://replica/app"]
+
+[connections.clustered]
+write = ["mysql://primary-a/app", "mysql://primary-b/app"]
+"#).unwrap();
+        let config = DatabaseConfig::from_file(&toml).unwrap();
+        assert_eq!(config.default, "main");
+        assert_eq!(config.connections["main"].max_connections, 5);
+        let analytics = &config.connections["analytics"];
+        assert_eq!(analytics.driver.as_deref(), Some("postgres"));
+        assert_eq!(analytics.url, "postgres://analytics/app");
+        assert_eq!(analytics.max_connections, 20);
+        assert_eq!(analytics.min_connections, 2);
+        assert_eq!(analytics.idle_timeout, Some(600));
+        assert_eq!(analytics.max_lifetime, None);
+        assert_eq!(analytics.statement_timeout, Some(30));
+        assert_eq!(analytics.read, vec!["postgres://replica/app".to_string()]);
+        let clustered = &config.connections["clustered"];
+        assert_eq!((clustered.url.as_str(), clustered.write.len()), ("", 2));
+
+        let yaml = dir.join("database.yaml");
+        std::fs::write(&yaml, "
+default: legacy
+connections:
+  legacy:
+    url: ${WEBRUST_TEST_ANALYTICS_URL}
+    max_lifetime: 1800
+").unwrap();
+        let config = DatabaseConfig::from_file(&yaml).unwrap();
+        assert_eq!(config.connections["legacy"].url, "postgres://analytics/app");
+        assert_eq!(config.connections["legacy"].max_lifetime, Some(1800));
+
+        let ini = dir.join("database.ini");
+        std::fs::write(&ini, "").unwrap();
+        assert!(matches!(DatabaseConfig::from_file(&ini), Err(DatabaseConfigError::Format(_))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -5,6 +5,7 @@ pub mod queue;
 pub mod database;
 pub mod session;
 pub mod logging;
+pub mod cache;
 
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -15,6 +16,7 @@ pub struct Config {
     pub database: database::DatabaseConfig,
     pub session: session::SessionConfig,
     pub logging: logging::LoggingConfig,
+    pub cache: cache::CacheConfig,
 }
 
 impl Config {
@@ -27,6 +29,7 @@ impl Config {
             database: database::DatabaseConfig::default(),
             session: session::SessionConfig::default(),
             logging: logging::LoggingConfig::default(),
+            cache: cache::CacheConfig::default(),
         }
     }
 }
@@ -1,39 +1,138 @@
 use sqlx::Pool;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
+pub mod connection;
+pub mod migrations;
 pub mod migrator;
 pub mod seeder;
 pub mod seeders;
 
-#[cfg(feature = "mysql")]
+// The backend is picked at compile time. An explicitly enabled `sqlite` or
+// `postgres` feature wins over the default `mysql` one, so
+// `cargo test --features sqlite` works without `--no-default-features`.
+// The `any` feature compiles every driver in and lets each connection's URL
+// pick one at runtime (see `Connection::grammar`).
+
+#[cfg(all(feature = "mysql", not(any(feature = "postgres", feature = "sqlite"))))]
 pub type Db = sqlx::MySql;
-#[cfg(feature = "mysql")]
+#[cfg(all(feature = "mysql", not(any(feature = "postgres", feature = "sqlite"))))]
 pub type DbArguments = sqlx::mysql::MySqlArguments;
-#[cfg(feature = "mysql")]
+#[cfg(all(feature = "mysql", not(any(feature = "postgres", feature = "sqlite"))))]
 pub type DbPoolOptions = sqlx::mysql::MySqlPoolOptions;
 
-#[cfg(feature = "postgres")]
+#[cfg(all(feature = "postgres", not(feature = "sqlite")))]
 pub type Db = sqlx::Postgres;
-#[cfg(feature = "postgres")]
+#[cfg(all(feature = "postgres", not(feature = "sqlite")))]
 pub type DbArguments = sqlx::postgres::PgArguments;
-#[cfg(feature = "postgres")]
+#[cfg(all(feature = "postgres", not(feature = "sqlite")))]
 pub type DbPoolOptions = sqlx::postgres::PgPoolOptions;
 
-#[cfg(feature = "sqlite")]
+#[cfg(all(feature = "sqlite", not(feature = "any")))]
 pub type Db = sqlx::Sqlite;
-#[cfg(feature = "sqlite")]
+#[cfg(all(feature = "sqlite", not(feature = "any")))]
 pub type DbArguments<'q> = sqlx::sqlite::SqliteArguments<'q>;
-#[cfg(feature = "sqlite")]
+#[cfg(all(feature = "sqlite", not(feature = "any")))]
 pub type DbPoolOptions = sqlx::sqlite::SqlitePoolOptions;
 
+#[cfg(feature = "any")]
+pub type Db = sqlx::Any;
+#[cfg(feature = "any")]
+pub type DbArguments<'q> = sqlx::any::AnyArguments<'q>;
+#[cfg(feature = "any")]
+pub type DbPoolOptions = sqlx::any::AnyPoolOptions;
+
+pub use connection::{Connection, ConnectionResolver, Transaction};
+
 pub type DbPool = Pool<Db>;
 pub type DbRow = <Db as sqlx::Database>::Row;
+pub type DbQueryResult = <Db as sqlx::Database>::QueryResult;
+
+/// The auto-increment id reported by the driver for an INSERT.
+/// Postgres has no such thing; Orbit reads the key back with `RETURNING` there.
+#[cfg(all(feature = "mysql", not(any(feature = "postgres", feature = "sqlite"))))]
+pub fn last_insert_id(result: &DbQueryResult) -> u64 {
+    result.last_insert_id()
+}
+
+#[cfg(all(feature = "postgres", not(feature = "sqlite")))]
+pub fn last_insert_id(_result: &DbQueryResult) -> u64 {
+    0
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "any")))]
+pub fn last_insert_id(result: &DbQueryResult) -> u64 {
+    result.last_insert_rowid() as u64
+}
+
+#[cfg(feature = "any")]
+pub fn last_insert_id(result: &DbQueryResult) -> u64 {
+    result.last_insert_id().unwrap_or_default() as u64
+}
+
+/// How datetimes are bound. The `any` driver has no date types, so there they
+/// are sent as "Y-m-d H:M:S" text, which MySQL and SQLite convert on their own.
+#[cfg(not(feature = "any"))]
+pub type DbDateTime = chrono::NaiveDateTime;
+#[cfg(feature = "any")]
+pub type DbDateTime = String;
+
+/// `datetime` in the form the driver binds
+#[cfg(not(feature = "any"))]
+pub fn db_datetime(datetime: chrono::NaiveDateTime) -> DbDateTime {
+    datetime
+}
+
+#[cfg(feature = "any")]
+pub fn db_datetime(datetime: chrono::NaiveDateTime) -> DbDateTime {
+    datetime.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// The local time to stamp created_at, updated_at and deleted_at with
+pub fn now() -> DbDateTime {
+    db_datetime(chrono::Local::now().naive_local())
+}
+
+tokio::task_local! {
+    /// Connections written to during the current `sticky_reads` scope
+    static WRITTEN: RefCell<HashSet<String>>;
+}
+
+/// Run `future` as one unit of work for sticky read replicas: once it writes to
+/// a sticky connection, its later reads on that connection go to the primary.
+/// The `sticky_reads` middleware wraps every request in one.
+pub async fn sticky_reads<F: Future>(future: F) -> F::Output {
+    WRITTEN.scope(RefCell::new(HashSet::new()), future).await
+}
+
+/// Whether connection `name` was written to in the current `sticky_reads` scope
+fn written(name: &str) -> bool {
+    WRITTEN.try_with(|written| written.borrow().contains(name)).unwrap_or(false)
+}
+
+/// The read replicas of a connection, used in turn
+#[derive(Clone)]
+struct Replicas {
+    pools: Vec<DbPool>,
+    next: Arc<AtomicUsize>,
+    sticky: bool,
+}
+
+impl Replicas {
+    /// Whether reads of connection `name` must stay on the primary
+    fn pinned(&self, name: &str) -> bool {
+        self.sticky && written(name)
+    }
+}
 
 #[derive(Clone)]
 pub struct DatabaseManager {
     default_name: String,
     pools: HashMap<String, DbPool>,
+    replicas: HashMap<String, Replicas>,
 }
 
 impl DatabaseManager {
@@ -41,20 +140,99 @@ impl DatabaseManager {
         Self {
             default_name,
             pools: HashMap::new(),
+            replicas: HashMap::new(),
         }
     }
 
     pub fn add(&mut self, name: &str, pool: DbPool) {
+        #[cfg(feature = "any")]
+        if name == self.default_name {
+            crate::orbit::Grammar::set_current(crate::orbit::Grammar::of(&pool));
+        }
         self.pools.insert(name.to_string(), pool);
     }
 
+    /// Send the connection's reads (Orbit SELECTs) to these pools, round-robin.
+    /// With `sticky`, reads go back to the primary for the rest of a
+    /// `sticky_reads` scope (usually the request) after it writes.
+    pub fn add_read_replicas(&mut self, name: &str, pools: Vec<DbPool>, sticky: bool) {
+        self.replicas.insert(name.to_string(), Replicas { pools, next: Arc::new(AtomicUsize::new(0)), sticky });
+    }
+
+    /// The primary (write) pool of a connection
     pub fn connection(&self, name: Option<&str>) -> Option<&DbPool> {
         let name = name.unwrap_or(&self.default_name);
         self.pools.get(name)
     }
 
+    /// The pool to read from: the next replica, or the primary when the
+    /// connection has none or a sticky connection was written to
+    pub fn read_connection(&self, name: Option<&str>) -> Option<&DbPool> {
+        let key = name.unwrap_or(&self.default_name);
+        match self.replicas.get(key) {
+            Some(replicas) if !replicas.pools.is_empty() && !replicas.pinned(key) => {
+                let index = replicas.next.fetch_add(1, Ordering::Relaxed) % replicas.pools.len();
+                replicas.pools.get(index)
+            }
+            _ => self.connection(name),
+        }
+    }
+
+    /// Note a write for sticky reads
+    pub(crate) fn record_write(&self, name: Option<&str>) {
+        let key = name.unwrap_or(&self.default_name);
+        if self.replicas.get(key).is_some_and(|replicas| replicas.sticky) {
+            // Outside a sticky_reads scope there's nothing to remember it in
+            let _ = WRITTEN.try_with(|written| written.borrow_mut().insert(key.to_string()));
+        }
+    }
+
     /// Get the default connection
     pub fn default_connection(&self) -> Option<&DbPool> {
         self.connection(None)
     }
+
+    /// Run the callback in a transaction on the default connection.
+    /// Commits when it returns Ok, rolls back when it returns Err (or panics).
+    ///
+    /// db.transaction(|tx| async move {
+    ///     let id = User::create(&tx, new_user).await?;
+    ///     Profile::create(&tx, json!({ "user_id": id })).await?;
+    ///     Ok(())
+    /// }).await?;
+    pub async fn transaction<F, Fut, R, E>(&self, callback: F) -> Result<R, E>
+    where
+        F: FnOnce(Transaction) -> Fut,
+        Fut: Future<Output = Result<R, E>>,
+        E: From<sqlx::Error>,
+    {
+        self.transaction_on(None, callback).await
+    }
+
+    /// Run the callback in a transaction on a named connection
+    pub async fn transaction_on<F, Fut, R, E>(&self, name: Option<&str>, callback: F) -> Result<R, E>
+    where
+        F: FnOnce(Transaction) -> Fut,
+        Fut: Future<Output = Result<R, E>>,
+        E: From<sqlx::Error>,
+    {
+        let pool = self
+            .connection(name)
+            .ok_or(sqlx::Error::Configuration("No database connection found".into()))?;
+        self.record_write(name);
+        let tx = Transaction::begin(pool).await?;
+
+        match callback(tx.clone()).await {
+            Ok(value) => {
+                tx.commit().await?;
+                Ok(value)
+            }
+            Err(e) => {
+                // The callback's error is the interesting one; a failed rollback
+                // still drops the connection's transaction when it is returned
+                let _ = tx.rollback().await;
+                Err(e)
+            }
+        }
+    }
 }
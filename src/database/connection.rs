@@ -0,0 +1,277 @@
+use crate::database::{DatabaseManager, Db, DbPool, DbQueryResult, DbRow};
+use crate::orbit::Grammar;
+use futures::channel::mpsc;
+use futures::{SinkExt, Stream, StreamExt};
+use sqlx::query::{Query, QueryAs, QueryScalar};
+use sqlx::{FromRow, IntoArguments};
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::Mutex;
+
+/// Where Orbit queries run: a pool, or an open transaction.
+/// Cheap to clone; clones of a transaction share the same connection.
+#[derive(Clone)]
+pub enum Connection {
+    Pool(DbPool),
+    Transaction(Transaction),
+}
+
+impl Connection {
+    /// The SQL dialect of the database behind this connection
+    pub fn grammar(&self) -> Grammar {
+        match self {
+            Connection::Pool(pool) => Grammar::of(pool),
+            Connection::Transaction(tx) => tx.grammar,
+        }
+    }
+
+    pub async fn execute<'q, A>(&self, query: Query<'q, Db, A>) -> Result<DbQueryResult, sqlx::Error>
+    where A: 'q + IntoArguments<'q, Db>
+    {
+        match self {
+            Connection::Pool(pool) => query.execute(pool).await,
+            Connection::Transaction(tx) => {
+                let mut guard = tx.inner.lock().await;
+                query.execute(&mut **open(&mut guard)?).await
+            }
+        }
+    }
+
+    pub async fn fetch_all<'q, O, A>(&self, query: QueryAs<'q, Db, O, A>) -> Result<Vec<O>, sqlx::Error>
+    where
+        A: 'q + IntoArguments<'q, Db>,
+        O: Send + Unpin + for<'r> FromRow<'r, DbRow>,
+    {
+        match self {
+            Connection::Pool(pool) => query.fetch_all(pool).await,
+            Connection::Transaction(tx) => {
+                let mut guard = tx.inner.lock().await;
+                query.fetch_all(&mut **open(&mut guard)?).await
+            }
+        }
+    }
+
+    pub async fn fetch_optional<'q, O, A>(&self, query: QueryAs<'q, Db, O, A>) -> Result<Option<O>, sqlx::Error>
+    where
+        A: 'q + IntoArguments<'q, Db>,
+        O: Send + Unpin + for<'r> FromRow<'r, DbRow>,
+    {
+        match self {
+            Connection::Pool(pool) => query.fetch_optional(pool).await,
+            Connection::Transaction(tx) => {
+                let mut guard = tx.inner.lock().await;
+                query.fetch_optional(&mut **open(&mut guard)?).await
+            }
+        }
+    }
+
+    pub async fn fetch_scalar<'q, O, A>(&self, query: QueryScalar<'q, Db, O, A>) -> Result<O, sqlx::Error>
+    where
+        A: 'q + IntoArguments<'q, Db>,
+        O: Send + Unpin,
+        (O,): for<'r> FromRow<'r, DbRow>,
+    {
+        match self {
+            Connection::Pool(pool) => query.fetch_one(pool).await,
+            Connection::Transaction(tx) => {
+                let mut guard = tx.inner.lock().await;
+                query.fetch_one(&mut **open(&mut guard)?).await
+            }
+        }
+    }
+
+    pub async fn fetch_rows<'q, A>(&self, query: Query<'q, Db, A>) -> Result<Vec<DbRow>, sqlx::Error>
+    where A: 'q + IntoArguments<'q, Db>
+    {
+        match self {
+            Connection::Pool(pool) => query.fetch_all(pool).await,
+            Connection::Transaction(tx) => {
+                let mut guard = tx.inner.lock().await;
+                query.fetch_all(&mut **open(&mut guard)?).await
+            }
+        }
+    }
+
+    pub async fn fetch_optional_row<'q, A>(&self, query: Query<'q, Db, A>) -> Result<Option<DbRow>, sqlx::Error>
+    where A: 'q + IntoArguments<'q, Db>
+    {
+        match self {
+            Connection::Pool(pool) => query.fetch_optional(pool).await,
+            Connection::Transaction(tx) => {
+                let mut guard = tx.inner.lock().await;
+                query.fetch_optional(&mut **open(&mut guard)?).await
+            }
+        }
+    }
+
+    pub async fn fetch_one_row<'q, A>(&self, query: Query<'q, Db, A>) -> Result<DbRow, sqlx::Error>
+    where A: 'q + IntoArguments<'q, Db>
+    {
+        self.fetch_optional_row(query).await?.ok_or(sqlx::Error::RowNotFound)
+    }
+
+    /// Send each row into `sink` as the database produces it.
+    /// Stops after the first error, or as soon as the receiving side is dropped.
+    ///
+    /// A transaction has a single connection, and holding it until the consumer
+    /// drained the stream would deadlock any query it runs on the same
+    /// transaction meanwhile. Inside a transaction the rows are therefore read
+    /// in full first and the connection released before they are sent.
+    pub async fn pipe<'q, O, A>(&self, query: QueryAs<'q, Db, O, A>, mut sink: mpsc::Sender<Result<O, sqlx::Error>>)
+    where
+        A: 'q + IntoArguments<'q, Db>,
+        O: Send + Unpin + for<'r> FromRow<'r, DbRow>,
+    {
+        match self {
+            Connection::Pool(pool) => forward(query.fetch(pool), sink).await,
+            Connection::Transaction(_) => match self.fetch_all(query).await {
+                Ok(rows) => forward(futures::stream::iter(rows.into_iter().map(Ok)), sink).await,
+                Err(e) => {
+                    let _ = sink.send(Err(e)).await;
+                }
+            },
+        }
+    }
+}
+
+async fn forward<O, S>(rows: S, mut sink: mpsc::Sender<Result<O, sqlx::Error>>)
+where S: Stream<Item = Result<O, sqlx::Error>>
+{
+    futures::pin_mut!(rows);
+    while let Some(row) = rows.next().await {
+        let failed = row.is_err();
+        if sink.send(row).await.is_err() || failed {
+            break;
+        }
+    }
+}
+
+fn open<'a>(guard: &'a mut Option<sqlx::Transaction<'static, Db>>) -> Result<&'a mut sqlx::Transaction<'static, Db>, sqlx::Error> {
+    guard
+        .as_mut()
+        .ok_or_else(|| sqlx::Error::Protocol("the transaction has already been committed or rolled back".into()))
+}
+
+/// An open database transaction.
+/// Handed to the callback of `DatabaseManager::transaction`; pass `&tx` to any
+/// Orbit or Builder method to run it inside the transaction.
+#[derive(Clone)]
+pub struct Transaction {
+    inner: Arc<Mutex<Option<sqlx::Transaction<'static, Db>>>>,
+    depth: Arc<AtomicUsize>,
+    grammar: Grammar,
+}
+
+impl Transaction {
+    pub async fn begin(pool: &DbPool) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Some(pool.begin().await?))),
+            depth: Arc::new(AtomicUsize::new(0)),
+            grammar: Grammar::of(pool),
+        })
+    }
+
+    /// Run the callback inside a SAVEPOINT of this transaction.
+    /// An error rolls back to the savepoint and leaves the outer transaction usable.
+    pub async fn transaction<F, Fut, R, E>(&self, callback: F) -> Result<R, E>
+    where
+        F: FnOnce(Transaction) -> Fut,
+        Fut: Future<Output = Result<R, E>>,
+        E: From<sqlx::Error>,
+    {
+        let level = self.depth.fetch_add(1, Ordering::SeqCst) + 1;
+        let savepoint = format!("orbit_savepoint_{}", level);
+
+        let result = match self.raw(&format!("SAVEPOINT {}", savepoint)).await {
+            Ok(()) => callback(self.clone()).await,
+            Err(e) => Err(e.into()),
+        };
+
+        let finish = match &result {
+            Ok(_) => self.raw(&format!("RELEASE SAVEPOINT {}", savepoint)).await,
+            Err(_) => self.raw(&format!("ROLLBACK TO SAVEPOINT {}", savepoint)).await,
+        };
+        self.depth.fetch_sub(1, Ordering::SeqCst);
+
+        let value = result?;
+        finish?;
+        Ok(value)
+    }
+
+    pub async fn commit(&self) -> Result<(), sqlx::Error> {
+        let tx = self.inner.lock().await.take();
+        match tx {
+            Some(tx) => tx.commit().await,
+            None => Ok(()),
+        }
+    }
+
+    pub async fn rollback(&self) -> Result<(), sqlx::Error> {
+        let tx = self.inner.lock().await.take();
+        match tx {
+            Some(tx) => tx.rollback().await,
+            None => Ok(()),
+        }
+    }
+
+    async fn raw(&self, sql: &str) -> Result<(), sqlx::Error> {
+        Connection::Transaction(self.clone()).execute(sqlx::query(sql)).await.map(|_| ())
+    }
+}
+
+/// Anything Orbit can run a model's queries on.
+/// `DatabaseManager` picks the model's named connection; a pool or a
+/// transaction runs everything on itself.
+pub trait ConnectionResolver: Send + Sync {
+    /// The connection for writes (and anything that must see them)
+    fn resolve(&self, name: Option<&str>) -> Result<Connection, sqlx::Error>;
+
+    /// The connection for SELECTs; a read replica when one is configured
+    fn resolve_read(&self, name: Option<&str>) -> Result<Connection, sqlx::Error> {
+        self.resolve(name)
+    }
+}
+
+impl ConnectionResolver for DatabaseManager {
+    fn resolve(&self, name: Option<&str>) -> Result<Connection, sqlx::Error> {
+        self.record_write(name);
+        self.connection(name)
+            .map(|pool| Connection::Pool(pool.clone()))
+            .ok_or(sqlx::Error::Configuration("No database connection found".into()))
+    }
+
+    fn resolve_read(&self, name: Option<&str>) -> Result<Connection, sqlx::Error> {
+        self.read_connection(name)
+            .map(|pool| Connection::Pool(pool.clone()))
+            .ok_or(sqlx::Error::Configuration("No database connection found".into()))
+    }
+}
+
+impl ConnectionResolver for DbPool {
+    fn resolve(&self, _name: Option<&str>) -> Result<Connection, sqlx::Error> {
+        Ok(Connection::Pool(self.clone()))
+    }
+}
+
+impl ConnectionResolver for Transaction {
+    fn resolve(&self, _name: Option<&str>) -> Result<Connection, sqlx::Error> {
+        Ok(Connection::Transaction(self.clone()))
+    }
+}
+
+impl ConnectionResolver for Connection {
+    fn resolve(&self, _name: Option<&str>) -> Result<Connection, sqlx::Error> {
+        Ok(self.clone())
+    }
+}
+
+impl<T: ConnectionResolver + ?Sized> ConnectionResolver for Arc<T> {
+    fn resolve(&self, name: Option<&str>) -> Result<Connection, sqlx::Error> {
+        (**self).resolve(name)
+    }
+
+    fn resolve_read(&self, name: Option<&str>) -> Result<Connection, sqlx::Error> {
+        (**self).resolve_read(name)
+    }
+}
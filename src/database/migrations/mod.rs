@@ -0,0 +1,10 @@
+use crate::database::migrator::Migration;
+
+// Register your Rust migrations here. They run in name order together with
+// the .sql files in migrations/.
+pub fn registry() -> Vec<Box<dyn Migration>> {
+    vec![
+        // Example:
+        // Box::new(create_posts_table::CreatePostsTable),
+    ]
+}
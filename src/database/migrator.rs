@@ -1,42 +1,156 @@
-use crate::database::{DatabaseManager, DbPool};
-use sqlx::{Row, Executor};
-use std::path::Path;
+use crate::database::{DatabaseManager, Db, DbPool, DbRow};
+use crate::orbit::grammar::Grammar;
+use crate::orbit::schema::Schema;
+use sha2::{Digest, Sha256};
+use sqlx::database::HasArguments;
+use sqlx::{ConnectOptions, Connection, Row, Executor};
+use std::path::{Path, PathBuf};
 use std::fs;
 
+/// A migration written in Rust with the schema builder.
+///
+/// The name uses the same `YYYYmmddHHMMSS_description` format as the files in
+/// `migrations/`, so both kinds run in one timestamp order and share the
+/// `migrations` table.
+///
+/// pub struct CreatePostsTable;
+///
+/// impl Migration for CreatePostsTable {
+///     fn name(&self) -> &str {
+///         "20250101120000_create_posts_table"
+///     }
+///
+///     fn up(&self, schema: &Schema) {
+///         schema.create("posts", |table| {
+///             table.id();
+///             table.string("title");
+///             table.timestamps();
+///         });
+///     }
+///
+///     fn down(&self, schema: &Schema) {
+///         schema.drop_if_exists("posts");
+///     }
+/// }
+pub trait Migration: Send + Sync {
+    fn name(&self) -> &str;
+    fn up(&self, schema: &Schema);
+    fn down(&self, schema: &Schema);
+}
+
 pub struct MigrationFile {
     pub name: String,
     pub up_sql: String,
     pub down_sql: String,
 }
 
+impl MigrationFile {
+    /// SHA-256 of the up and down SQL, stored when the migration runs so later
+    /// edits to an applied migration can be reported
+    pub fn checksum(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.up_sql.as_bytes());
+        hasher.update(b"\n-- --- DOWN ---\n");
+        hasher.update(self.down_sql.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+/// A row of `migrate:status`
+pub struct MigrationStatus {
+    pub name: String,
+    /// The batch it ran in, None while pending
+    pub batch: Option<i32>,
+    /// Ran, but the file changed since
+    pub modified: bool,
+    /// Recorded in the migrations table, but the file is gone
+    pub missing: bool,
+}
+
+/// A row of the `migrations` table
+struct Ran {
+    migration: String,
+    batch: i32,
+    checksum: Option<String>,
+}
+
+/// Name of the advisory lock held while migrating
+const LOCK_NAME: &str = "webrust_migrations";
+/// pg_advisory_lock takes a bigint key ("webrust" in ASCII)
+const PG_LOCK_KEY: i64 = 0x0077_6562_7275_7374;
+
 pub struct Migrator {
     manager: DatabaseManager,
+    migrations: Vec<Box<dyn Migration>>,
+    pretend: bool,
+    step: bool,
 }
 
 impl Migrator {
     pub fn new(manager: DatabaseManager) -> Self {
-        Self { manager }
+        Self { manager, migrations: Vec::new(), pretend: false, step: false }
+    }
+
+    /// Add Rust migrations to run alongside the .sql files
+    pub fn with_migrations(mut self, migrations: Vec<Box<dyn Migration>>) -> Self {
+        self.migrations.extend(migrations);
+        self
+    }
+
+    /// Print the SQL that would run instead of executing it
+    pub fn pretend(mut self, pretend: bool) -> Self {
+        self.pretend = pretend;
+        self
+    }
+
+    /// Give every migration its own batch, so they can be rolled back one at a time
+    pub fn step(mut self, step: bool) -> Self {
+        self.step = step;
+        self
+    }
+
+    fn pool(&self) -> Result<&DbPool, sqlx::Error> {
+        self.manager
+            .default_connection()
+            .ok_or(sqlx::Error::Configuration("No default database connection".into()))
+    }
+
+    async fn migration_table_exists(&self) -> Result<bool, sqlx::Error> {
+        let exists_query = match Grammar::current() {
+            Grammar::MySql => "SELECT 1 FROM information_schema.tables WHERE table_schema = DATABASE() AND table_name = 'migrations'",
+            Grammar::Postgres => "SELECT 1 FROM information_schema.tables WHERE table_schema = current_schema() AND table_name = 'migrations'",
+            Grammar::Sqlite => "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'migrations'",
+        };
+        Ok(sqlx::query(exists_query).fetch_optional(self.pool()?).await?.is_some())
     }
 
     pub async fn ensure_migration_table(&self) -> Result<(), sqlx::Error> {
-        let pool = self.manager.default_connection().expect("No default connection");
+        let pool = self.pool()?;
 
-        let exists_query = "SHOW TABLES LIKE 'migrations'";
-        let exists = sqlx::query(exists_query).fetch_optional(pool).await?;
+        let schema = Schema::new();
+        if !self.migration_table_exists().await? {
+            schema.create("migrations", |table| {
+                table.id();
+                table.string("migration");
+                table.integer("batch");
+                table.string("checksum").nullable();
+            });
+        } else if sqlx::query("SELECT checksum FROM migrations WHERE 1 = 0").execute(pool).await.is_err() {
+            // Tables created before checksums were recorded
+            schema.table("migrations", |table| {
+                table.string("checksum").nullable();
+            });
+        }
 
-        if exists.is_none() {
-             let sql = "CREATE TABLE migrations (
-                id BIGINT AUTO_INCREMENT PRIMARY KEY,
-                migration VARCHAR(255) NOT NULL,
-                batch INT NOT NULL
-            )";
-            sqlx::query(sql).execute(pool).await?;
+        for statement in schema.statements().map_err(|e| sqlx::Error::Configuration(e.into()))? {
+            pool.execute(statement.as_str()).await?;
         }
 
         Ok(())
     }
 
-    fn load_migrations(&self, path: &Path) -> Vec<MigrationFile> {
+    /// The .sql files in `path` and the registered Rust migrations, in name order
+    fn load_migrations(&self, path: &Path) -> Result<Vec<MigrationFile>, sqlx::Error> {
         let mut migrations = Vec::new();
 
         if let Ok(entries) = fs::read_dir(path) {
@@ -65,96 +179,842 @@ impl Migrator {
                 }
             }
         }
-        migrations
+
+        for migration in &self.migrations {
+            let (up, down) = (Schema::new(), Schema::new());
+            migration.up(&up);
+            migration.down(&down);
+
+            if migrations.iter().any(|m| m.name == migration.name()) {
+                return Err(sqlx::Error::Configuration(
+                    format!("Migration '{}' exists both as a .sql file and in Rust", migration.name()).into(),
+                ));
+            }
+
+            let compile = |schema: &Schema| {
+                schema.to_sql().map_err(|e| {
+                    sqlx::Error::Configuration(format!("Migration '{}': {}", migration.name(), e).into())
+                })
+            };
+            migrations.push(MigrationFile {
+                name: migration.name().to_string(),
+                up_sql: compile(&up)?,
+                down_sql: compile(&down)?,
+            });
+        }
+
+        migrations.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(migrations)
     }
 
+    /// Run every pending migration
     pub async fn run(&self, migrations_path: &Path) -> Result<(), sqlx::Error> {
-        self.ensure_migration_table().await?;
-        let pool = self.manager.default_connection().unwrap();
-        let migrations = self.load_migrations(migrations_path);
-
-        // Get ran migrations
-        let ran_migrations: Vec<String> = sqlx::query("SELECT migration FROM migrations")
-            .map(|row: sqlx::mysql::MySqlRow| row.get(0))
-            .fetch_all(pool)
-            .await?;
-
-        // Get next batch number
-        let last_batch: Option<i32> = sqlx::query("SELECT MAX(batch) FROM migrations")
-            .map(|row: sqlx::mysql::MySqlRow| row.get(0))
-            .fetch_one(pool)
-            .await
-            .unwrap_or(None);
-        let batch = last_batch.unwrap_or(0) + 1;
+        self.locked(self.run_pending(migrations_path)).await
+    }
 
-        for migration in migrations {
-            if !ran_migrations.contains(&migration.name) {
-                println!("Migrating: {}", migration.name);
+    /// Roll back the last batch
+    pub async fn rollback(&self, migrations_path: &Path) -> Result<(), sqlx::Error> {
+        self.locked(async {
+            let ran = self.ran().await?;
+            let last_batch = ran.iter().map(|r| r.batch).max();
+            let batch: Vec<Ran> = ran.into_iter().filter(|r| Some(r.batch) == last_batch).collect();
+            self.roll_back(migrations_path, batch).await
+        })
+        .await
+    }
 
-                if !migration.up_sql.is_empty() {
-                    pool.execute(migration.up_sql.as_str()).await?;
-                }
+    /// Roll back the last `steps` migrations, whatever batch they ran in
+    pub async fn rollback_steps(&self, migrations_path: &Path, steps: usize) -> Result<(), sqlx::Error> {
+        self.locked(async {
+            let ran = self.ran().await?;
+            let skip = ran.len().saturating_sub(steps);
+            self.roll_back(migrations_path, ran.into_iter().skip(skip).collect()).await
+        })
+        .await
+    }
 
-                sqlx::query("INSERT INTO migrations (migration, batch) VALUES (?, ?)")
-                    .bind(&migration.name)
-                    .bind(batch)
-                    .execute(pool)
-                    .await?;
+    /// Roll back every migration
+    pub async fn reset(&self, migrations_path: &Path) -> Result<(), sqlx::Error> {
+        self.locked(async {
+            let ran = self.ran().await?;
+            self.roll_back(migrations_path, ran).await
+        })
+        .await
+    }
 
-                println!("Migrated:  {}", migration.name);
-            }
+    /// Roll back every migration and run them all again, under one lock
+    pub async fn refresh(&self, migrations_path: &Path) -> Result<(), sqlx::Error> {
+        self.locked(async {
+            let ran = self.ran().await?;
+            self.roll_back(migrations_path, ran).await?;
+            self.run_pending(migrations_path).await
+        })
+        .await
+    }
+
+    /// Drop every table (ignoring the down migrations), then migrate from scratch
+    pub async fn fresh(&self, migrations_path: &Path) -> Result<(), sqlx::Error> {
+        self.locked(async {
+            self.drop_all_tables().await?;
+            self.run_pending(migrations_path).await
+        })
+        .await
+    }
+
+    /// Every known migration with whether (and in which batch) it ran
+    pub async fn status(&self, migrations_path: &Path) -> Result<Vec<MigrationStatus>, sqlx::Error> {
+        self.ensure_migration_table().await?;
+        let migrations = self.load_migrations(migrations_path)?;
+        let ran = self.ran().await?;
+
+        let mut status: Vec<MigrationStatus> = migrations
+            .iter()
+            .map(|migration| {
+                let row = ran.iter().find(|r| r.migration == migration.name);
+                MigrationStatus {
+                    name: migration.name.clone(),
+                    batch: row.map(|r| r.batch),
+                    modified: row.and_then(|r| r.checksum.as_ref()).is_some_and(|c| *c != migration.checksum()),
+                    missing: false,
+                }
+            })
+            .collect();
+
+        for row in ran.iter().filter(|r| !migrations.iter().any(|m| m.name == r.migration)) {
+            status.push(MigrationStatus { name: row.migration.clone(), batch: Some(row.batch), modified: false, missing: true });
         }
 
-        Ok(())
+        Ok(status)
     }
 
-    pub async fn rollback(&self, migrations_path: &Path) -> Result<(), sqlx::Error> {
+    /// Write the database structure and the `migrations` rows to the backend's
+    /// snapshot file. A database that has no migrations recorded loads the snapshot
+    /// before running the migrations newer than it. With `prune` the .sql files
+    /// the snapshot covers are deleted.
+    pub async fn dump(&self, migrations_path: &Path, prune: bool) -> Result<PathBuf, sqlx::Error> {
         self.ensure_migration_table().await?;
-        let pool = self.manager.default_connection().unwrap();
-        let migrations = self.load_migrations(migrations_path);
+        let ran = self.ran().await?;
 
-        // Get last batch
-        let last_batch: Option<i32> = sqlx::query("SELECT MAX(batch) FROM migrations")
-            .map(|row: sqlx::mysql::MySqlRow| row.get(0))
-            .fetch_one(pool)
-            .await?;
+        let mut snapshot = format!("-- Schema snapshot written by `rune schema:dump` on {}\n\n", chrono::Utc::now().to_rfc3339());
+        snapshot.push_str(&self.dump_structure().await?);
+        for row in &ran {
+            snapshot.push_str(&format!(
+                "INSERT INTO migrations (migration, batch, checksum) VALUES ({}, {}, {});\n",
+                quote(&row.migration),
+                row.batch,
+                row.checksum.as_deref().map_or("NULL".to_string(), quote)
+            ));
+        }
 
-        if let Some(batch) = last_batch {
-            let migrations_to_rollback: Vec<String> = sqlx::query("SELECT migration FROM migrations WHERE batch = ?")
-                .bind(batch)
-                .map(|row: sqlx::mysql::MySqlRow| row.get(0))
+        let path = schema_path(migrations_path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, snapshot)?;
+
+        if prune {
+            for row in &ran {
+                let file = migrations_path.join(format!("{}.sql", row.migration));
+                if file.exists() {
+                    fs::remove_file(&file)?;
+                    println!("Pruned: {}", row.migration);
+                }
+            }
+        }
+
+        Ok(path)
+    }
+
+    /// CREATE statements for every table, each ending with `;`
+    async fn dump_structure(&self) -> Result<String, sqlx::Error> {
+        let pool = self.pool()?;
+
+        match Grammar::current() {
+            Grammar::Sqlite => {
+                let statements: Vec<String> = sqlx::query(
+                    "SELECT sql FROM sqlite_master WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite_%' \
+                     ORDER BY CASE type WHEN 'table' THEN 0 WHEN 'view' THEN 1 ELSE 2 END, name",
+                )
+                .map(|row: DbRow| row.get(0))
+                .fetch_all(pool)
+                .await?;
+                Ok(statements.iter().map(|sql| format!("{};\n\n", sql)).collect())
+            }
+            Grammar::MySql => {
+                let tables: Vec<String> = sqlx::query(
+                    "SELECT table_name FROM information_schema.tables \
+                     WHERE table_schema = DATABASE() AND table_type = 'BASE TABLE' ORDER BY table_name",
+                )
+                .map(|row: DbRow| row.get(0))
                 .fetch_all(pool)
                 .await?;
 
-            // Create a map for easy lookup
-            let mut migration_map = std::collections::HashMap::new();
-            for m in &migrations {
-                migration_map.insert(m.name.clone(), m);
+                // Tables are created in name order, so foreign keys may point forward
+                let mut dump = String::from("SET FOREIGN_KEY_CHECKS = 0;\n\n");
+                for table in tables {
+                    let row = sqlx::query(&format!("SHOW CREATE TABLE {}", Grammar::MySql.wrap_table(&table))).fetch_one(pool).await?;
+                    let create: String = row.try_get(1).or_else(|_| row.try_get::<Vec<u8>, _>(1).map(|b| String::from_utf8_lossy(&b).into_owned()))?;
+                    let create = create
+                        .split(' ')
+                        .filter(|part| !part.starts_with("AUTO_INCREMENT="))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    dump.push_str(&format!("{};\n\n", create));
+                }
+                dump.push_str("SET FOREIGN_KEY_CHECKS = 1;\n\n");
+                Ok(dump)
             }
+            // Postgres has no SHOW CREATE TABLE; pg_dump knows every object type
+            Grammar::Postgres => {
+                let output = pg_dump(pool).output()?;
+                if !output.status.success() {
+                    return Err(sqlx::Error::Protocol(format!("pg_dump failed: {}", String::from_utf8_lossy(&output.stderr))));
+                }
 
-            // Rollback in reverse order of execution (usually)
-            for name in migrations_to_rollback.iter().rev() {
-                if let Some(migration) = migration_map.get(name) {
-                    println!("Rolling back: {}", name);
+                // Drop psql meta-commands and the search_path reset, which would
+                // leave the pooled connection unable to find its own tables
+                Ok(String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .filter(|line| !line.starts_with('\\') && !line.contains("set_config('search_path'"))
+                    .map(|line| format!("{}\n", line))
+                    .collect())
+            }
+        }
+    }
 
-                    if !migration.down_sql.is_empty() {
-                        pool.execute(migration.down_sql.as_str()).await?;
-                    }
+    /// Load the snapshot written by `dump`, if there is one
+    async fn load_schema(&self, migrations_path: &Path) -> Result<(), sqlx::Error> {
+        let path = schema_path(migrations_path);
+        if !path.exists() {
+            return Ok(());
+        }
+
+        let sql = fs::read_to_string(&path)?;
+        println!("Loading stored database schema: {}", path.display());
+        let pool = self.pool()?;
+        let table_exists = self.migration_table_exists().await?;
+        // One transaction, and so one connection for session settings in the snapshot
+        let mut tx = pool.begin().await?;
+        // An empty migrations table left by `status` or `dump` is recreated by the snapshot
+        if table_exists {
+            (&mut *tx).execute("DROP TABLE migrations").await?;
+        }
+        for statement in split_statements(&sql, Grammar::current()) {
+            (&mut *tx).execute(statement.as_str()).await.inspect_err(|_| failed(&statement))?;
+        }
+        tx.commit().await?;
+        println!("Loaded stored database schema.");
+        Ok(())
+    }
 
-                    sqlx::query("DELETE FROM migrations WHERE migration = ?")
-                        .bind(name)
+    /// Whether any migration has been recorded; false without a migrations table
+    async fn has_migrated(&self) -> Result<bool, sqlx::Error> {
+        if !self.migration_table_exists().await? {
+            return Ok(false);
+        }
+        Ok(sqlx::query("SELECT 1 FROM migrations LIMIT 1").fetch_optional(self.pool()?).await?.is_some())
+    }
+
+    async fn run_pending(&self, migrations_path: &Path) -> Result<(), sqlx::Error> {
+        let table_exists = self.migration_table_exists().await?;
+        let fresh = !self.has_migrated().await?;
+        if self.pretend {
+            // Pretending leaves the database untouched: no migrations table, no snapshot
+            if fresh && schema_path(migrations_path).exists() {
+                println!("-- The stored database schema {} would be loaded first\n", schema_path(migrations_path).display());
+            }
+        } else {
+            if fresh {
+                self.load_schema(migrations_path).await?;
+            }
+            self.ensure_migration_table().await?;
+        }
+        let pool = self.pool()?;
+        let migrations = self.load_migrations(migrations_path)?;
+        let ran = match table_exists || !self.pretend {
+            true => self.ran().await?,
+            false => Vec::new(),
+        };
+
+        for row in &ran {
+            let Some(migration) = migrations.iter().find(|m| m.name == row.migration) else {
+                continue;
+            };
+            match &row.checksum {
+                Some(checksum) if *checksum != migration.checksum() => {
+                    println!("⚠️  Migration '{}' was modified after it ran. Create a new migration instead.", row.migration);
+                }
+                // Recorded before checksums existed
+                None if !self.pretend => {
+                    sqlx::query(&sql("UPDATE migrations SET checksum = ? WHERE migration = ?"))
+                        .bind(migration.checksum())
+                        .bind(&row.migration)
                         .execute(pool)
                         .await?;
-
-                    println!("Rolled back:  {}", name);
-                } else {
-                    println!("⚠️  Migration '{}' found in database but file is missing. Skipping.", name);
                 }
+                _ => {}
             }
-        } else {
+        }
+
+        let mut batch = ran.iter().map(|r| r.batch).max().unwrap_or(0) + 1;
+        let pending: Vec<&MigrationFile> = migrations.iter().filter(|m| !ran.iter().any(|r| r.migration == m.name)).collect();
+
+        if pending.is_empty() {
+            println!("Nothing to migrate.");
+        }
+
+        for migration in pending {
+            if self.pretend {
+                self.print(&migration.name, &migration.up_sql);
+                continue;
+            }
+
+            println!("Migrating: {}", migration.name);
+            let insert = sql("INSERT INTO migrations (migration, batch, checksum) VALUES (?, ?, ?)");
+            let record = sqlx::query(&insert)
+                .bind(&migration.name)
+                .bind(batch)
+                .bind(migration.checksum());
+            self.apply(&migration.up_sql, record).await?;
+            println!("Migrated:  {}", migration.name);
+
+            if self.step {
+                batch += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run the down SQL of `ran` (oldest first), newest first
+    async fn roll_back(&self, migrations_path: &Path, ran: Vec<Ran>) -> Result<(), sqlx::Error> {
+        let migrations = self.load_migrations(migrations_path)?;
+
+        if ran.is_empty() {
             println!("Nothing to rollback.");
         }
 
+        for row in ran.iter().rev() {
+            let name = &row.migration;
+            let Some(migration) = migrations.iter().find(|m| m.name == *name) else {
+                println!("⚠️  Migration '{}' found in database but file is missing. Skipping.", name);
+                continue;
+            };
+
+            if self.pretend {
+                self.print(name, &migration.down_sql);
+                continue;
+            }
+
+            println!("Rolling back: {}", name);
+            let delete = sql("DELETE FROM migrations WHERE migration = ?");
+            let record = sqlx::query(&delete).bind(name);
+            self.apply(&migration.down_sql, record).await?;
+            println!("Rolled back:  {}", name);
+        }
+
+        Ok(())
+    }
+
+    /// Execute a migration's statements and its bookkeeping row together. Postgres
+    /// and SQLite run them in one transaction, so a failing migration leaves nothing
+    /// behind; MySQL commits DDL implicitly, so there it's statement by statement.
+    async fn apply<'q>(&self, sql: &str, record: sqlx::query::Query<'q, Db, <Db as HasArguments<'q>>::Arguments>) -> Result<(), sqlx::Error> {
+        let pool = self.pool()?;
+        let grammar = Grammar::current();
+        let statements = split_statements(sql, grammar);
+
+        if grammar == Grammar::MySql {
+            for statement in &statements {
+                pool.execute(statement.as_str()).await.inspect_err(|_| failed(statement))?;
+            }
+            record.execute(pool).await?;
+            return Ok(());
+        }
+
+        let mut tx = pool.begin().await?;
+        for statement in &statements {
+            (&mut *tx).execute(statement.as_str()).await.inspect_err(|_| failed(statement))?;
+        }
+        record.execute(&mut *tx).await?;
+        tx.commit().await
+    }
+
+    async fn ran(&self) -> Result<Vec<Ran>, sqlx::Error> {
+        sqlx::query("SELECT migration, batch, checksum FROM migrations ORDER BY id")
+            .map(|row: DbRow| Ran { migration: row.get(0), batch: row.get(1), checksum: row.get(2) })
+            .fetch_all(self.pool()?)
+            .await
+    }
+
+    async fn drop_all_tables(&self) -> Result<(), sqlx::Error> {
+        let pool = self.pool()?;
+        let grammar = Grammar::current();
+        let query = match grammar {
+            Grammar::MySql => "SELECT table_name FROM information_schema.tables WHERE table_schema = DATABASE() AND table_type = 'BASE TABLE'",
+            Grammar::Postgres => "SELECT tablename FROM pg_tables WHERE schemaname = current_schema()",
+            Grammar::Sqlite => "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+        };
+        let tables: Vec<String> = sqlx::query(query).map(|row: DbRow| row.get(0)).fetch_all(pool).await?;
+
+        let mut statements: Vec<String> = tables
+            .iter()
+            .map(|table| match grammar {
+                Grammar::Postgres => format!("DROP TABLE IF EXISTS {} CASCADE", grammar.wrap_table(table)),
+                Grammar::MySql | Grammar::Sqlite => format!("DROP TABLE IF EXISTS {}", grammar.wrap_table(table)),
+            })
+            .collect();
+        // Drop in any order without tripping over foreign keys
+        match grammar {
+            Grammar::MySql => {
+                statements.insert(0, "SET FOREIGN_KEY_CHECKS = 0".to_string());
+                statements.push("SET FOREIGN_KEY_CHECKS = 1".to_string());
+            }
+            Grammar::Sqlite => {
+                statements.insert(0, "PRAGMA foreign_keys = OFF".to_string());
+                statements.push("PRAGMA foreign_keys = ON".to_string());
+            }
+            Grammar::Postgres => {}
+        }
+
+        if self.pretend {
+            self.print("fresh", &statements.join(";\n"));
+            return Ok(());
+        }
+
+        println!("Dropping all tables...");
+        // One connection, so the session settings above apply to every DROP
+        let mut conn = pool.acquire().await?;
+        for statement in &statements {
+            (&mut *conn).execute(statement.as_str()).await?;
+        }
         Ok(())
     }
+
+    /// Hold an advisory lock while `work` runs so two hosts deploying at once
+    /// don't run the same migrations. SQLite databases are local files, and its
+    /// write lock already serializes migrations.
+    ///
+    /// The lock is taken on a connection of its own, opened outside the pool, so
+    /// `work` still has every pooled connection (even with `max_connections = 1`).
+    async fn locked<F, T>(&self, work: F) -> Result<T, sqlx::Error>
+    where F: std::future::Future<Output = Result<T, sqlx::Error>>
+    {
+        let pool = self.pool()?;
+        let (lock, unlock) = match Grammar::current() {
+            Grammar::MySql => (format!("SELECT GET_LOCK('{}', 60)", LOCK_NAME), format!("SELECT RELEASE_LOCK('{}')", LOCK_NAME)),
+            Grammar::Postgres => (format!("SELECT pg_advisory_lock({})", PG_LOCK_KEY), format!("SELECT pg_advisory_unlock({})", PG_LOCK_KEY)),
+            Grammar::Sqlite => return work.await,
+        };
+
+        // Advisory locks belong to the session, so keep this connection until unlocking
+        let mut conn = pool.connect_options().connect().await?;
+        let row = sqlx::query(&lock).fetch_one(&mut conn).await?;
+        // GET_LOCK returns 0 on timeout; pg_advisory_lock simply waits
+        if Grammar::current() == Grammar::MySql && row.try_get::<Option<i64>, _>(0)? != Some(1) {
+            return Err(sqlx::Error::Protocol("Timed out waiting for another process to finish migrating".into()));
+        }
+
+        let result = work.await;
+        let unlocked = sqlx::query(&unlock).execute(&mut conn).await;
+        // The lock belongs to the session, so closing the connection releases it anyway
+        conn.close().await.ok();
+        if let Err(e) = unlocked {
+            eprintln!("Failed to release the migration lock: {}", e);
+            // A failed migration is the error worth reporting
+            return result.and(Err(e));
+        }
+        result
+    }
+
+    fn print(&self, name: &str, sql: &str) {
+        println!("-- {}", name);
+        let statements = split_statements(sql, Grammar::current());
+        if statements.is_empty() {
+            println!("-- (no SQL)");
+        }
+        for statement in statements {
+            println!("{};", statement);
+        }
+        println!();
+    }
+}
+
+/// Where `dump` writes the snapshot for the compiled backend, e.g. `migrations/schema/mysql-schema.sql`
+pub fn schema_path(migrations_path: &Path) -> PathBuf {
+    let driver = match Grammar::current() {
+        Grammar::MySql => "mysql",
+        Grammar::Postgres => "postgres",
+        Grammar::Sqlite => "sqlite",
+    };
+    migrations_path.join("schema").join(format!("{}-schema.sql", driver))
+}
+
+/// `pg_dump --schema-only` for the database the pool connects to.
+/// sqlx spells its URL parameters its own way, so they are dropped and
+/// the SSL mode is passed on as libpq's PGSSLMODE instead.
+fn pg_dump(pool: &DbPool) -> std::process::Command {
+    let mut url = pool.connect_options().to_url_lossy();
+    let ssl_mode = url
+        .query_pairs()
+        .find(|(key, _)| key == "ssl-mode" || key == "sslmode")
+        .map(|(_, mode)| match mode.to_lowercase().as_str() {
+            "disabled" => "disable".to_string(),
+            "preferred" => "prefer".to_string(),
+            "required" => "require".to_string(),
+            other => other.replace('_', "-"),
+        });
+    url.set_query(None);
+
+    let mut command = std::process::Command::new("pg_dump");
+    command.args(["--schema-only", "--no-owner", "--no-privileges", url.as_str()]);
+    if let Some(mode) = ssl_mode {
+        command.env("PGSSLMODE", mode);
+    }
+    command
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// The bookkeeping queries are written with `?`; Postgres wants `$1, $2, ...`
+fn sql(query: &str) -> String {
+    Grammar::current().parameterize(query)
+}
+
+fn failed(statement: &str) {
+    eprintln!("Failed statement:\n{}", statement);
+}
+
+/// Split a migration script into statements on the `;` between them.
+///
+/// Semicolons inside quoted strings and identifiers, comments, Postgres
+/// dollar-quoted bodies (`$$ ... $$`, `$fn$ ... $fn$`) and `CREATE TRIGGER ...
+/// BEGIN ... END` blocks don't end a statement. Line comments are dropped, as are
+/// statements that are empty once they're gone. MySQL strings may use backslash
+/// escapes; Postgres and SQLite treat the backslash literally.
+pub fn split_statements(sql: &str, grammar: Grammar) -> Vec<String> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        match c {
+            '-' if next == Some('-') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            '/' if next == Some('*') => {
+                let end = find(&chars, i + 2, &['*', '/']).map_or(chars.len(), |end| end + 2);
+                // MySQL runs /*! ... */ comments as SQL
+                if chars.get(i + 2) == Some(&'!') {
+                    current.extend(&chars[i..end]);
+                } else {
+                    current.push(' ');
+                }
+                i = end;
+                continue;
+            }
+            '\'' | '"' | '`' => {
+                let mut j = i + 1;
+                while j < chars.len() && chars[j] != c {
+                    if chars[j] == '\\' && c != '`' && grammar == Grammar::MySql {
+                        j += 1;
+                    }
+                    j += 1;
+                }
+                let end = (j + 1).min(chars.len());
+                current.extend(&chars[i..end]);
+                i = end;
+                continue;
+            }
+            '$' if !current.ends_with(|p: char| p.is_alphanumeric() || p == '_') => {
+                if let Some(tag) = dollar_tag(&chars, i) {
+                    let end = find(&chars, i + tag.len(), &tag).map_or(chars.len(), |end| end + tag.len());
+                    current.extend(&chars[i..end]);
+                    i = end;
+                    continue;
+                }
+                current.push(c);
+            }
+            ';' if !inside_trigger(&current) => {
+                push_statement(&mut statements, &current);
+                current.clear();
+            }
+            _ => current.push(c),
+        }
+        i += 1;
+    }
+
+    push_statement(&mut statements, &current);
+    statements
+}
+
+fn push_statement(statements: &mut Vec<String>, statement: &str) {
+    let statement = statement.trim();
+    if !statement.is_empty() {
+        statements.push(statement.to_string());
+    }
+}
+
+/// `$$` or `$tag$` starting at `start`
+fn dollar_tag(chars: &[char], start: usize) -> Option<Vec<char>> {
+    let mut end = start + 1;
+    while end < chars.len() && (chars[end].is_alphanumeric() || chars[end] == '_') {
+        end += 1;
+    }
+    let tag_ok = chars.get(end) == Some(&'$') && !chars.get(start + 1).is_some_and(|c| c.is_ascii_digit());
+    tag_ok.then(|| chars[start..=end].to_vec())
+}
+
+fn find(chars: &[char], from: usize, needle: &[char]) -> Option<usize> {
+    (from..chars.len()).find(|&i| chars[i..].starts_with(needle))
+}
+
+/// A trigger body's statements end with `;` too; the trigger ends at `END`
+fn inside_trigger(statement: &str) -> bool {
+    let mut words = statement.split_whitespace().map(|w| w.to_uppercase());
+    let is_trigger = words.next().is_some_and(|w| w == "CREATE")
+        && words
+            .find(|w| !matches!(w.as_str(), "OR" | "REPLACE" | "TEMP" | "TEMPORARY") && !w.starts_with("DEFINER"))
+            .is_some_and(|w| w == "TRIGGER");
+    is_trigger && !statement.split_whitespace().last().is_some_and(|w| w.eq_ignore_ascii_case("END"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct CreatePostsTable;
+
+    impl Migration for CreatePostsTable {
+        fn name(&self) -> &str {
+            "20240102000000_create_posts_table"
+        }
+
+        fn up(&self, schema: &Schema) {
+            schema.create("posts", |table| {
+                table.id();
+                table.string("title");
+            });
+            schema.table("users", |table| {
+                table.string("nickname").nullable();
+            });
+        }
+
+        fn down(&self, schema: &Schema) {
+            schema.drop_if_exists("posts");
+        }
+    }
+
+    struct ChangeTitleColumn;
+
+    impl Migration for ChangeTitleColumn {
+        fn name(&self) -> &str {
+            "20240104000000_change_title_column"
+        }
+
+        fn up(&self, schema: &Schema) {
+            schema.table("posts", |table| {
+                table.text("title").change();
+            });
+        }
+
+        fn down(&self, _schema: &Schema) {}
+    }
+
+    fn migrator() -> Migrator {
+        Migrator::new(DatabaseManager::new("default".to_string())).with_migrations(vec![Box::new(CreatePostsTable)])
+    }
+
+    #[test]
+    fn test_rust_migrations_are_interleaved_with_sql_files() {
+        let dir = std::env::temp_dir().join(format!("webrust_migrations_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("20240101000000_create_users.sql"), "-- --- UP ---\nCREATE TABLE users (id INT);\n-- --- DOWN ---\nDROP TABLE users;").unwrap();
+        fs::write(dir.join("20240103000000_create_tags.sql"), "CREATE TABLE tags (id INT);").unwrap();
+
+        let migrations = migrator().load_migrations(&dir).unwrap();
+        let names: Vec<&str> = migrations.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["20240101000000_create_users", "20240102000000_create_posts_table", "20240103000000_create_tags"]);
+        assert_eq!(migrations[1].up_sql.lines().filter(|l| l.starts_with("CREATE TABLE") || l.starts_with("ALTER TABLE")).count(), 2);
+        assert!(migrations[1].down_sql.starts_with("DROP TABLE IF EXISTS"));
+
+        fs::write(dir.join("20240102000000_create_posts_table.sql"), "CREATE TABLE posts (id INT);").unwrap();
+        assert!(migrator().load_migrations(&dir).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_checksum_tracks_up_and_down_sql() {
+        let file = |up: &str, down: &str| MigrationFile { name: "m".into(), up_sql: up.into(), down_sql: down.into() };
+
+        let checksum = file("CREATE TABLE a (id INT);", "DROP TABLE a;").checksum();
+        assert_eq!(checksum.len(), 64);
+        assert_eq!(checksum, file("CREATE TABLE a (id INT);", "DROP TABLE a;").checksum());
+        assert_ne!(checksum, file("CREATE TABLE a (id BIGINT);", "DROP TABLE a;").checksum());
+        assert_ne!(checksum, file("CREATE TABLE a (id INT);", "").checksum());
+    }
+
+    #[test]
+    fn test_split_statements() {
+        let sql = "-- Migration: seed\n\
+                   CREATE TABLE notes (body TEXT); -- trailing; comment\n\
+                   INSERT INTO notes VALUES ('a;b'), ('it''s; fine'), (\"c;d\");\n\
+                   /* block; comment */\n\
+                   ;\n\
+                   INSERT INTO `semi;colon` VALUES (1)";
+        assert_eq!(
+            split_statements(sql, Grammar::Sqlite),
+            vec![
+                "CREATE TABLE notes (body TEXT)",
+                "INSERT INTO notes VALUES ('a;b'), ('it''s; fine'), (\"c;d\")",
+                "INSERT INTO `semi;colon` VALUES (1)",
+            ]
+        );
+
+        let escaped = "INSERT INTO notes VALUES ('it\\'s; mysql'); SELECT 1";
+        assert_eq!(split_statements(escaped, Grammar::MySql).len(), 2);
+        assert_eq!(split_statements("SELECT 'C:\\'; SELECT 2", Grammar::Postgres).len(), 2);
+    }
+
+    #[test]
+    fn test_split_statements_keeps_bodies_together() {
+        let function = "CREATE FUNCTION touch() RETURNS trigger AS $$\n\
+                        BEGIN NEW.updated_at := now(); RETURN NEW; END;\n\
+                        $$ LANGUAGE plpgsql;\n\
+                        CREATE FUNCTION two() RETURNS int AS $body$ SELECT 1; SELECT 2; $body$ LANGUAGE sql;\n\
+                        SELECT $1";
+        let statements = split_statements(function, Grammar::Postgres);
+        assert_eq!(statements.len(), 3);
+        assert!(statements[0].ends_with("$$ LANGUAGE plpgsql"));
+        assert!(statements[1].contains("SELECT 1; SELECT 2;"));
+        assert_eq!(statements[2], "SELECT $1");
+
+        let trigger = "CREATE TRIGGER touch AFTER UPDATE ON notes BEGIN\n\
+                       UPDATE notes SET updated_at = 1 WHERE id = NEW.id;\n\
+                       END;\n\
+                       DROP TABLE backend";
+        assert_eq!(split_statements(trigger, Grammar::Sqlite).len(), 2);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_migrations_run_and_roll_back_on_sqlite() {
+        let dir = std::env::temp_dir().join(format!("webrust_migrator_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let users = "-- --- UP ---\n\
+            CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);\n\
+            INSERT INTO users (name) VALUES ('semi;colon');\n\
+            -- --- DOWN ---\n\
+            DROP TABLE users;";
+        fs::write(dir.join("20240101000000_create_users.sql"), users).unwrap();
+
+        let db = crate::support::testing::memory_database().await;
+        let pool = db.default_connection().unwrap().clone();
+        let tables = || async {
+            sqlx::query_scalar::<_, String>("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name")
+                .fetch_all(&pool)
+                .await
+                .unwrap()
+        };
+        let migrator = || Migrator::new(db.clone()).with_migrations(vec![Box::new(CreatePostsTable)]);
+
+        migrator().step(true).run(&dir).await.unwrap();
+        assert_eq!(tables().await, ["migrations", "posts", "users"]);
+        let status = migrator().status(&dir).await.unwrap();
+        assert_eq!(status.iter().map(|s| s.batch).collect::<Vec<_>>(), [Some(1), Some(2)]);
+
+        // A failing migration leaves neither its tables nor a migrations row behind
+        fs::write(dir.join("20240105000000_broken.sql"), "CREATE TABLE tags (id INTEGER); INSERT INTO missing VALUES (1);").unwrap();
+        assert!(migrator().run(&dir).await.is_err());
+        assert_eq!(tables().await, ["migrations", "posts", "users"]);
+        fs::remove_file(dir.join("20240105000000_broken.sql")).unwrap();
+
+        // Edited after running
+        fs::write(dir.join("20240101000000_create_users.sql"), "CREATE TABLE users (id INTEGER);").unwrap();
+        let status = migrator().status(&dir).await.unwrap();
+        assert!(status[0].modified && !status[1].modified);
+        fs::write(dir.join("20240101000000_create_users.sql"), users).unwrap();
+
+        migrator().rollback(&dir).await.unwrap();
+        assert_eq!(tables().await, ["migrations", "users"]);
+        migrator().rollback_steps(&dir, 1).await.unwrap();
+        assert_eq!(tables().await, ["migrations"]);
+
+        migrator().run(&dir).await.unwrap();
+        migrator().fresh(&dir).await.unwrap();
+        assert_eq!(tables().await, ["migrations", "posts", "users"]);
+        migrator().refresh(&dir).await.unwrap();
+        assert_eq!(tables().await, ["migrations", "posts", "users"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_pretend_leaves_the_database_untouched() {
+        let dir = std::env::temp_dir().join(format!("webrust_pretend_{}", std::process::id()));
+        let db = crate::support::testing::memory_database().await;
+        Migrator::new(db.clone()).with_migrations(vec![Box::new(CreatePostsTable)]).pretend(true).run(&dir).await.unwrap();
+
+        let tables: Vec<String> = sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table'")
+            .fetch_all(db.default_connection().unwrap())
+            .await
+            .unwrap();
+        assert!(tables.is_empty(), "{:?}", tables);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_changes_sqlite_cannot_make_fail_the_run() {
+        let dir = std::env::temp_dir().join(format!("webrust_unsupported_{}", std::process::id()));
+        let db = crate::support::testing::memory_database().await;
+        let migrator = Migrator::new(db).with_migrations(vec![Box::new(ChangeTitleColumn)]);
+
+        let error = migrator.run(&dir).await.unwrap_err().to_string();
+        assert!(error.contains("Migration '20240104000000_change_title_column': SQLite can't modify columns"), "{}", error);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_schema_dump_is_loaded_into_an_empty_database() {
+        let dir = std::env::temp_dir().join(format!("webrust_schema_dump_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("20240101000000_create_users.sql"), "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);").unwrap();
+
+        let first = crate::support::testing::memory_database().await;
+        let migrator = Migrator::new(first.clone()).with_migrations(vec![Box::new(CreatePostsTable)]);
+        migrator.run(&dir).await.unwrap();
+        let path = migrator.dump(&dir, true).await.unwrap();
+        assert_eq!(path, dir.join("schema").join("sqlite-schema.sql"));
+        assert!(!dir.join("20240101000000_create_users.sql").exists());
+
+        fs::write(dir.join("20240103000000_create_tags.sql"), "CREATE TABLE tags (id INTEGER PRIMARY KEY);").unwrap();
+        let second = crate::support::testing::memory_database().await;
+        let migrator = Migrator::new(second.clone()).with_migrations(vec![Box::new(CreatePostsTable)]);
+        // `status` creates an empty migrations table; the snapshot still has to be loaded
+        assert!(migrator.status(&dir).await.unwrap().iter().all(|s| s.batch.is_none()));
+        migrator.run(&dir).await.unwrap();
+
+        let pool = second.default_connection().unwrap();
+        let ran: Vec<(String, i32)> = sqlx::query_as("SELECT migration, batch FROM migrations ORDER BY id").fetch_all(pool).await.unwrap();
+        assert_eq!(ran, [
+            ("20240101000000_create_users".to_string(), 1),
+            ("20240102000000_create_posts_table".to_string(), 1),
+            ("20240103000000_create_tags".to_string(), 2),
+        ]);
+        let columns: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info('users')").fetch_all(pool).await.unwrap();
+        assert_eq!(columns, ["id", "name", "nickname"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
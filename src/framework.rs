@@ -4,6 +4,9 @@ use std::sync::Arc;
 use crate::database::{DbPool, DbPoolOptions, DatabaseManager};
 use tera::Tera;
 use crate::config::Config;
+use crate::config::database::{DatabaseConfig, DatabaseConfigError, DatabaseConnectionConfig};
+use crate::orbit::Grammar;
+use std::time::Duration;
 use crate::cache::Cache;
 use axum::Router;
 
@@ -41,36 +44,113 @@ pub fn build_tera() -> Result<Tera, tera::Error> {
     Ok(tera)
 }
 
-pub async fn build_database_manager() -> DatabaseManager {
-    let config = Config::new();
-    let mut manager = DatabaseManager::new(config.database.default.clone());
+/// The grammar a configured connection speaks, from its `driver` or URL.
+/// None (with a warning) when it can't be used by this build.
+fn connection_grammar(name: &str, config: &DatabaseConnectionConfig, url: &str) -> Option<Grammar> {
+    let from_url = Grammar::from_url(url);
+    let grammar = match &config.driver {
+        Some(driver) => match Grammar::from_driver(driver) {
+            Some(grammar) if from_url.is_none_or(|g| g == grammar) => grammar,
+            Some(_) => {
+                tracing::warn!("⚠️  Database connection '{}' declares driver '{}' but its URL is for another one. Skipping.", name, driver);
+                return None;
+            }
+            None => {
+                tracing::warn!("⚠️  Database connection '{}' has unknown driver '{}'. Skipping.", name, driver);
+                return None;
+            }
+        },
+        None => from_url.unwrap_or_else(Grammar::current),
+    };
+
+    if cfg!(not(feature = "any")) && grammar != Grammar::current() {
+        tracing::warn!("⚠️  Database connection '{}' needs the {:?} driver; build with `--features any` to mix drivers. Skipping.", name, grammar);
+        return None;
+    }
+    Some(grammar)
+}
+
+async fn connect_pool(config: &DatabaseConnectionConfig, grammar: Grammar, url: &str) -> Result<DbPool, sqlx::Error> {
+    let mut options = DbPoolOptions::new()
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .acquire_timeout(Duration::from_secs(5))
+        .idle_timeout(config.idle_timeout.map(Duration::from_secs))
+        .max_lifetime(config.max_lifetime.map(Duration::from_secs));
+
+    let timeout = config.statement_timeout.and_then(|secs| grammar.compile_statement_timeout(secs * 1000));
+    if let Some(sql) = timeout {
+        options = options.after_connect(move |conn, _meta| {
+            let sql = sql.clone();
+            Box::pin(async move {
+                sqlx::Executor::execute(conn, sql.as_str()).await?;
+                Ok(())
+            })
+        });
+    }
+
+    options.connect(url).await
+}
 
-    for (name, conn_config) in &config.database.connections {
-        if conn_config.url.is_empty() {
+pub async fn build_database_manager() -> Result<DatabaseManager, DatabaseConfigError> {
+    let database = DatabaseConfig::load()?;
+    let mut manager = DatabaseManager::new(database.default.clone());
+
+    #[cfg(feature = "any")]
+    sqlx::any::install_default_drivers();
+
+    for (name, conn_config) in &database.connections {
+        let primaries = match conn_config.write.is_empty() {
+            true => vec![conn_config.url.clone()],
+            false => conn_config.write.clone(),
+        };
+        if primaries.iter().all(|url| url.is_empty()) {
             tracing::warn!("⚠️  Database connection '{}' has no URL configured. Skipping.", name);
             continue;
         }
 
-        // We currently only support the compiled feature's driver (e.g. mysql)
-        // So we ignore conn_config.driver for now, or warn if it doesn't match.
-        // Ideally we would check if conn_config.driver matches the compiled feature.
+        let Some(grammar) = primaries.iter().find(|url| !url.is_empty()).and_then(|url| connection_grammar(name, conn_config, url)) else {
+            continue;
+        };
+        if conn_config.statement_timeout.is_some() && grammar.compile_statement_timeout(0).is_none() {
+            tracing::warn!("⚠️  Database connection '{}': statement_timeout isn't supported by this driver. Ignoring it.", name);
+        }
 
-        let pool_result = DbPoolOptions::new()
-            .max_connections(conn_config.max_connections)
-            .acquire_timeout(std::time::Duration::from_secs(5))
-            .connect(&conn_config.url)
-            .await;
+        // The first write host that accepts connections becomes the primary
+        let mut primary = None;
+        for url in primaries.iter().filter(|url| !url.is_empty()) {
+            match connect_pool(conn_config, grammar, url).await {
+                Ok(pool) => {
+                    primary = Some(pool);
+                    break;
+                }
+                Err(e) => tracing::warn!("⚠️  Failed to connect to database '{}': {}.", name, e),
+            }
+        }
 
-        match pool_result {
-            Ok(pool) => {
+        match primary {
+            Some(pool) => {
                 tracing::info!("✅ Database connection '{}' established", name);
                 manager.add(name, pool);
             }
-            Err(e) => {
-                tracing::warn!("⚠️  Failed to connect to database '{}': {}. Skipping.", name, e);
+            None => {
+                tracing::warn!("⚠️  No write host of database '{}' is reachable. Skipping.", name);
+                continue;
             }
         }
+
+        let mut replicas = Vec::new();
+        for url in conn_config.read.iter().filter(|url| !url.is_empty()) {
+            match connect_pool(conn_config, grammar, url).await {
+                Ok(pool) => replicas.push(pool),
+                Err(e) => tracing::warn!("⚠️  Failed to connect to a read replica of '{}': {}. Skipping it.", name, e),
+            }
+        }
+        if !replicas.is_empty() {
+            tracing::info!("✅ Database connection '{}' reads from {} replica(s)", name, replicas.len());
+            manager.add_read_replicas(name, replicas, conn_config.sticky);
+        }
     }
 
-    manager
+    Ok(manager)
 }
@@ -2,8 +2,10 @@ pub mod logging;
 pub mod api_auth;
 pub mod csrf;
 pub mod inertia;
+pub mod sticky_reads;
 
 pub use logging::log_request;
 pub use api_auth::api_auth;
 pub use csrf::csrf_protection;
 pub use inertia::share_inertia_data;
+pub use sticky_reads::sticky_reads;
@@ -0,0 +1,12 @@
+use axum::{
+    body::Body,
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+
+/// After a request writes to a connection with sticky read replicas, its
+/// remaining reads on that connection go to the primary and see the write.
+pub async fn sticky_reads(req: Request<Body>, next: Next) -> Response {
+    crate::database::sticky_reads(next.run(req)).await
+}
@@ -197,7 +197,10 @@ mod tests {
             .exclude_path("/status")
             .build();
 
-        assert_eq!(limiter.config.exclude_paths.len(), 2);
+        // The defaults (/health, /metrics) are kept alongside the new paths
+        for path in ["/health", "/metrics", "/status"] {
+            assert!(limiter.exclude_paths().iter().any(|p| p == path));
+        }
     }
 
     #[test]
@@ -149,6 +149,11 @@ impl RateLimiter {
         self.config.max_requests
     }
 
+    /// Get excluded paths
+    pub fn exclude_paths(&self) -> &[String] {
+        &self.config.exclude_paths
+    }
+
     /// Reset limit for a key
     pub async fn reset(&self, key: &str) {
         self.limits.write().await.remove(key);
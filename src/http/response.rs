@@ -7,6 +7,7 @@ use axum::{
     body::Body,
 };
 use serde_json::{json, Value};
+use crate::orbit::Paginator;
 
 /// Success response with data
 pub fn success<T: serde::Serialize>(data: T) -> Response {
@@ -119,27 +120,36 @@ pub fn server_error(message: &str) -> Response {
     error(StatusCode::INTERNAL_SERVER_ERROR, message)
 }
 
-/// Paginated response
-pub fn paginated<T: serde::Serialize>(
-    data: Vec<T>,
-    current_page: i64,
-    per_page: i64,
-    total: i64,
-) -> Response {
-    let total_pages = (total as f64 / per_page as f64).ceil() as i64;
+/// Paginated response with next/prev links
+/// paginated(Post::query().paginate(&db, page, 15).await?.with_path("/api/posts"))
+pub fn paginated<T: serde::Serialize>(paginator: Paginator<T>) -> Response {
+    let links = json!({
+        "next": paginator.next_page_url(),
+        "prev": paginator.prev_page_url(),
+    });
+
+    let pagination = match paginator.current_page {
+        Some(current_page) => json!({
+            "current_page": current_page,
+            "per_page": paginator.per_page,
+            "total": paginator.total,
+            "total_pages": paginator.last_page,
+            "has_more": paginator.has_more
+        }),
+        None => json!({
+            "per_page": paginator.per_page,
+            "next_cursor": paginator.next_cursor,
+            "has_more": paginator.has_more
+        }),
+    };
 
     (
         StatusCode::OK,
         axum::Json(json!({
             "success": true,
-            "data": data,
-            "pagination": {
-                "current_page": current_page,
-                "per_page": per_page,
-                "total": total,
-                "total_pages": total_pages,
-                "has_more": current_page < total_pages
-            }
+            "data": paginator.data,
+            "pagination": pagination,
+            "links": links
         }))
     ).into_response()
 }
@@ -22,7 +22,8 @@ use clap::Parser;
 use crate::cli::{Cli, Command, RuneCommand};
 use crate::framework::{AppState, build_tera, build_database_manager};
 use crate::routes::router;
-use crate::cache::{Cache, RedisCache, FileCache, MemoryCache};
+use crate::cache::{Cache, RedisCache, FileCache, MemoryCache, TieredCache, DatabaseCache};
+use crate::config::cache::CacheConfig;
 use std::process::{Command as ProcessCommand, Child};
 use notify::{Watcher, RecursiveMode};
 use std::sync::mpsc::channel;
@@ -78,11 +79,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     let _guard = crate::services::log::setup();
 
                     // Initialize Database Manager (handles multiple connections)
-                    let db_manager = build_database_manager().await;
+                    let db_manager = build_database_manager().await?;
 
                     // Initialize Cache
-                    let cache_driver = std::env::var("CACHE_DRIVER").unwrap_or_else(|_| "file".to_string());
-                    let cache: Cache = match cache_driver.as_str() {
+                    let cache_config = CacheConfig::default();
+                    let cache: Cache = match cache_config.driver.as_str() {
                         "redis" => {
                             let redis_url = std::env::var("REDIS_URL").expect("REDIS_URL must be set when CACHE_DRIVER is redis");
                             match RedisCache::new(&redis_url).await {
@@ -96,13 +97,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                 }
                             }
                         }
+                        "tiered" => {
+                            let redis_url = std::env::var("REDIS_URL").expect("REDIS_URL must be set when CACHE_DRIVER is tiered");
+                            match TieredCache::connect(&redis_url, &cache_config).await {
+                                Ok(c) => {
+                                    tracing::info!("✅ Tiered Cache connected (memory in front of Redis)");
+                                    Cache::Tiered(c)
+                                }
+                                Err(e) => {
+                                    tracing::error!("❌ Failed to connect to Redis: {}", e);
+                                    panic!("Failed to connect to Redis");
+                                }
+                            }
+                        }
+                        "database" => {
+                            tracing::info!("✅ Using Database Cache (table {})", cache_config.database_table);
+                            Cache::Database(DatabaseCache::from_config(db_manager.clone(), &cache_config))
+                        }
                         "file" => {
                             tracing::info!("✅ Using File Cache (storage/cache)");
                             Cache::File(FileCache::new("storage/cache"))
                         }
                         _ => {
                             tracing::info!("✅ Using Memory Cache");
-                            Cache::Memory(MemoryCache::new())
+                            Cache::Memory(MemoryCache::from_config(&cache_config))
                         }
                     };
 
@@ -138,14 +156,32 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 RuneCommand::MakeMigration { name } => {
                     cli::make_migration(&name)?;
                 }
-                RuneCommand::Migrate => {
-                    cli::run_migrations().await?;
+                RuneCommand::CacheTable => {
+                    cli::make_cache_table()?;
+                }
+                RuneCommand::Migrate { step, pretend } => {
+                    cli::run_migrations(step, pretend).await?;
                 }
-                RuneCommand::MigrateRollback => {
-                    cli::rollback_migrations().await?;
+                RuneCommand::SchemaDump { prune } => {
+                    cli::dump_schema(prune).await?;
+                }
+                RuneCommand::MigrateStatus => {
+                    cli::migration_status().await?;
+                }
+                RuneCommand::MigrateReset { pretend } => {
+                    cli::reset_migrations(pretend).await?;
+                }
+                RuneCommand::MigrateRefresh { step } => {
+                    cli::refresh_migrations(step).await?;
+                }
+                RuneCommand::MigrateFresh { step } => {
+                    cli::fresh_migrations(step).await?;
+                }
+                RuneCommand::MigrateRollback { step, pretend } => {
+                    cli::rollback_migrations(step, pretend).await?;
                 }
                 RuneCommand::DbSeed { class } => {
-                    let db_manager = build_database_manager().await;
+                    let db_manager = build_database_manager().await?;
                     println!("🌱 Seeding database...");
 
                     // In a real scenario, we would use a registry or reflection to find the seeder by name.
@@ -177,7 +213,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     queue_config.queue_name = queue;
 
                     // Initialize Database Manager for failed jobs
-                    let db_manager = build_database_manager().await;
+                    let db_manager = build_database_manager().await?;
 
                     // Register jobs here
                     let mut registry = crate::services::queue::JobRegistry::new();
@@ -191,14 +227,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     println!("⏰ Starting Scheduler...");
 
                     // Initialize Cache
-                    let cache_driver = std::env::var("CACHE_DRIVER").unwrap_or_else(|_| "file".to_string());
-                    let cache: Cache = match cache_driver.as_str() {
+                    let cache_config = CacheConfig::default();
+                    let cache: Cache = match cache_config.driver.as_str() {
                         "redis" => {
                             let redis_url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1".to_string());
-                            RedisCache::new(&redis_url).await.map(Cache::Redis).unwrap_or_else(|_| Cache::Memory(MemoryCache::new()))
+                            RedisCache::new(&redis_url).await.map(Cache::Redis).unwrap_or_else(|_| Cache::Memory(MemoryCache::from_config(&cache_config)))
                         }
+                        "tiered" => {
+                            let redis_url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1".to_string());
+                            TieredCache::connect(&redis_url, &cache_config).await.map(Cache::Tiered).unwrap_or_else(|_| Cache::Memory(MemoryCache::from_config(&cache_config)))
+                        }
+                        "database" => Cache::Database(DatabaseCache::from_config(build_database_manager().await?, &cache_config)),
                         "file" => Cache::File(FileCache::new("storage/cache")),
-                        _ => Cache::Memory(MemoryCache::new()),
+                        _ => Cache::Memory(MemoryCache::from_config(&cache_config)),
                     };
 
                     let scheduler = crate::services::scheduler::Scheduler::new(cache).await.expect("Failed to create scheduler");
@@ -222,16 +263,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     cli::make_command(&name)?;
                 }
                 RuneCommand::Tinker => {
-                    let db_manager = build_database_manager().await;
+                    let db_manager = build_database_manager().await?;
                     let tera = build_tera()?;
-                    let cache_driver = std::env::var("CACHE_DRIVER").unwrap_or_else(|_| "file".to_string());
-                    let cache: Cache = match cache_driver.as_str() {
+                    let cache_config = CacheConfig::default();
+                    let cache: Cache = match cache_config.driver.as_str() {
                         "redis" => {
                             let redis_url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1".to_string());
-                            RedisCache::new(&redis_url).await.map(Cache::Redis).unwrap_or_else(|_| Cache::Memory(MemoryCache::new()))
+                            RedisCache::new(&redis_url).await.map(Cache::Redis).unwrap_or_else(|_| Cache::Memory(MemoryCache::from_config(&cache_config)))
+                        }
+                        "tiered" => {
+                            let redis_url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1".to_string());
+                            TieredCache::connect(&redis_url, &cache_config).await.map(Cache::Tiered).unwrap_or_else(|_| Cache::Memory(MemoryCache::from_config(&cache_config)))
                         }
+                        "database" => Cache::Database(DatabaseCache::from_config(db_manager.clone(), &cache_config)),
                         "file" => Cache::File(FileCache::new("storage/cache")),
-                        _ => Cache::Memory(MemoryCache::new()),
+                        _ => Cache::Memory(MemoryCache::from_config(&cache_config)),
                     };
                     let state = AppState::new(db_manager, tera, cache);
                     if let Err(e) = crate::commands::tinker::tinker(state).await {
@@ -1,4 +1,4 @@
 pub mod user;
 pub mod observer;
 
-pub use observer::{Observer, Observable};
+pub use observer::{ModelEvent, Observer, Observable};
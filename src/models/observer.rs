@@ -1,7 +1,70 @@
 use async_trait::async_trait;
 use serde_json::Value;
+use std::fmt;
+
+/// Model lifecycle events.
+/// Orbit fires saving → creating → created → saved on create, saving → updating →
+/// updated → saved on update, deleting → deleted and restoring → restored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelEvent {
+    Creating,
+    Created,
+    Updating,
+    Updated,
+    Deleting,
+    Deleted,
+    Saving,
+    Saved,
+    Restoring,
+    Restored,
+}
+
+impl ModelEvent {
+    /// Events fired before the write; an observer error aborts it
+    pub fn is_before(self) -> bool {
+        matches!(self, Self::Creating | Self::Updating | Self::Deleting | Self::Saving | Self::Restoring)
+    }
+}
+
+impl fmt::Display for ModelEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Creating => "creating",
+            Self::Created => "created",
+            Self::Updating => "updating",
+            Self::Updated => "updated",
+            Self::Deleting => "deleting",
+            Self::Deleted => "deleted",
+            Self::Saving => "saving",
+            Self::Saved => "saved",
+            Self::Restoring => "restoring",
+            Self::Restored => "restored",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Call `event` on each observer in order, stopping at the first error
+pub async fn dispatch(observers: &[Box<dyn Observer>], event: ModelEvent, model: &Value) -> Result<(), Box<dyn std::error::Error>> {
+    for observer in observers {
+        match event {
+            ModelEvent::Creating => observer.creating(model).await?,
+            ModelEvent::Created => observer.created(model).await?,
+            ModelEvent::Updating => observer.updating(model).await?,
+            ModelEvent::Updated => observer.updated(model).await?,
+            ModelEvent::Deleting => observer.deleting(model).await?,
+            ModelEvent::Deleted => observer.deleted(model).await?,
+            ModelEvent::Saving => observer.saving(model).await?,
+            ModelEvent::Saved => observer.saved(model).await?,
+            ModelEvent::Restoring => observer.restoring(model).await?,
+            ModelEvent::Restored => observer.restored(model).await?,
+        }
+    }
+    Ok(())
+}
 
-/// Observer trait - implement this to observe model lifecycle events
+/// Observer trait - implement this to observe model lifecycle events.
+/// `model` is the model's attributes as JSON (for creating, the attributes being inserted).
 #[async_trait]
 pub trait Observer: Send + Sync {
     /// Called when a model is being created (before save)
@@ -43,76 +106,69 @@ pub trait Observer: Send + Sync {
     async fn saved(&self, _model: &Value) -> Result<(), Box<dyn std::error::Error>> {
         Ok(())
     }
+
+    /// Called when a soft deleted model is being restored
+    async fn restoring(&self, _model: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    /// Called after a soft deleted model is restored
+    async fn restored(&self, _model: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
 }
 
-/// Model observable trait - add to your models
+/// Model observable trait - add to your models.
+/// Orbit models fire these automatically once `Orbit::observers` returns them
+/// (`#[orbit(observable)]` with the derive).
 #[async_trait]
 pub trait Observable: Send + Sync {
     /// Get observers for this model
     fn observers() -> Vec<Box<dyn Observer>>;
 
+    /// Trigger any event
+    async fn fire(&self, event: ModelEvent, model: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        dispatch(&Self::observers(), event, model).await
+    }
+
     /// Trigger creating event
-    async fn fire_creating(&self) -> Result<(), Box<dyn std::error::Error>> {
-        for observer in Self::observers() {
-            observer.creating(&Value::Null).await?;
-        }
-        Ok(())
+    async fn fire_creating(&self, model: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        self.fire(ModelEvent::Creating, model).await
     }
 
     /// Trigger created event
-    async fn fire_created(&self) -> Result<(), Box<dyn std::error::Error>> {
-        for observer in Self::observers() {
-            observer.created(&Value::Null).await?;
-        }
-        Ok(())
+    async fn fire_created(&self, model: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        self.fire(ModelEvent::Created, model).await
     }
 
     /// Trigger updating event
-    async fn fire_updating(&self) -> Result<(), Box<dyn std::error::Error>> {
-        for observer in Self::observers() {
-            observer.updating(&Value::Null).await?;
-        }
-        Ok(())
+    async fn fire_updating(&self, model: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        self.fire(ModelEvent::Updating, model).await
     }
 
     /// Trigger updated event
-    async fn fire_updated(&self) -> Result<(), Box<dyn std::error::Error>> {
-        for observer in Self::observers() {
-            observer.updated(&Value::Null).await?;
-        }
-        Ok(())
+    async fn fire_updated(&self, model: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        self.fire(ModelEvent::Updated, model).await
     }
 
     /// Trigger deleting event
-    async fn fire_deleting(&self) -> Result<(), Box<dyn std::error::Error>> {
-        for observer in Self::observers() {
-            observer.deleting(&Value::Null).await?;
-        }
-        Ok(())
+    async fn fire_deleting(&self, model: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        self.fire(ModelEvent::Deleting, model).await
     }
 
     /// Trigger deleted event
-    async fn fire_deleted(&self) -> Result<(), Box<dyn std::error::Error>> {
-        for observer in Self::observers() {
-            observer.deleted(&Value::Null).await?;
-        }
-        Ok(())
+    async fn fire_deleted(&self, model: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        self.fire(ModelEvent::Deleted, model).await
     }
 
     /// Trigger saving event
-    async fn fire_saving(&self) -> Result<(), Box<dyn std::error::Error>> {
-        for observer in Self::observers() {
-            observer.saving(&Value::Null).await?;
-        }
-        Ok(())
+    async fn fire_saving(&self, model: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        self.fire(ModelEvent::Saving, model).await
     }
 
     /// Trigger saved event
-    async fn fire_saved(&self) -> Result<(), Box<dyn std::error::Error>> {
-        for observer in Self::observers() {
-            observer.saved(&Value::Null).await?;
-        }
-        Ok(())
+    async fn fire_saved(&self, model: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        self.fire(ModelEvent::Saved, model).await
     }
 }
 
@@ -202,21 +258,21 @@ mod tests {
     #[tokio::test]
     async fn test_observer_created() {
         let model = TestModel;
-        let result = model.fire_created().await;
+        let result = model.fire_created(&serde_json::json!({ "id": 1 })).await;
         assert!(result.is_ok());
     }
 
     #[tokio::test]
     async fn test_observer_updated() {
         let model = TestModel;
-        let result = model.fire_updated().await;
+        let result = model.fire_updated(&serde_json::json!({ "id": 1 })).await;
         assert!(result.is_ok());
     }
 
     #[tokio::test]
     async fn test_observer_deleted() {
         let model = TestModel;
-        let result = model.fire_deleted().await;
+        let result = model.fire_deleted(&serde_json::json!({ "id": 1 })).await;
         assert!(result.is_ok());
     }
 }
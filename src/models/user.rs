@@ -1,30 +1,22 @@
 use chrono::{DateTime, Utc};
-use serde::Serialize;
-use sqlx::FromRow;
 use crate::database::DbPool;
 use crate::orbit::Orbit;
 
-#[derive(Debug, Serialize, FromRow)]
+// Orbit also derives Serialize, leaving out the hidden fields
+#[derive(Debug, Orbit)]
+#[orbit(table = "users")]
 pub struct User {
     pub id: i64,
     pub username: String,
     pub email: String,
-    #[serde(skip)] // Don't serialize password to JSON
+    #[orbit(hidden)]
     pub password_hash: String,
+    #[orbit(cast = "datetime")]
     pub created_at: Option<DateTime<Utc>>,
+    #[orbit(cast = "datetime")]
     pub updated_at: Option<DateTime<Utc>>,
 }
 
-impl Orbit for User {
-    fn table_name() -> &'static str {
-        "users"
-    }
-
-    fn id(&self) -> i64 {
-        self.id
-    }
-}
-
 impl User {
     // Custom methods can still exist
     pub async fn find_by_email(pool: &DbPool, email: &str) -> Result<Option<User>, sqlx::Error> {
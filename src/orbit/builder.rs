@@ -1,38 +1,372 @@
-use crate::database::{Db, DbArguments, DbRow, DbPool, DatabaseManager};
-use crate::orbit::Orbit;
-use sqlx::{FromRow, Execute, Encode, Type, Arguments};
+use crate::database::{Connection, ConnectionResolver, Db, DbArguments, DbRow};
+use crate::orbit::grammar::Grammar;
+use crate::orbit::pagination::{encode_cursor, Paginator};
+use crate::orbit::relations::{eager_load, Loaded};
+use crate::orbit::scope::Scope;
+use crate::orbit::casts::{bind_attribute, cast_for};
+use crate::orbit::{fill, Orbit};
+use sqlx::{FromRow, Decode, Encode, Type, Arguments, Row};
+use futures::channel::mpsc;
+use futures::stream::{BoxStream, StreamExt};
+use std::future::Future;
 use std::marker::PhantomData;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+/// Rows `Builder::stream` buffers ahead of the consumer
+const STREAM_BUFFER: usize = 64;
+
+/// A value that can be bound to a query parameter
+pub trait Bindable: 'static + for<'q> Encode<'q, Db> + Type<Db> + Send + Sync + Clone {}
+
+impl<V> Bindable for V where V: 'static + for<'q> Encode<'q, Db> + Type<Db> + Send + Sync + Clone {}
+
+pub type ArgumentApplier = Box<dyn Fn(&mut DbArguments) + Send + Sync>;
+
+fn binder<V: Bindable>(val: V) -> ArgumentApplier {
+    Box::new(move |args: &mut DbArguments| {
+        args.add(val.clone());
+    })
+}
+
+/// A single WHERE condition, compiled by the grammar when the SQL is built
+enum WhereClause {
+    Basic { boolean: &'static str, column: String, operator: String },
+    Raw { boolean: &'static str, sql: String },
+    In { boolean: &'static str, column: String, count: usize, not: bool },
+    Null { boolean: &'static str, column: String, not: bool },
+    Between { boolean: &'static str, column: String },
+    Exists { boolean: &'static str, query: Box<Builder<()>> },
+    Group { boolean: &'static str, wheres: Vec<WhereClause> },
+}
+
+impl WhereClause {
+    fn boolean(&self) -> &'static str {
+        match self {
+            WhereClause::Basic { boolean, .. }
+            | WhereClause::Raw { boolean, .. }
+            | WhereClause::In { boolean, .. }
+            | WhereClause::Null { boolean, .. }
+            | WhereClause::Between { boolean, .. }
+            | WhereClause::Exists { boolean, .. }
+            | WhereClause::Group { boolean, .. } => boolean,
+        }
+    }
+
+    fn compile(&self, grammar: &Grammar) -> String {
+        match self {
+            WhereClause::Basic { column, operator, .. } => format!("{} {} ?", grammar.wrap(column), operator),
+            WhereClause::Raw { sql, .. } => sql.clone(),
+            WhereClause::In { column, count, not, .. } => {
+                let placeholders = vec!["?"; *count].join(", ");
+                let keyword = if *not { "NOT IN" } else { "IN" };
+                format!("{} {} ({})", grammar.wrap(column), keyword, placeholders)
+            }
+            WhereClause::Null { column, not, .. } => {
+                let keyword = if *not { "IS NOT NULL" } else { "IS NULL" };
+                format!("{} {}", grammar.wrap(column), keyword)
+            }
+            WhereClause::Between { column, .. } => format!("{} BETWEEN ? AND ?", grammar.wrap(column)),
+            WhereClause::Exists { query, .. } => format!("EXISTS ({})", query.compile_select(grammar)),
+            WhereClause::Group { wheres, .. } => format!("({})", Builder::<()>::compile_wheres(wheres, grammar)),
+        }
+    }
+}
+
+/// The conditions a global scope added, kept apart so the scope can be removed again
+struct ScopeClause {
+    name: String,
+    wheres: Vec<WhereClause>,
+    bindings: Vec<ArgumentApplier>,
+}
+
+struct JoinClause {
+    kind: &'static str,
+    table: String,
+    first: String,
+    operator: String,
+    second: String,
+}
 
 /// The Query Builder
 pub struct Builder<T> {
     table: String,
+    grammar: Grammar,
     select: Vec<String>,
-    joins: Vec<String>,
-    wheres: Vec<String>,
-    order: Vec<String>,
+    joins: Vec<JoinClause>,
+    scopes: Vec<ScopeClause>,
+    wheres: Vec<WhereClause>,
+    groups: Vec<String>,
+    havings: Vec<WhereClause>,
+    order: Vec<(String, String)>,
     limit: Option<i64>,
     offset: Option<i64>,
-    argument_appliers: Vec<Box<dyn Fn(&mut DbArguments) + Send + Sync>>,
+    argument_appliers: Vec<ArgumentApplier>,
+    having_appliers: Vec<ArgumentApplier>,
+    eager: Vec<String>,
     _marker: PhantomData<T>,
 }
 
+impl<T> Builder<T> {
+    /// Compile the query into the grammar's SQL (placeholders included)
+    pub fn to_sql(&self) -> String {
+        self.grammar.parameterize(&self.compile_select(&self.grammar))
+    }
+
+    /// Compile with `?` placeholders so the result can still be embedded in another query
+    fn compile_select(&self, grammar: &Grammar) -> String {
+        self.compile_select_columns(grammar, &self.select)
+    }
+
+    fn compile_select_columns(&self, grammar: &Grammar, columns: &[String]) -> String {
+        let columns: Vec<String> = columns.iter().map(|c| grammar.wrap(c)).collect();
+        let mut sql = format!("SELECT {} FROM {}", columns.join(", "), grammar.wrap_table(&self.table));
+        sql.push_str(&self.compile_body(grammar));
+
+        if !self.order.is_empty() {
+            let order: Vec<String> = self
+                .order
+                .iter()
+                .map(|(column, direction)| format!("{} {}", grammar.wrap(column), direction))
+                .collect();
+            sql.push_str(" ORDER BY ");
+            sql.push_str(&order.join(", "));
+        }
+
+        if let Some(limit) = self.limit {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+
+        if let Some(offset) = self.offset {
+            sql.push_str(&format!(" OFFSET {}", offset));
+        }
+
+        sql
+    }
+
+    /// JOIN, WHERE, GROUP BY and HAVING, shared by selects and aggregates
+    fn compile_body(&self, grammar: &Grammar) -> String {
+        let mut sql = String::new();
+
+        for join in &self.joins {
+            sql.push_str(&format!(
+                " {} {} ON {} {} {}",
+                join.kind,
+                grammar.wrap_table(&join.table),
+                grammar.wrap(&join.first),
+                join.operator,
+                grammar.wrap(&join.second)
+            ));
+        }
+
+        let wheres = self.compile_where_clause(grammar);
+        if !wheres.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&wheres);
+        }
+
+        if !self.groups.is_empty() {
+            let groups: Vec<String> = self.groups.iter().map(|c| grammar.wrap(c)).collect();
+            sql.push_str(" GROUP BY ");
+            sql.push_str(&groups.join(", "));
+        }
+
+        if !self.havings.is_empty() {
+            sql.push_str(" HAVING ");
+            sql.push_str(&Self::compile_wheres(&self.havings, grammar));
+        }
+
+        sql
+    }
+
+    /// Compile `SELECT <expression> AS aggregate` over this query.
+    /// Grouped, limited or distinct queries are aggregated through a subquery.
+    fn compile_aggregate(&self, grammar: &Grammar, function: &str, column: &str) -> String {
+        let is_distinct = self.select.first().is_some_and(|c| c.starts_with("DISTINCT"));
+        let wrap_subquery = !self.groups.is_empty() || self.limit.is_some() || self.offset.is_some() || is_distinct;
+
+        let column = if column == "*" {
+            column.to_string()
+        } else if wrap_subquery {
+            // Outside the subquery only the bare column name is visible
+            grammar.wrap(column.rsplit('.').next().unwrap_or(column))
+        } else {
+            grammar.wrap(column)
+        };
+
+        let expression = match function {
+            // SUM and AVG come back as DECIMAL/NUMERIC on MySQL and Postgres
+            "SUM" => grammar.cast_to_float(&format!("COALESCE(SUM({}), 0)", column)),
+            "AVG" => grammar.cast_to_float(&format!("AVG({})", column)),
+            _ => format!("{}({})", function, column),
+        };
+
+        if wrap_subquery {
+            format!(
+                "SELECT {} AS aggregate FROM ({}) AS aggregate_table",
+                expression,
+                self.compile_select(grammar)
+            )
+        } else {
+            format!(
+                "SELECT {} AS aggregate FROM {}{}",
+                expression,
+                grammar.wrap_table(&self.table),
+                self.compile_body(grammar)
+            )
+        }
+    }
+
+    /// UPDATE table SET <sets> WHERE ..., where each set is already rendered (`"a" = ?`)
+    fn compile_update(&self, grammar: &Grammar, sets: &[String], key: &str) -> String {
+        format!(
+            "UPDATE {} SET {}{}",
+            grammar.wrap_table(&self.table),
+            sets.join(", "),
+            self.compile_write_filter(grammar, key)
+        )
+    }
+
+    fn compile_delete(&self, grammar: &Grammar, key: &str) -> String {
+        format!("DELETE FROM {}{}", grammar.wrap_table(&self.table), self.compile_write_filter(grammar, key))
+    }
+
+    /// The WHERE clause of an UPDATE or DELETE.
+    /// Joined, grouped or limited queries pick the affected keys through a derived
+    /// table, which every backend (MySQL included) lets us read while writing.
+    fn compile_write_filter(&self, grammar: &Grammar, key: &str) -> String {
+        let plain = self.joins.is_empty() && self.groups.is_empty() && self.limit.is_none() && self.offset.is_none();
+
+        if plain {
+            let wheres = self.compile_where_clause(grammar);
+            return match wheres.is_empty() {
+                true => String::new(),
+                false => format!(" WHERE {}", wheres),
+            };
+        }
+
+        let keys = self.compile_select_columns(grammar, &[format!("{}.{}", self.table, key)]);
+        format!(
+            " WHERE {} IN (SELECT {} FROM ({}) AS orbit_keys)",
+            grammar.wrap(key),
+            grammar.wrap(key),
+            keys
+        )
+    }
+
+    /// Global scope conditions ANDed with the query's own, e.g.
+    /// `"tenant_id" = ? AND ("a" = ? OR "b" = ?)`
+    fn compile_where_clause(&self, grammar: &Grammar) -> String {
+        let mut parts: Vec<String> = self
+            .scopes
+            .iter()
+            .map(|scope| match scope.wheres.len() {
+                1 => Self::compile_wheres(&scope.wheres, grammar),
+                _ => format!("({})", Self::compile_wheres(&scope.wheres, grammar)),
+            })
+            .collect();
+
+        if !self.wheres.is_empty() {
+            let wheres = Self::compile_wheres(&self.wheres, grammar);
+            let has_or = self.wheres.iter().any(|w| w.boolean() == "OR");
+            parts.push(if has_or && !parts.is_empty() { format!("({})", wheres) } else { wheres });
+        }
+
+        parts.join(" AND ")
+    }
+
+    fn compile_wheres(wheres: &[WhereClause], grammar: &Grammar) -> String {
+        let mut sql = String::new();
+        for (i, clause) in wheres.iter().enumerate() {
+            if i > 0 {
+                sql.push_str(&format!(" {} ", clause.boolean()));
+            }
+            sql.push_str(&clause.compile(grammar));
+        }
+        sql
+    }
+
+    fn build_arguments(&self) -> DbArguments {
+        let mut args = DbArguments::default();
+        self.apply_bindings(&mut args);
+        args
+    }
+
+    /// How many values the query binds
+    pub(crate) fn binding_count(&self) -> usize {
+        self.scopes.iter().map(|scope| scope.bindings.len()).sum::<usize>()
+            + self.argument_appliers.len()
+            + self.having_appliers.len()
+    }
+
+    /// Append the query's bindings after any already on `args` (e.g. the SET values of an UPDATE)
+    fn apply_bindings(&self, args: &mut DbArguments) {
+        // Scope bindings come first, and WHERE bindings always precede HAVING bindings in the SQL
+        let scoped = self.scopes.iter().flat_map(|scope| &scope.bindings);
+        for applier in scoped.chain(&self.argument_appliers).chain(&self.having_appliers) {
+            applier(args);
+        }
+    }
+
+    /// Take every binding in the order it appears in the compiled SQL
+    fn take_bindings(&mut self) -> Vec<ArgumentApplier> {
+        let mut bindings: Vec<ArgumentApplier> = self.scopes.iter_mut().flat_map(|scope| std::mem::take(&mut scope.bindings)).collect();
+        bindings.append(&mut self.argument_appliers);
+        bindings.append(&mut self.having_appliers);
+        bindings
+    }
+
+    /// Drop the model type so the query can be nested inside another builder
+    fn erase(self) -> Builder<()> {
+        Builder {
+            table: self.table,
+            grammar: self.grammar,
+            select: self.select,
+            joins: self.joins,
+            scopes: self.scopes,
+            wheres: self.wheres,
+            groups: self.groups,
+            havings: self.havings,
+            order: self.order,
+            limit: self.limit,
+            offset: self.offset,
+            argument_appliers: self.argument_appliers,
+            having_appliers: self.having_appliers,
+            eager: self.eager,
+            _marker: PhantomData,
+        }
+    }
+}
+
 impl<T> Builder<T>
 where T: Orbit + Send + Unpin + for<'r> FromRow<'r, DbRow>
 {
     pub fn new(table: &str) -> Self {
         Self {
             table: table.to_string(),
+            grammar: Grammar::current(),
             select: vec!["*".to_string()],
             joins: Vec::new(),
+            scopes: Vec::new(),
             wheres: Vec::new(),
+            groups: Vec::new(),
+            havings: Vec::new(),
             order: Vec::new(),
             limit: None,
             offset: None,
             argument_appliers: Vec::new(),
+            having_appliers: Vec::new(),
+            eager: Vec::new(),
             _marker: PhantomData,
         }
     }
 
+    /// Compile this query with a different grammar than the compiled backend's
+    pub fn with_grammar(mut self, grammar: Grammar) -> Self {
+        self.grammar = grammar;
+        self
+    }
+
     pub fn select(mut self, columns: &[&str]) -> Self {
         self.select = columns.iter().map(|s| s.to_string()).collect();
         self
@@ -41,28 +375,26 @@ where T: Orbit + Send + Unpin + for<'r> FromRow<'r, DbRow>
     /// Add a raw where clause (e.g. "id = ?")
     /// You must provide the value in the execute phase or use `where_eq`
     pub fn where_raw(mut self, clause: &str) -> Self {
-        self.wheres.push(clause.to_string());
+        self.wheres.push(WhereClause::Raw { boolean: "AND", sql: clause.to_string() });
         self
     }
 
-    pub fn r#where<V>(mut self, col: &str, op: &str, val: V) -> Self
-    where V: 'static + Encode<'static, Db> + Type<Db> + Send + Sync + Clone
-    {
-        self.wheres.push(format!("{} {} ?", col, op));
-        self.argument_appliers.push(Box::new(move |args| {
-            args.add(val.clone());
-        }));
+    pub fn r#where<V: Bindable>(mut self, col: &str, op: &str, val: V) -> Self {
+        self.wheres.push(WhereClause::Basic {
+            boolean: "AND",
+            column: col.to_string(),
+            operator: op.to_string(),
+        });
+        self.argument_appliers.push(binder(val));
         self
     }
 
-    pub fn where_eq<V>(mut self, col: &str, val: V) -> Self
-    where V: 'static + Encode<'static, Db> + Type<Db> + Send + Sync + Clone
-    {
+    pub fn where_eq<V: Bindable>(self, col: &str, val: V) -> Self {
         self.r#where(col, "=", val)
     }
 
     pub fn order_by(mut self, column: &str, direction: &str) -> Self {
-        self.order.push(format!("{} {}", column, direction));
+        self.order.push((column.to_string(), direction.to_string()));
         self
     }
 
@@ -79,13 +411,25 @@ where T: Orbit + Send + Unpin + for<'r> FromRow<'r, DbRow>
     /// Add a JOIN clause
     /// join("posts", "users.id", "=", "posts.user_id")
     pub fn join(mut self, table: &str, first: &str, op: &str, second: &str) -> Self {
-        self.joins.push(format!("INNER JOIN {} ON {} {} {}", table, first, op, second));
+        self.joins.push(JoinClause {
+            kind: "INNER JOIN",
+            table: table.to_string(),
+            first: first.to_string(),
+            operator: op.to_string(),
+            second: second.to_string(),
+        });
         self
     }
 
     /// Add a LEFT JOIN clause
     pub fn left_join(mut self, table: &str, first: &str, op: &str, second: &str) -> Self {
-        self.joins.push(format!("LEFT JOIN {} ON {} {} {}", table, first, op, second));
+        self.joins.push(JoinClause {
+            kind: "LEFT JOIN",
+            table: table.to_string(),
+            first: first.to_string(),
+            operator: op.to_string(),
+            second: second.to_string(),
+        });
         self
     }
 
@@ -94,9 +438,74 @@ where T: Orbit + Send + Unpin + for<'r> FromRow<'r, DbRow>
     pub fn where_exists<R>(mut self, subquery: Builder<R>) -> Self
     where R: Orbit + Send + Unpin + for<'r> FromRow<'r, DbRow>
     {
-        let sql = subquery.to_sql();
-        self.wheres.push(format!("EXISTS ({})", sql));
-        self.argument_appliers.extend(subquery.argument_appliers);
+        let mut subquery = subquery.erase();
+        let appliers = subquery.take_bindings();
+        self.wheres.push(WhereClause::Exists { boolean: "AND", query: Box::new(subquery) });
+        self.argument_appliers.extend(appliers);
+        self
+    }
+
+    /// Apply a local scope
+    /// Post::query().scope(Published).scope(|q: Builder<Post>| q.latest("id"))
+    pub fn scope<S: Scope<T>>(self, scope: S) -> Self {
+        scope.apply(self)
+    }
+
+    /// Apply a named global scope. Only the WHERE conditions it adds are kept;
+    /// they are ANDed with the rest of the query and can be removed by name.
+    /// `Orbit::query()` calls this for each of `Orbit::global_scopes`.
+    pub fn with_global_scope<S: Scope<T>>(mut self, name: &str, scope: S) -> Self {
+        let applied = scope.apply(Builder::new(&self.table).with_grammar(self.grammar));
+        if !applied.wheres.is_empty() {
+            self.scopes.push(ScopeClause {
+                name: name.to_string(),
+                wheres: applied.wheres,
+                bindings: applied.argument_appliers,
+            });
+        }
+        self
+    }
+
+    /// Remove a global scope from this query
+    /// Post::query().without_global_scope("tenant")
+    pub fn without_global_scope(mut self, name: &str) -> Self {
+        self.scopes.retain(|scope| scope.name != name);
+        self
+    }
+
+    /// Remove every global scope from this query
+    pub fn without_global_scopes(mut self) -> Self {
+        self.scopes.clear();
+        self
+    }
+
+    /// Add a parenthesized group of conditions joined with AND
+    /// where_group(|q| q.where_eq("role", "admin").or_where("karma", ">", 100))
+    /// => AND (role = ? OR karma > ?)
+    pub fn where_group<F>(self, callback: F) -> Self
+    where F: FnOnce(Builder<T>) -> Builder<T>
+    {
+        self.add_group("AND", callback)
+    }
+
+    /// Add a parenthesized group of conditions joined with OR
+    pub fn or_where_group<F>(self, callback: F) -> Self
+    where F: FnOnce(Builder<T>) -> Builder<T>
+    {
+        self.add_group("OR", callback)
+    }
+
+    fn add_group<F>(mut self, boolean: &'static str, callback: F) -> Self
+    where F: FnOnce(Builder<T>) -> Builder<T>
+    {
+        let nested = callback(Builder::new(&self.table).with_grammar(self.grammar));
+        if nested.wheres.is_empty() {
+            return self;
+        }
+
+        // The group's bindings sit exactly where the group is rendered
+        self.wheres.push(WhereClause::Group { boolean, wheres: nested.wheres });
+        self.argument_appliers.extend(nested.argument_appliers);
         self
     }
 
@@ -113,83 +522,287 @@ where T: Orbit + Send + Unpin + for<'r> FromRow<'r, DbRow>
         crate::dd!(sql);
     }
 
-    pub fn to_sql(&self) -> String {
-        let mut sql = format!("SELECT {} FROM {}", self.select.join(", "), self.table);
-
-        if !self.joins.is_empty() {
-            sql.push_str(" ");
-            sql.push_str(&self.joins.join(" "));
-        }
+    pub async fn get(mut self, db: &impl ConnectionResolver) -> Result<Vec<T>, sqlx::Error> {
+        self.ensure_nothing_to_load("get")?;
+        let conn = self.reader(db)?;
+        let sql = self.to_sql();
+        let args = self.build_arguments();
+        conn.fetch_all(sqlx::query_as_with(&sql, args)).await
+    }
 
-        if !self.wheres.is_empty() {
-            sql.push_str(" WHERE ");
-            sql.push_str(&self.wheres.join(" AND "));
-        }
+    pub async fn first(mut self, db: &impl ConnectionResolver) -> Result<Option<T>, sqlx::Error> {
+        self.ensure_nothing_to_load("first")?;
+        let conn = self.reader(db)?;
+        self.limit = Some(1);
+        let sql = self.to_sql();
+        let args = self.build_arguments();
+        conn.fetch_optional(sqlx::query_as_with(&sql, args)).await
+    }
 
-        if !self.order.is_empty() {
-            sql.push_str(" ORDER BY ");
-            sql.push_str(&self.order.join(", "));
-        }
+    /// Eager load a relationship declared in `Orbit::relation`.
+    /// Nested relations use dots: with("posts.comments")
+    /// Results are returned by `get_loaded`, `first_loaded`, `paginate_loaded` and
+    /// `cursor_paginate_loaded`; the other fetching methods return an error instead
+    /// of dropping the relations.
+    pub fn with(mut self, relation: &str) -> Self {
+        self.eager.push(relation.to_string());
+        self
+    }
 
-        if let Some(limit) = self.limit {
-            sql.push_str(&format!(" LIMIT {}", limit));
-        }
+    /// Run the query and eager load the relations requested with `with`
+    pub async fn get_loaded(mut self, db: &impl ConnectionResolver) -> Result<Vec<Loaded<T>>, sqlx::Error> {
+        let eager = std::mem::take(&mut self.eager);
+        let mut models: Vec<Loaded<T>> = self.get(db).await?.into_iter().map(Loaded::new).collect();
+        eager_load(db, &mut models, &eager).await?;
+        Ok(models)
+    }
 
-        if let Some(offset) = self.offset {
-            sql.push_str(&format!(" OFFSET {}", offset));
-        }
+    /// Fetch the first result with the relations requested with `with`
+    pub async fn first_loaded(mut self, db: &impl ConnectionResolver) -> Result<Option<Loaded<T>>, sqlx::Error> {
+        self.limit = Some(1);
+        Ok(self.get_loaded(db).await?.into_iter().next())
+    }
 
-        sql
+    /// `paginate` with the relations requested with `with` loaded for the page
+    pub async fn paginate_loaded(mut self, db: &impl ConnectionResolver, page: i64, per_page: i64) -> Result<Paginator<Loaded<T>>, sqlx::Error> {
+        let eager = std::mem::take(&mut self.eager);
+        let mut page = self.paginate(db, page, per_page).await?.map(Loaded::new);
+        eager_load(db, &mut page.data, &eager).await?;
+        Ok(page)
     }
 
-    fn build_arguments(&self) -> DbArguments {
-        let mut args = DbArguments::default();
-        for applier in &self.argument_appliers {
-            applier(&mut args);
-        }
-        args
+    /// `cursor_paginate` with the relations requested with `with` loaded for the page
+    pub async fn cursor_paginate_loaded<V>(mut self, db: &impl ConnectionResolver, column: &str, after: Option<V>, limit: i64) -> Result<Paginator<Loaded<T>>, sqlx::Error>
+    where V: Bindable + serde::Serialize + for<'r> Decode<'r, Db>
+    {
+        let eager = std::mem::take(&mut self.eager);
+        let mut page = self.cursor_paginate(db, column, after, limit).await?.map(Loaded::new);
+        eager_load(db, &mut page.data, &eager).await?;
+        Ok(page)
     }
 
-    pub async fn get(self, manager: &DatabaseManager) -> Result<Vec<T>, sqlx::Error> {
-        let pool = manager.connection(T::connection()).ok_or(sqlx::Error::Configuration("No database connection found".into()))?;
-        let sql = self.to_sql();
-        let args = self.build_arguments();
-        sqlx::query_as_with(&sql, args)
-            .fetch_all(pool)
-            .await
+    /// Fail a fetch that returns bare models when `with` asked for relations,
+    /// rather than silently leaving them unloaded
+    fn ensure_nothing_to_load(&self, method: &str) -> Result<(), sqlx::Error> {
+        if self.eager.is_empty() {
+            return Ok(());
+        }
+        Err(sqlx::Error::Configuration(
+            format!("`{}` doesn't load relations; use the *_loaded variant for with({:?})", method, self.eager.join(", ")).into(),
+        ))
     }
 
-    pub async fn first(mut self, manager: &DatabaseManager) -> Result<Option<T>, sqlx::Error> {
-        let pool = manager.connection(T::connection()).ok_or(sqlx::Error::Configuration("No database connection found".into()))?;
-        self.limit = Some(1);
+    /// Fetch the raw rows, for callers that need columns beyond the model's fields
+    pub(crate) async fn fetch_rows(mut self, db: &impl ConnectionResolver) -> Result<Vec<DbRow>, sqlx::Error> {
+        let conn = self.reader(db)?;
         let sql = self.to_sql();
         let args = self.build_arguments();
-        sqlx::query_as_with(&sql, args)
-            .fetch_optional(pool)
-            .await
+        conn.fetch_rows(sqlx::query_with(&sql, args)).await
     }
 
     /// Paginate results (Laravel-style)
-    /// Usage: query.paginate(manager, page, per_page).await
-    pub async fn paginate(mut self, manager: &DatabaseManager, page: i64, per_page: i64) -> Result<(Vec<T>, i64), sqlx::Error> {
-        let pool = manager.connection(T::connection()).ok_or(sqlx::Error::Configuration("No database connection found".into()))?;
+    /// The total is counted with the same joins, wheres and groups as the page.
+    /// Usage: query.paginate(db, page, per_page).await
+    pub async fn paginate(mut self, db: &impl ConnectionResolver, page: i64, per_page: i64) -> Result<Paginator<T>, sqlx::Error> {
+        self.ensure_nothing_to_load("paginate")?;
+        let conn = self.reader(db)?;
+        let page = page.max(1);
+        let per_page = per_page.max(1);
 
         // Get total count
-        let count_sql = format!("SELECT COUNT(*) as count FROM {}", self.table);
-        let count_result: (i64,) = sqlx::query_as(&count_sql).fetch_one(pool).await?;
-        let total = count_result.0;
+        self.limit = None;
+        self.offset = None;
+        let count_sql = self.grammar.parameterize(&self.compile_aggregate(&self.grammar, "COUNT", "*"));
+        let total: i64 = conn.fetch_scalar(sqlx::query_scalar_with(&count_sql, self.build_arguments())).await?;
 
         // Apply pagination
-        self.offset = Some((page - 1) * per_page);
+        // Both come from the query string; don't let huge values overflow
+        self.offset = Some((page - 1).saturating_mul(per_page));
         self.limit = Some(per_page);
 
         let sql = self.to_sql();
         let args = self.build_arguments();
-        let items = sqlx::query_as_with(&sql, args)
-            .fetch_all(pool)
-            .await?;
+        let items = conn.fetch_all(sqlx::query_as_with(&sql, args)).await?;
 
-        Ok((items, total))
+        Ok(Paginator::new(items, total, per_page, page))
+    }
+
+    /// Keyset pagination: seek past `after` instead of counting an OFFSET,
+    /// so every page costs the same on large tables. `column` must be unique
+    /// (usually the primary key); a DESC `order_by` on it pages backwards.
+    /// let page = Post::query().cursor_paginate(&db, "id", decode_cursor::<i64>(&cursor), 20).await?;
+    pub async fn cursor_paginate<V>(mut self, db: &impl ConnectionResolver, column: &str, after: Option<V>, limit: i64) -> Result<Paginator<T>, sqlx::Error>
+    where V: Bindable + serde::Serialize + for<'r> Decode<'r, Db>
+    {
+        self.ensure_nothing_to_load("cursor_paginate")?;
+        let conn = self.reader(db)?;
+        let limit = limit.max(1);
+        let descending = self
+            .order
+            .iter()
+            .any(|(c, direction)| c == column && direction.eq_ignore_ascii_case("DESC"));
+
+        if let Some(after) = after {
+            self.isolate_wheres();
+            self = self.r#where(column, if descending { "<" } else { ">" }, after);
+        }
+
+        self.order = vec![(column.to_string(), if descending { "DESC" } else { "ASC" }.to_string())];
+        self.offset = None;
+        // One extra row tells us whether there is a next page
+        self.limit = Some(limit.saturating_add(1));
+
+        let sql = self.to_sql();
+        let args = self.build_arguments();
+        let mut rows = conn.fetch_rows(sqlx::query_with(&sql, args)).await?;
+
+        let has_more = rows.len() as i64 > limit;
+        rows.truncate(limit as usize);
+
+        let next_cursor = match rows.last() {
+            Some(row) if has_more => {
+                let key = column.rsplit('.').next().unwrap_or(column);
+                Some(encode_cursor(&row.try_get::<V, _>(key)?))
+            }
+            _ => None,
+        };
+
+        let items = rows.iter().map(T::from_row).collect::<Result<Vec<T>, _>>()?;
+        Ok(Paginator::cursor(items, limit, next_cursor))
+    }
+
+    /// Process the results in batches of `size` rows, one query per batch.
+    /// Without an explicit order the rows are ordered by primary key so batches don't overlap.
+    /// Prefer `chunk_by_id` when the callback changes the columns being filtered on.
+    ///
+    /// User::query().chunk(&db, 500, |users| async move {
+    ///     for user in users { send_newsletter(&user).await?; }
+    ///     Ok::<_, sqlx::Error>(())
+    /// }).await?;
+    pub async fn chunk<F, Fut, E>(mut self, db: &impl ConnectionResolver, size: i64, mut callback: F) -> Result<(), E>
+    where
+        F: FnMut(Vec<T>) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: From<sqlx::Error>,
+    {
+        self.ensure_nothing_to_load("chunk")?;
+        let conn = self.reader(db)?;
+        let size = size.max(1);
+        if self.order.is_empty() {
+            self.order.push((T::primary_key().to_string(), "ASC".to_string()));
+        }
+
+        let mut page = 0;
+        loop {
+            self.limit = Some(size);
+            self.offset = Some(page * size);
+            let sql = self.to_sql();
+            let batch: Vec<T> = conn.fetch_all(sqlx::query_as_with(&sql, self.build_arguments())).await?;
+
+            let count = batch.len() as i64;
+            if count == 0 {
+                break;
+            }
+            callback(batch).await?;
+            if count < size {
+                break;
+            }
+            page += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Like `chunk`, but seeks past the last primary key instead of using OFFSET:
+    /// each batch costs the same, and updating or deleting rows inside the callback
+    /// cannot make the next batch skip any.
+    pub async fn chunk_by_id<F, Fut, E>(mut self, db: &impl ConnectionResolver, size: i64, mut callback: F) -> Result<(), E>
+    where
+        F: FnMut(Vec<T>) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: From<sqlx::Error>,
+    {
+        self.ensure_nothing_to_load("chunk_by_id")?;
+        let conn = self.reader(db)?;
+        let size = size.max(1);
+        let last_id = Arc::new(AtomicI64::new(i64::MIN));
+
+        self.isolate_wheres();
+        self.wheres.push(WhereClause::Basic {
+            boolean: "AND",
+            column: T::primary_key().to_string(),
+            operator: ">".to_string(),
+        });
+        let seek = last_id.clone();
+        self.argument_appliers.push(Box::new(move |args: &mut DbArguments| {
+            args.add(seek.load(Ordering::SeqCst));
+        }));
+        self.order = vec![(T::primary_key().to_string(), "ASC".to_string())];
+        self.limit = Some(size);
+        self.offset = None;
+
+        let sql = self.to_sql();
+        loop {
+            let batch: Vec<T> = conn.fetch_all(sqlx::query_as_with(&sql, self.build_arguments())).await?;
+
+            let count = batch.len() as i64;
+            match batch.last() {
+                Some(last) => last_id.store(last.id(), Ordering::SeqCst),
+                None => break,
+            }
+            callback(batch).await?;
+            if count < size {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stream the results one row at a time as the database sends them,
+    /// so memory stays flat however many rows match.
+    /// A bounded buffer applies back-pressure; dropping the stream stops the query.
+    /// Inside a transaction the rows are buffered first, so the transaction stays
+    /// free for other queries while the stream is consumed.
+    ///
+    /// let mut users = User::query().stream(&db);
+    /// while let Some(user) = users.next().await { export(&user?)?; }
+    pub fn stream(mut self, db: &impl ConnectionResolver) -> BoxStream<'static, Result<T, sqlx::Error>>
+    where T: 'static
+    {
+        let conn = match self.ensure_nothing_to_load("stream").and_then(|_| self.reader(db)) {
+            Ok(conn) => conn,
+            Err(e) => return futures::stream::once(async move { Err(e) }).boxed(),
+        };
+
+        let (sink, rows) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            let sql = self.to_sql();
+            conn.pipe(sqlx::query_as_with(&sql, self.build_arguments()), sink).await;
+        });
+        rows.boxed()
+    }
+
+    /// The connection to read from; the query compiles for its grammar from here on
+    fn reader(&mut self, db: &impl ConnectionResolver) -> Result<Connection, sqlx::Error> {
+        let conn = db.resolve_read(T::connection())?;
+        self.grammar = conn.grammar();
+        Ok(conn)
+    }
+
+    /// The connection to write to; the query compiles for its grammar from here on
+    fn writer(&mut self, db: &impl ConnectionResolver) -> Result<Connection, sqlx::Error> {
+        let conn = db.resolve(T::connection())?;
+        self.grammar = conn.grammar();
+        Ok(conn)
+    }
+
+    /// Wrap the current conditions in parentheses so an appended AND applies to all of them
+    fn isolate_wheres(&mut self) {
+        let existing = std::mem::take(&mut self.wheres);
+        if !existing.is_empty() {
+            self.wheres.push(WhereClause::Group { boolean: "AND", wheres: existing });
+        }
     }
 
     /// Get distinct results
@@ -203,106 +816,489 @@ where T: Orbit + Send + Unpin + for<'r> FromRow<'r, DbRow>
     }
 
     /// Add OR WHERE clause
-    pub fn or_where<V>(mut self, col: &str, op: &str, val: V) -> Self
-    where V: 'static + Encode<'static, Db> + Type<Db> + Send + Sync + Clone
-    {
-        if !self.wheres.is_empty() {
-            // Replace the last AND with OR by modifying how we construct the clause
-            let last_where = self.wheres.pop().unwrap();
-            self.wheres.push(format!("{} OR {} {} ?", last_where, col, op));
-        } else {
-            self.wheres.push(format!("{} {} ?", col, op));
-        }
-        self.argument_appliers.push(Box::new(move |args| {
-            args.add(val.clone());
-        }));
+    pub fn or_where<V: Bindable>(mut self, col: &str, op: &str, val: V) -> Self {
+        self.wheres.push(WhereClause::Basic {
+            boolean: "OR",
+            column: col.to_string(),
+            operator: op.to_string(),
+        });
+        self.argument_appliers.push(binder(val));
         self
     }
 
     /// Where IN clause
-    pub fn where_in<V>(mut self, col: &str, values: Vec<V>) -> Self
-    where V: 'static + Encode<'static, Db> + Type<Db> + Send + Sync + Clone
-    {
-        let placeholders = vec!["?"; values.len()].join(", ");
-        self.wheres.push(format!("{} IN ({})", col, placeholders));
-
-        for val in values {
-            self.argument_appliers.push(Box::new(move |args| {
-                args.add(val.clone());
-            }));
-        }
+    pub fn where_in<V: Bindable>(mut self, col: &str, values: Vec<V>) -> Self {
+        self.wheres.push(WhereClause::In {
+            boolean: "AND",
+            column: col.to_string(),
+            count: values.len(),
+            not: false,
+        });
+        self.argument_appliers.extend(values.into_iter().map(binder));
         self
     }
 
     /// Where NOT IN clause
-    pub fn where_not_in<V>(mut self, col: &str, values: Vec<V>) -> Self
-    where V: 'static + Encode<'static, Db> + Type<Db> + Send + Sync + Clone
-    {
-        let placeholders = vec!["?"; values.len()].join(", ");
-        self.wheres.push(format!("{} NOT IN ({})", col, placeholders));
-
-        for val in values {
-            self.argument_appliers.push(Box::new(move |args| {
-                args.add(val.clone());
-            }));
-        }
+    pub fn where_not_in<V: Bindable>(mut self, col: &str, values: Vec<V>) -> Self {
+        self.wheres.push(WhereClause::In {
+            boolean: "AND",
+            column: col.to_string(),
+            count: values.len(),
+            not: true,
+        });
+        self.argument_appliers.extend(values.into_iter().map(binder));
         self
     }
 
     /// Where NULL clause
     pub fn where_null(mut self, col: &str) -> Self {
-        self.wheres.push(format!("{} IS NULL", col));
+        self.wheres.push(WhereClause::Null { boolean: "AND", column: col.to_string(), not: false });
         self
     }
 
     /// Where NOT NULL clause
     pub fn where_not_null(mut self, col: &str) -> Self {
-        self.wheres.push(format!("{} IS NOT NULL", col));
+        self.wheres.push(WhereClause::Null { boolean: "AND", column: col.to_string(), not: true });
         self
     }
 
     /// Where BETWEEN clause
-    pub fn where_between<V>(mut self, col: &str, min: V, max: V) -> Self
-    where V: 'static + Encode<'static, Db> + Type<Db> + Send + Sync + Clone
-    {
-        self.wheres.push(format!("{} BETWEEN ? AND ?", col));
-        self.argument_appliers.push(Box::new(move |args| {
-            args.add(min.clone());
-        }));
-        self.argument_appliers.push(Box::new(move |args| {
-            args.add(max.clone());
-        }));
+    pub fn where_between<V: Bindable>(mut self, col: &str, min: V, max: V) -> Self {
+        self.wheres.push(WhereClause::Between { boolean: "AND", column: col.to_string() });
+        self.argument_appliers.push(binder(min));
+        self.argument_appliers.push(binder(max));
         self
     }
 
     /// Add DESC sorting (shortcut)
-    pub fn latest(mut self, column: &str) -> Self {
-        self.order.push(format!("{} DESC", column));
-        self
+    pub fn latest(self, column: &str) -> Self {
+        self.order_by(column, "DESC")
     }
 
     /// Add ASC sorting (shortcut)
-    pub fn oldest(mut self, column: &str) -> Self {
-        self.order.push(format!("{} ASC", column));
-        self
+    pub fn oldest(self, column: &str) -> Self {
+        self.order_by(column, "ASC")
     }
 
     /// Group by clause
     pub fn group_by(mut self, columns: &[&str]) -> Self {
-        for col in columns {
-            self.wheres.push(format!("GROUP BY {}", col));
-        }
+        self.groups.extend(columns.iter().map(|c| c.to_string()));
         self
     }
 
     /// Add HAVING clause (for GROUP BY)
-    pub fn having<V>(mut self, col: &str, op: &str, val: V) -> Self
-    where V: 'static + Encode<'static, Db> + Type<Db> + Send + Sync + Clone
+    /// having("COUNT(*)", ">", 5) or having("total", ">=", 100)
+    pub fn having<V: Bindable>(mut self, col: &str, op: &str, val: V) -> Self {
+        self.havings.push(WhereClause::Basic {
+            boolean: "AND",
+            column: col.to_string(),
+            operator: op.to_string(),
+        });
+        self.having_appliers.push(binder(val));
+        self
+    }
+
+    /// Add OR HAVING clause
+    pub fn or_having<V: Bindable>(mut self, col: &str, op: &str, val: V) -> Self {
+        self.havings.push(WhereClause::Basic {
+            boolean: "OR",
+            column: col.to_string(),
+            operator: op.to_string(),
+        });
+        self.having_appliers.push(binder(val));
+        self
+    }
+
+    /// Add a raw HAVING clause (e.g. "SUM(amount) > 100")
+    pub fn having_raw(mut self, clause: &str) -> Self {
+        self.havings.push(WhereClause::Raw { boolean: "AND", sql: clause.to_string() });
+        self
+    }
+
+    // Aggregates
+
+    async fn aggregate<V>(mut self, db: &impl ConnectionResolver, function: &str, column: &str) -> Result<V, sqlx::Error>
+    where V: for<'r> Decode<'r, Db> + Type<Db> + Send + Unpin
     {
-        self.wheres.push(format!("HAVING {} {} ?", col, op));
-        self.argument_appliers.push(Box::new(move |args| {
-            args.add(val.clone());
-        }));
+        let conn = self.reader(db)?;
+        let sql = self.grammar.parameterize(&self.compile_aggregate(&self.grammar, function, column));
+        let args = self.build_arguments();
+        conn.fetch_scalar(sqlx::query_scalar_with(&sql, args)).await
+    }
+
+    /// Count the rows matching the query
+    pub async fn count(self, db: &impl ConnectionResolver) -> Result<i64, sqlx::Error> {
+        self.aggregate(db, "COUNT", "*").await
+    }
+
+    /// Sum a column (0 when no rows match)
+    pub async fn sum(self, db: &impl ConnectionResolver, column: &str) -> Result<f64, sqlx::Error> {
+        self.aggregate(db, "SUM", column).await
+    }
+
+    /// Average of a column (None when no rows match)
+    pub async fn avg(self, db: &impl ConnectionResolver, column: &str) -> Result<Option<f64>, sqlx::Error> {
+        self.aggregate(db, "AVG", column).await
+    }
+
+    /// Smallest value of a column, decoded as the column's own type
+    /// let oldest: Option<i64> = User::query().min(&db, "age").await?;
+    pub async fn min<V>(self, db: &impl ConnectionResolver, column: &str) -> Result<Option<V>, sqlx::Error>
+    where V: for<'r> Decode<'r, Db> + Type<Db> + Send + Unpin
+    {
+        self.aggregate(db, "MIN", column).await
+    }
+
+    /// Largest value of a column, decoded as the column's own type
+    pub async fn max<V>(self, db: &impl ConnectionResolver, column: &str) -> Result<Option<V>, sqlx::Error>
+    where V: for<'r> Decode<'r, Db> + Type<Db> + Send + Unpin
+    {
+        self.aggregate(db, "MAX", column).await
+    }
+
+    /// Whether any row matches the query
+    pub async fn exists(mut self, db: &impl ConnectionResolver) -> Result<bool, sqlx::Error> {
+        let conn = self.reader(db)?;
+        let query = self.exists_query();
+        let sql = query.to_sql();
+        let args = query.build_arguments();
+        let row = conn.fetch_optional_row(sqlx::query_with(&sql, args)).await?;
+        Ok(row.is_some())
+    }
+
+    /// SELECT 1 ... LIMIT 1
+    fn exists_query(mut self) -> Self {
+        self.select = vec!["1".to_string()];
+        self.order.clear();
+        self.limit = Some(1);
         self
     }
+
+    /// Whether no row matches the query
+    pub async fn doesnt_exist(self, db: &impl ConnectionResolver) -> Result<bool, sqlx::Error> {
+        Ok(!self.exists(db).await?)
+    }
+
+    // Bulk writes
+
+    /// Update every row matching the query, returning the number of affected rows
+    /// User::query().where_eq("active", false).update(&db, json!({ "status": "archived" })).await?
+    pub async fn update<D>(mut self, db: &impl ConnectionResolver, data: D) -> Result<u64, sqlx::Error>
+    where D: serde::Serialize
+    {
+        let conn = self.writer(db)?;
+        let mut attributes = fill::<T, D>(data)?;
+        let model_casts = T::casts();
+
+        let mut sets = Vec::new();
+        let mut bindings: Vec<ArgumentApplier> = Vec::new();
+
+        if T::TIMESTAMPS {
+            attributes.remove("updated_at");
+        }
+
+        for (k, v) in attributes {
+            let cast = cast_for(&model_casts, &k);
+            sets.push(format!("{} = ?", self.grammar.wrap(&k)));
+            bindings.push(Box::new(move |args: &mut DbArguments| bind_attribute(args, &v, cast)));
+        }
+
+        if T::TIMESTAMPS {
+            sets.push(format!("{} = ?", self.grammar.wrap("updated_at")));
+            bindings.push(binder(crate::database::now()));
+        }
+
+        if sets.is_empty() {
+            return Ok(0);
+        }
+
+        self.execute_update(conn, sets, bindings).await
+    }
+
+    /// Add `amount` to a column of every matching row
+    /// Post::query().where_eq("id", 1).increment(&db, "views", 1).await?
+    pub async fn increment<V: Bindable>(self, db: &impl ConnectionResolver, column: &str, amount: V) -> Result<u64, sqlx::Error> {
+        self.step(db, column, "+", amount).await
+    }
+
+    /// Subtract `amount` from a column of every matching row
+    pub async fn decrement<V: Bindable>(self, db: &impl ConnectionResolver, column: &str, amount: V) -> Result<u64, sqlx::Error> {
+        self.step(db, column, "-", amount).await
+    }
+
+    async fn step<V: Bindable>(mut self, db: &impl ConnectionResolver, column: &str, operator: &str, amount: V) -> Result<u64, sqlx::Error> {
+        let conn = self.writer(db)?;
+        let wrapped = self.grammar.wrap(column);
+        let mut sets = vec![format!("{} = {} {} ?", wrapped, wrapped, operator)];
+        let mut bindings = vec![binder(amount)];
+
+        if T::TIMESTAMPS {
+            sets.push(format!("{} = ?", self.grammar.wrap("updated_at")));
+            bindings.push(binder(crate::database::now()));
+        }
+
+        self.execute_update(conn, sets, bindings).await
+    }
+
+    /// Delete every row matching the query.
+    /// Models with SOFT_DELETES only get their `deleted_at` stamped.
+    pub async fn delete(mut self, db: &impl ConnectionResolver) -> Result<u64, sqlx::Error> {
+        if T::SOFT_DELETES {
+            let conn = self.writer(db)?;
+            let sets = vec![format!("{} = ?", self.grammar.wrap("deleted_at"))];
+            let bindings = vec![binder(crate::database::now())];
+            return self.execute_update(conn, sets, bindings).await;
+        }
+
+        self.force_delete(db).await
+    }
+
+    /// Permanently delete every row matching the query, even for soft deleting models
+    pub async fn force_delete(mut self, db: &impl ConnectionResolver) -> Result<u64, sqlx::Error> {
+        let conn = self.writer(db)?;
+        let sql = self.grammar.parameterize(&self.compile_delete(&self.grammar, T::primary_key()));
+        let args = self.build_arguments();
+        let res = conn.execute(sqlx::query_with(&sql, args)).await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Run an UPDATE; `bindings` fill the SET placeholders, ahead of the query's own
+    async fn execute_update(self, conn: Connection, sets: Vec<String>, bindings: Vec<ArgumentApplier>) -> Result<u64, sqlx::Error> {
+        let sql = self.grammar.parameterize(&self.compile_update(&self.grammar, &sets, T::primary_key()));
+        let mut args = DbArguments::default();
+        for applier in &bindings {
+            applier(&mut args);
+        }
+        self.apply_bindings(&mut args);
+        let res = conn.execute(sqlx::query_with(&sql, args)).await?;
+        Ok(res.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(sqlx::FromRow)]
+    struct Post {
+        id: i64,
+    }
+
+    impl Orbit for Post {
+        fn table_name() -> &'static str {
+            "posts"
+        }
+
+        fn id(&self) -> i64 {
+            self.id
+        }
+    }
+
+    #[test]
+    fn test_to_sql_mysql() {
+        let sql = Post::query()
+            .with_grammar(Grammar::MySql)
+            .select(&["posts.id", "title"])
+            .join("users", "users.id", "=", "posts.user_id")
+            .where_eq("active", true)
+            .or_where("views", ">", 10_i64)
+            .order_by("created_at", "DESC")
+            .limit(5)
+            .to_sql();
+
+        assert_eq!(
+            sql,
+            "SELECT `posts`.`id`, `title` FROM `posts` INNER JOIN `users` ON `users`.`id` = `posts`.`user_id` \
+             WHERE `active` = ? OR `views` > ? ORDER BY `created_at` DESC LIMIT 5"
+        );
+    }
+
+    #[test]
+    fn test_to_sql_postgres_numbers_placeholders() {
+        let sql = Post::query()
+            .with_grammar(Grammar::Postgres)
+            .where_in("id", vec![1_i64, 2, 3])
+            .where_between("views", 1_i64, 100_i64)
+            .to_sql();
+
+        assert_eq!(
+            sql,
+            "SELECT * FROM \"posts\" WHERE \"id\" IN ($1, $2, $3) AND \"views\" BETWEEN $4 AND $5"
+        );
+    }
+
+    #[test]
+    fn test_where_group_sets_precedence() {
+        let sql = Post::query()
+            .with_grammar(Grammar::MySql)
+            .where_eq("published", true)
+            .where_group(|q| q.where_eq("author_id", 1_i64).or_where("featured", "=", true))
+            .to_sql();
+
+        assert_eq!(
+            sql,
+            "SELECT * FROM `posts` WHERE `published` = ? AND (`author_id` = ? OR `featured` = ?)"
+        );
+    }
+
+    #[test]
+    fn test_nested_groups_keep_argument_order() {
+        let query = Post::query()
+            .with_grammar(Grammar::Postgres)
+            .where_eq("a", 1_i64)
+            .or_where_group(|q| {
+                q.where_eq("b", 2_i64)
+                    .where_group(|q| q.where_eq("c", 3_i64).or_where("d", "=", 4_i64))
+            })
+            .where_eq("e", 5_i64);
+
+        assert_eq!(
+            query.to_sql(),
+            "SELECT * FROM \"posts\" WHERE \"a\" = $1 OR (\"b\" = $2 AND (\"c\" = $3 OR \"d\" = $4)) AND \"e\" = $5"
+        );
+        assert_eq!(query.argument_appliers.len(), 5);
+    }
+
+    #[test]
+    fn test_empty_group_is_ignored() {
+        let sql = Post::query()
+            .with_grammar(Grammar::MySql)
+            .where_group(|q| q)
+            .to_sql();
+
+        assert_eq!(sql, "SELECT * FROM `posts`");
+    }
+
+    #[test]
+    fn test_group_by_and_having_are_separate_clauses() {
+        let query = Post::query()
+            .with_grammar(Grammar::Postgres)
+            .select(&["user_id", "COUNT(*) as total"])
+            .where_eq("published", true)
+            .group_by(&["user_id"])
+            .having("COUNT(*)", ">", 5_i64)
+            .or_having("SUM(views)", ">", 1000_i64)
+            .order_by("user_id", "ASC");
+
+        assert_eq!(
+            query.to_sql(),
+            "SELECT \"user_id\", COUNT(*) AS \"total\" FROM \"posts\" WHERE \"published\" = $1 \
+             GROUP BY \"user_id\" HAVING COUNT(*) > $2 OR SUM(views) > $3 ORDER BY \"user_id\" ASC"
+        );
+    }
+
+    #[test]
+    fn test_compile_aggregate() {
+        let query = Post::query().where_eq("published", true).order_by("id", "DESC");
+        assert_eq!(
+            query.compile_aggregate(&Grammar::MySql, "COUNT", "*"),
+            "SELECT COUNT(*) AS aggregate FROM `posts` WHERE `published` = ?"
+        );
+        assert_eq!(
+            query.compile_aggregate(&Grammar::Postgres, "SUM", "posts.views"),
+            "SELECT CAST(COALESCE(SUM(\"posts\".\"views\"), 0) AS DOUBLE PRECISION) AS aggregate FROM \"posts\" WHERE \"published\" = ?"
+        );
+    }
+
+    #[test]
+    fn test_compile_aggregate_wraps_grouped_queries() {
+        let query = Post::query().select(&["user_id"]).group_by(&["user_id"]);
+        assert_eq!(
+            query.compile_aggregate(&Grammar::Sqlite, "COUNT", "*"),
+            "SELECT COUNT(*) AS aggregate FROM (SELECT \"user_id\" FROM \"posts\" GROUP BY \"user_id\") AS aggregate_table"
+        );
+    }
+
+    #[test]
+    fn test_exists_selects_a_literal() {
+        let query = || Post::query().where_eq("published", true).order_by("id", "DESC");
+        assert_eq!(
+            query().with_grammar(Grammar::MySql).exists_query().to_sql(),
+            "SELECT 1 FROM `posts` WHERE `published` = ? LIMIT 1"
+        );
+        assert_eq!(
+            query().with_grammar(Grammar::Postgres).exists_query().to_sql(),
+            "SELECT 1 FROM \"posts\" WHERE \"published\" = $1 LIMIT 1"
+        );
+    }
+
+    #[test]
+    fn test_where_exists_keeps_placeholder_order() {
+        let subquery = Post::query()
+            .where_raw("comments.post_id = posts.id")
+            .where_eq("approved", true);
+
+        let sql = Post::query()
+            .with_grammar(Grammar::Postgres)
+            .where_eq("published", true)
+            .where_exists(subquery.with_grammar(Grammar::Postgres))
+            .where_eq("featured", true)
+            .to_sql();
+
+        assert!(sql.contains("\"published\" = $1 AND EXISTS ("));
+        assert!(sql.contains("\"approved\" = $2)"));
+        assert!(sql.ends_with("\"featured\" = $3"));
+    }
+
+    #[test]
+    fn test_compile_bulk_writes() {
+        let query = Post::query().where_eq("published", false);
+        let sets = vec!["`status` = ?".to_string()];
+        assert_eq!(
+            query.compile_update(&Grammar::MySql, &sets, "id"),
+            "UPDATE `posts` SET `status` = ? WHERE `published` = ?"
+        );
+        assert_eq!(query.compile_delete(&Grammar::MySql, "id"), "DELETE FROM `posts` WHERE `published` = ?");
+        assert_eq!(Post::query().compile_delete(&Grammar::Sqlite, "id"), "DELETE FROM \"posts\"");
+    }
+
+    #[test]
+    fn test_compile_limited_write_selects_keys() {
+        let query = Post::query()
+            .join("users", "users.id", "=", "posts.user_id")
+            .where_eq("users.banned", true)
+            .oldest("posts.id")
+            .limit(100);
+
+        assert_eq!(
+            query.compile_delete(&Grammar::MySql, "id"),
+            "DELETE FROM `posts` WHERE `id` IN (SELECT `id` FROM (SELECT `posts`.`id` FROM `posts` \
+             INNER JOIN `users` ON `users`.`id` = `posts`.`user_id` WHERE `users`.`banned` = ? \
+             ORDER BY `posts`.`id` ASC LIMIT 100) AS orbit_keys)"
+        );
+    }
+
+    #[test]
+    fn test_global_scopes_are_anded_and_removable() {
+        let query = Post::query()
+            .with_global_scope("tenant", |q: Builder<Post>| q.where_eq("tenant_id", 7))
+            .where_eq("status", "draft".to_string())
+            .or_where("status", "=", "review".to_string());
+
+        assert_eq!(
+            query.compile_select(&Grammar::MySql),
+            "SELECT * FROM `posts` WHERE `tenant_id` = ? AND (`status` = ? OR `status` = ?)"
+        );
+        assert_eq!(
+            query.compile_delete(&Grammar::MySql, "id"),
+            "DELETE FROM `posts` WHERE `tenant_id` = ? AND (`status` = ? OR `status` = ?)"
+        );
+
+        let query = query.without_global_scope("tenant");
+        assert_eq!(
+            query.compile_select(&Grammar::MySql),
+            "SELECT * FROM `posts` WHERE `status` = ? OR `status` = ?"
+        );
+    }
+
+    #[test]
+    fn test_local_scope_applies_to_query() {
+        let published = |q: Builder<Post>| q.where_not_null("published_at").latest("published_at");
+        let query = Post::query().scope(published);
+
+        assert_eq!(
+            query.compile_select(&Grammar::Postgres),
+            "SELECT * FROM \"posts\" WHERE \"published_at\" IS NOT NULL ORDER BY \"published_at\" DESC"
+        );
+    }
 }
@@ -0,0 +1,249 @@
+use crate::database::{db_datetime, DbArguments, DbRow};
+use crate::orbit::bind_json_value;
+use crate::support::crypt::Crypt;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use sqlx::{Arguments, Row};
+
+/// Format datetimes are written in
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// How an attribute is converted between the model and its column.
+///
+/// Declared per field with `#[orbit(cast = "json")]`, or by hand in `Orbit::casts`.
+/// `set` runs on every write (create, update, insert_many, upsert, bulk update),
+/// `get` when a derived model is loaded.
+#[derive(Debug, Clone, Copy)]
+pub enum Cast {
+    /// Any serde value stored as JSON text; bound as JSON on Postgres so `jsonb` columns accept it.
+    /// The `any` driver has no JSON type, so there the column must be text.
+    Json,
+    /// true/false, 1/0, "1"/"0", "true"/"false", "yes"/"no", "on"/"off"
+    Bool,
+    /// RFC 3339, "Y-m-d H:M:S", "Y-m-d" or a unix timestamp; stored as UTC. Load into `DateTime<Utc>`.
+    DateTime,
+    /// A serde enum; values it doesn't accept are rejected before the write
+    Enum(fn(&Value) -> bool),
+    /// Any serde value encrypted with APP_KEY (see `support::crypt`); the JSON of the value is encrypted
+    Encrypted,
+}
+
+impl Cast {
+    /// Convert an attribute into the value stored in the column
+    pub fn set(&self, column: &str, value: Value) -> Result<Value, sqlx::Error> {
+        if value.is_null() {
+            return Ok(value);
+        }
+
+        match self {
+            Cast::Json => Ok(Value::String(value.to_string())),
+            Cast::Bool => match &value {
+                Value::Bool(_) => Ok(value),
+                Value::Number(n) => Ok(Value::Bool(n.as_f64() != Some(0.0))),
+                Value::String(s) => match s.to_lowercase().as_str() {
+                    "1" | "true" | "yes" | "on" => Ok(Value::Bool(true)),
+                    "0" | "false" | "no" | "off" | "" => Ok(Value::Bool(false)),
+                    _ => Err(invalid(column, "is not a boolean")),
+                },
+                _ => Err(invalid(column, "is not a boolean")),
+            },
+            Cast::DateTime => {
+                let datetime = match &value {
+                    Value::String(s) => parse_datetime(s),
+                    Value::Number(n) => n.as_i64().and_then(|secs| Utc.timestamp_opt(secs, 0).single()),
+                    _ => None,
+                };
+                datetime
+                    .map(|dt| Value::String(dt.format(DATETIME_FORMAT).to_string()))
+                    .ok_or_else(|| invalid(column, "is not a valid datetime"))
+            }
+            Cast::Enum(accepts) => match accepts(&value) {
+                true => Ok(value),
+                false => Err(invalid(column, "is not a valid variant")),
+            },
+            Cast::Encrypted => Crypt::encrypt(&value.to_string())
+                .map(Value::String)
+                .map_err(|e| invalid(column, &e.to_string())),
+        }
+    }
+
+    /// Read a column into the attribute value the model field deserializes from
+    pub fn get(&self, row: &DbRow, column: &str) -> Result<Value, sqlx::Error> {
+        match self {
+            Cast::Json => {
+                if let Some(value) = native_json(row, column) {
+                    return Ok(value.unwrap_or(Value::Null));
+                }
+                match row.try_get::<Option<String>, _>(column)? {
+                    Some(text) => serde_json::from_str(&text).map_err(|e| decode_error(column, e)),
+                    None => Ok(Value::Null),
+                }
+            }
+            Cast::Bool => {
+                if let Ok(value) = row.try_get::<Option<bool>, _>(column) {
+                    return Ok(value.map(Value::Bool).unwrap_or(Value::Null));
+                }
+                let value = row.try_get::<Option<i64>, _>(column)?;
+                Ok(value.map(|n| Value::Bool(n != 0)).unwrap_or(Value::Null))
+            }
+            Cast::DateTime => {
+                let datetime = match native_datetime(row, column) {
+                    Some(value) => value,
+                    None => match row.try_get::<Option<String>, _>(column)? {
+                        Some(text) => Some(parse_datetime(&text).ok_or_else(|| decode_error(column, "not a valid datetime"))?),
+                        None => None,
+                    },
+                };
+                Ok(datetime.map(|dt| Value::String(dt.to_rfc3339())).unwrap_or(Value::Null))
+            }
+            Cast::Enum(_) => {
+                if let Ok(value) = row.try_get::<Option<String>, _>(column) {
+                    return Ok(value.map(Value::String).unwrap_or(Value::Null));
+                }
+                let value = row.try_get::<Option<i64>, _>(column)?;
+                Ok(value.map(Value::from).unwrap_or(Value::Null))
+            }
+            Cast::Encrypted => match row.try_get::<Option<String>, _>(column)? {
+                Some(payload) => {
+                    let plaintext = Crypt::decrypt(&payload).map_err(|e| decode_error(column, e))?;
+                    serde_json::from_str(&plaintext).map_err(|e| decode_error(column, e))
+                }
+                None => Ok(Value::Null),
+            },
+        }
+    }
+}
+
+/// Load a cast column into a model field; used by `#[derive(Orbit)]`
+pub fn decode<T: DeserializeOwned>(row: &DbRow, column: &str, cast: Cast) -> Result<T, sqlx::Error> {
+    serde_json::from_value(cast.get(row, column)?).map_err(|e| decode_error(column, e))
+}
+
+/// Bind an attribute produced by `Cast::set`; datetimes are bound as timestamps so
+/// Postgres accepts them for `timestamp` columns, and JSON as JSON for `jsonb` ones
+pub(crate) fn bind_attribute(args: &mut DbArguments, value: &Value, cast: Option<Cast>) {
+    if let (Some(Cast::DateTime), Value::String(s)) = (cast, value) {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(s, DATETIME_FORMAT) {
+            args.add(db_datetime(datetime));
+            return;
+        }
+    }
+    if let (Some(Cast::Json), Value::String(s)) = (cast, value) {
+        if bind_native_json(args, s) {
+            return;
+        }
+    }
+    bind_json_value(args, value);
+}
+
+/// Postgres has no implicit cast from text to `jsonb`, so JSON is sent with the JSONB type.
+/// Text columns still accept it through the assignment cast from `jsonb` to text.
+#[cfg(all(feature = "postgres", not(feature = "sqlite")))]
+fn bind_native_json(args: &mut DbArguments, text: &str) -> bool {
+    match serde_json::from_str::<Value>(text) {
+        Ok(json) => {
+            args.add(sqlx::types::Json(json));
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// MySQL and SQLite take JSON as text
+#[cfg(not(all(feature = "postgres", not(feature = "sqlite"))))]
+fn bind_native_json(_args: &mut DbArguments, _text: &str) -> bool {
+    false
+}
+
+/// A `json`/`jsonb` column read through the driver's JSON type; None when it isn't one
+#[cfg(all(feature = "postgres", not(feature = "sqlite")))]
+fn native_json(row: &DbRow, column: &str) -> Option<Option<Value>> {
+    row.try_get::<Option<sqlx::types::Json<Value>>, _>(column).ok().map(|value| value.map(|json| json.0))
+}
+
+#[cfg(not(all(feature = "postgres", not(feature = "sqlite"))))]
+fn native_json(_row: &DbRow, _column: &str) -> Option<Option<Value>> {
+    None
+}
+
+/// The cast declared for a column, if any
+pub(crate) fn cast_for(casts: &[(&str, Cast)], column: &str) -> Option<Cast> {
+    casts.iter().find(|(name, _)| *name == column).map(|(_, cast)| *cast)
+}
+
+/// A datetime column read through the driver's own date types; None when it isn't one
+#[cfg(not(feature = "any"))]
+fn native_datetime(row: &DbRow, column: &str) -> Option<Option<DateTime<Utc>>> {
+    if let Ok(value) = row.try_get::<Option<DateTime<Utc>>, _>(column) {
+        return Some(value);
+    }
+    row.try_get::<Option<NaiveDateTime>, _>(column).ok().map(|value| value.map(|dt| dt.and_utc()))
+}
+
+/// The `any` driver has no date types; datetimes always arrive as text
+#[cfg(feature = "any")]
+fn native_datetime(_row: &DbRow, _column: &str) -> Option<Option<DateTime<Utc>>> {
+    None
+}
+
+fn parse_datetime(text: &str) -> Option<DateTime<Utc>> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(text) {
+        return Some(datetime.with_timezone(&Utc));
+    }
+    ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(text, "%Y-%m-%d").ok().and_then(|d| d.and_hms_opt(0, 0, 0)))
+        .map(|datetime| datetime.and_utc())
+}
+
+fn invalid(column: &str, reason: &str) -> sqlx::Error {
+    sqlx::Error::Protocol(format!("invalid value for `{}`: {}", column, reason))
+}
+
+fn decode_error<E: ToString>(column: &str, error: E) -> sqlx::Error {
+    sqlx::Error::ColumnDecode { index: column.to_string(), source: error.to_string().into() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(serde::Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    #[allow(dead_code)]
+    enum Status {
+        Draft,
+        Published,
+    }
+
+    #[test]
+    fn test_set_converts_attributes() {
+        assert_eq!(Cast::Json.set("tags", json!(["a", "b"])).unwrap(), json!("[\"a\",\"b\"]"));
+        assert_eq!(Cast::Bool.set("active", json!("on")).unwrap(), json!(true));
+        assert_eq!(Cast::Bool.set("active", json!(0)).unwrap(), json!(false));
+        assert!(Cast::Bool.set("active", json!("maybe")).is_err());
+        assert_eq!(Cast::DateTime.set("at", json!("2024-05-01T12:30:00+02:00")).unwrap(), json!("2024-05-01 10:30:00"));
+        assert_eq!(Cast::DateTime.set("at", json!("2024-05-01")).unwrap(), json!("2024-05-01 00:00:00"));
+        assert_eq!(Cast::DateTime.set("at", json!(0)).unwrap(), json!("1970-01-01 00:00:00"));
+        assert_eq!(Cast::Json.set("tags", Value::Null).unwrap(), Value::Null);
+
+        let status = Cast::Enum(|value| serde_json::from_value::<Status>(value.clone()).is_ok());
+        assert_eq!(status.set("status", json!("draft")).unwrap(), json!("draft"));
+        assert!(status.set("status", json!("deleted")).is_err());
+    }
+
+    #[test]
+    fn test_encrypted_stores_the_json_of_the_value() {
+        std::env::set_var("APP_KEY", "orbit-test-key");
+        for value in [json!("123-45-6789"), json!(42), json!({ "pin": "1234" })] {
+            let Value::String(payload) = Cast::Encrypted.set("secret", value.clone()).unwrap() else {
+                panic!("an encrypted value is stored as text");
+            };
+            let plaintext = Crypt::decrypt(&payload).unwrap();
+            assert_eq!(serde_json::from_str::<Value>(&plaintext).unwrap(), value);
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+/// A column of model `M` holding values of type `V`.
+///
+/// `#[derive(Orbit)]` generates one per field, so a misspelled column is a
+/// compile error instead of a runtime SQL error. It derefs to the column name:
+///
+/// User::query().where_eq(&User::EMAIL, email).latest(&User::CREATED_AT)
+pub struct Column<M, V> {
+    name: &'static str,
+    _marker: PhantomData<fn() -> (M, V)>,
+}
+
+impl<M, V> Column<M, V> {
+    pub const fn new(name: &'static str) -> Self {
+        Self { name, _marker: PhantomData }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<M, V> Clone for Column<M, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<M, V> Copy for Column<M, V> {}
+
+impl<M, V> Deref for Column<M, V> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.name
+    }
+}
+
+impl<M, V> fmt::Display for Column<M, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+impl<M, V> fmt::Debug for Column<M, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Column({})", self.name)
+    }
+}
@@ -0,0 +1,398 @@
+use crate::database::DbPool;
+#[cfg(not(feature = "any"))]
+use crate::database::Db;
+#[cfg(feature = "any")]
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// The SQL dialect Orbit compiles queries and schema definitions into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grammar {
+    MySql,
+    Postgres,
+    Sqlite,
+}
+
+/// Ties a sqlx backend to the grammar Orbit should emit for it.
+pub trait HasGrammar {
+    const GRAMMAR: Grammar;
+}
+
+#[cfg(feature = "mysql")]
+impl HasGrammar for sqlx::MySql {
+    const GRAMMAR: Grammar = Grammar::MySql;
+}
+
+#[cfg(feature = "postgres")]
+impl HasGrammar for sqlx::Postgres {
+    const GRAMMAR: Grammar = Grammar::Postgres;
+}
+
+#[cfg(feature = "sqlite")]
+impl HasGrammar for sqlx::Sqlite {
+    const GRAMMAR: Grammar = Grammar::Sqlite;
+}
+
+/// The grammar of the default connection, in `any` mode where it is only known at runtime
+#[cfg(feature = "any")]
+static CURRENT: AtomicU8 = AtomicU8::new(0);
+
+#[cfg(not(feature = "any"))]
+impl Grammar {
+    /// The grammar of the compiled `Db` backend
+    pub const fn current() -> Self {
+        <Db as HasGrammar>::GRAMMAR
+    }
+
+    /// The grammar of the database behind `pool`
+    pub fn of(_pool: &DbPool) -> Self {
+        Self::current()
+    }
+}
+
+#[cfg(feature = "any")]
+impl Grammar {
+    /// The grammar of the default connection (MySQL until one is added)
+    pub fn current() -> Self {
+        match CURRENT.load(Ordering::Relaxed) {
+            1 => Grammar::Postgres,
+            2 => Grammar::Sqlite,
+            _ => Grammar::MySql,
+        }
+    }
+
+    /// Make `grammar` the one `current` returns; `DatabaseManager` calls this for its default connection
+    pub(crate) fn set_current(grammar: Grammar) {
+        let value = match grammar {
+            Grammar::MySql => 0,
+            Grammar::Postgres => 1,
+            Grammar::Sqlite => 2,
+        };
+        CURRENT.store(value, Ordering::Relaxed);
+    }
+
+    /// The grammar of the database behind `pool`, read from its URL
+    pub fn of(pool: &DbPool) -> Self {
+        Self::from_url(pool.connect_options().database_url.as_str()).unwrap_or_else(Self::current)
+    }
+}
+
+impl Grammar {
+    /// The grammar for a driver name: mysql (or mariadb), postgres (pgsql, postgresql) or sqlite
+    pub fn from_driver(driver: &str) -> Option<Self> {
+        match driver.to_ascii_lowercase().as_str() {
+            "mysql" | "mariadb" => Some(Grammar::MySql),
+            "postgres" | "postgresql" | "pgsql" => Some(Grammar::Postgres),
+            "sqlite" => Some(Grammar::Sqlite),
+            _ => None,
+        }
+    }
+
+    /// The grammar for a connection URL, from its scheme
+    pub fn from_url(url: &str) -> Option<Self> {
+        url.split_once(':').and_then(|(scheme, _)| Self::from_driver(scheme))
+    }
+
+    /// Quote an identifier such as `users.id` or `email as login`.
+    /// Expressions (anything that isn't a plain identifier) are left untouched.
+    pub fn wrap(&self, value: &str) -> String {
+        let lower = value.to_ascii_lowercase();
+        if let Some(pos) = lower.find(" as ") {
+            let (column, alias) = (&value[..pos], &value[pos + 4..]);
+            return format!("{} AS {}", self.wrap(column.trim()), self.wrap_segment(alias.trim()));
+        }
+
+        let segments: Vec<&str> = value.split('.').collect();
+        if !segments.iter().all(|s| *s == "*" || Self::is_identifier(s)) {
+            return value.to_string();
+        }
+
+        segments
+            .iter()
+            .map(|s| self.wrap_segment(s))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    /// Quote a table name
+    pub fn wrap_table(&self, table: &str) -> String {
+        self.wrap(table)
+    }
+
+    fn wrap_segment(&self, segment: &str) -> String {
+        if segment == "*" || !Self::is_identifier(segment) {
+            return segment.to_string();
+        }
+        match self {
+            Grammar::MySql => format!("`{}`", segment),
+            Grammar::Postgres | Grammar::Sqlite => format!("\"{}\"", segment),
+        }
+    }
+
+    /// Numeric literals such as the `1` of `SELECT 1` aren't identifiers
+    fn is_identifier(value: &str) -> bool {
+        !value.is_empty()
+            && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !value.chars().all(|c| c.is_ascii_digit())
+    }
+
+    /// The bind parameter for the given (1-based) position
+    pub fn parameter(&self, index: usize) -> String {
+        match self {
+            Grammar::Postgres => format!("${}", index),
+            Grammar::MySql | Grammar::Sqlite => "?".to_string(),
+        }
+    }
+
+    /// Rewrite `?` placeholders into the dialect's parameter syntax.
+    /// Placeholders inside quoted strings or identifiers are left alone.
+    pub fn parameterize(&self, sql: &str) -> String {
+        if *self != Grammar::Postgres {
+            return sql.to_string();
+        }
+
+        let mut out = String::with_capacity(sql.len());
+        let mut quote: Option<char> = None;
+        let mut index = 0;
+
+        for c in sql.chars() {
+            match quote {
+                Some(q) if c == q => quote = None,
+                Some(_) => {}
+                None if c == '\'' || c == '"' || c == '`' => quote = Some(c),
+                None if c == '?' => {
+                    index += 1;
+                    out.push_str(&self.parameter(index));
+                    continue;
+                }
+                None => {}
+            }
+            out.push(c);
+        }
+
+        out
+    }
+
+    /// Cast a numeric expression to the dialect's double precision type
+    pub fn cast_to_float(&self, expression: &str) -> String {
+        match self {
+            Grammar::MySql => format!("CAST({} AS DOUBLE)", expression),
+            Grammar::Postgres => format!("CAST({} AS DOUBLE PRECISION)", expression),
+            Grammar::Sqlite => format!("CAST({} AS REAL)", expression),
+        }
+    }
+
+    /// Whether inserted keys are read back through `RETURNING` instead of the driver's last insert id.
+    /// Postgres has no last insert id, and the `any` driver doesn't pass SQLite's on.
+    pub fn uses_returning(&self) -> bool {
+        match self {
+            Grammar::Postgres => true,
+            Grammar::Sqlite => cfg!(feature = "any"),
+            Grammar::MySql => false,
+        }
+    }
+
+    /// INSERT INTO table (a, b) VALUES (?, ?) [RETURNING key].
+    /// With no columns the row is all defaults: `() VALUES ()` on MySQL, `DEFAULT VALUES` elsewhere.
+    pub fn compile_insert(&self, table: &str, columns: &[String], key: &str) -> String {
+        let mut sql = match self {
+            Grammar::Postgres | Grammar::Sqlite if columns.is_empty() => {
+                format!("INSERT INTO {} DEFAULT VALUES", self.wrap_table(table))
+            }
+            _ => {
+                let wrapped: Vec<String> = columns.iter().map(|c| self.wrap(c)).collect();
+                format!(
+                    "INSERT INTO {} ({}) VALUES ({})",
+                    self.wrap_table(table),
+                    wrapped.join(", "),
+                    vec!["?"; columns.len()].join(", ")
+                )
+            }
+        };
+
+        if self.uses_returning() {
+            sql.push_str(&format!(" RETURNING {}", self.wrap(key)));
+        }
+
+        self.parameterize(&sql)
+    }
+
+    /// The session statement that caps how long a statement may run, if the dialect has one.
+    /// MySQL's `max_execution_time` only applies to SELECTs.
+    pub fn compile_statement_timeout(&self, millis: u64) -> Option<String> {
+        match self {
+            Grammar::MySql => Some(format!("SET SESSION max_execution_time = {}", millis)),
+            Grammar::Postgres => Some(format!("SET statement_timeout = {}", millis)),
+            Grammar::Sqlite => None,
+        }
+    }
+
+    /// Most bound parameters a single statement may carry; batched inserts are chunked to stay below it
+    pub fn max_parameters(&self) -> usize {
+        match self {
+            Grammar::MySql => 65_535,
+            Grammar::Postgres => 32_767,
+            Grammar::Sqlite => 32_766,
+        }
+    }
+
+    /// INSERT INTO table (a, b) VALUES (?, ?), (?, ?).
+    /// With no columns Postgres and SQLite can only insert one row of defaults
+    /// (`DEFAULT VALUES`), so `rows` is ignored there and callers insert one at a time.
+    pub fn compile_insert_many(&self, table: &str, columns: &[String], rows: usize) -> String {
+        if columns.is_empty() && *self != Grammar::MySql {
+            return format!("INSERT INTO {} DEFAULT VALUES", self.wrap_table(table));
+        }
+
+        let wrapped: Vec<String> = columns.iter().map(|c| self.wrap(c)).collect();
+        let row = format!("({})", vec!["?"; columns.len()].join(", "));
+
+        let sql = format!(
+            "INSERT INTO {} ({}) VALUES {}",
+            self.wrap_table(table),
+            wrapped.join(", "),
+            vec![row; rows].join(", ")
+        );
+        self.parameterize(&sql)
+    }
+
+    /// Insert rows, overwriting the `update` columns of rows that collide on `unique_by`.
+    /// With nothing to update, colliding rows are skipped.
+    pub fn compile_upsert(&self, table: &str, columns: &[String], rows: usize, unique_by: &[String], update: &[String]) -> String {
+        let insert = self.compile_insert_many(table, columns, rows);
+
+        match self {
+            Grammar::MySql if update.is_empty() => insert.replacen("INSERT INTO", "INSERT IGNORE INTO", 1),
+            Grammar::MySql => {
+                let sets: Vec<String> = update
+                    .iter()
+                    .map(|c| format!("{} = VALUES({})", self.wrap(c), self.wrap(c)))
+                    .collect();
+                format!("{} ON DUPLICATE KEY UPDATE {}", insert, sets.join(", "))
+            }
+            Grammar::Postgres | Grammar::Sqlite => {
+                let target: Vec<String> = unique_by.iter().map(|c| self.wrap(c)).collect();
+                let action = if update.is_empty() {
+                    "NOTHING".to_string()
+                } else {
+                    let sets: Vec<String> = update
+                        .iter()
+                        .map(|c| format!("{} = excluded.{}", self.wrap(c), self.wrap(c)))
+                        .collect();
+                    format!("UPDATE SET {}", sets.join(", "))
+                };
+                format!("{} ON CONFLICT ({}) DO {}", insert, target.join(", "), action)
+            }
+        }
+    }
+
+    /// UPDATE table SET a = ?, b = ? WHERE key = ?
+    pub fn compile_update(&self, table: &str, columns: &[String], key: &str) -> String {
+        let sets: Vec<String> = columns.iter().map(|c| format!("{} = ?", self.wrap(c))).collect();
+        let sql = format!(
+            "UPDATE {} SET {} WHERE {} = ?",
+            self.wrap_table(table),
+            sets.join(", "),
+            self.wrap(key)
+        );
+        self.parameterize(&sql)
+    }
+
+    /// DELETE FROM table WHERE key = ?
+    pub fn compile_delete(&self, table: &str, key: &str) -> String {
+        let sql = format!("DELETE FROM {} WHERE {} = ?", self.wrap_table(table), self.wrap(key));
+        self.parameterize(&sql)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_url() {
+        assert_eq!(Grammar::from_url("mysql://root@localhost/app"), Some(Grammar::MySql));
+        assert_eq!(Grammar::from_url("postgresql://localhost/app"), Some(Grammar::Postgres));
+        assert_eq!(Grammar::from_url("sqlite::memory:"), Some(Grammar::Sqlite));
+        assert_eq!(Grammar::from_url("mssql://localhost/app"), None);
+        assert_eq!(Grammar::from_driver("pgsql"), Some(Grammar::Postgres));
+    }
+
+    #[test]
+    fn test_wrap_identifiers() {
+        assert_eq!(Grammar::MySql.wrap("users.id"), "`users`.`id`");
+        assert_eq!(Grammar::Postgres.wrap("users.*"), "\"users\".*");
+        assert_eq!(Grammar::Sqlite.wrap("email as login"), "\"email\" AS \"login\"");
+        assert_eq!(Grammar::MySql.wrap("COUNT(*)"), "COUNT(*)");
+    }
+
+    #[test]
+    fn test_parameterize_postgres() {
+        let sql = Grammar::Postgres.parameterize("SELECT * FROM t WHERE a = ? AND b = '?' AND c = ?");
+        assert_eq!(sql, "SELECT * FROM t WHERE a = $1 AND b = '?' AND c = $2");
+        assert_eq!(Grammar::MySql.parameterize("a = ?"), "a = ?");
+    }
+
+    #[test]
+    fn test_compile_insert() {
+        let columns = vec!["name".to_string(), "email".to_string()];
+        assert_eq!(
+            Grammar::MySql.compile_insert("users", &columns, "id"),
+            "INSERT INTO `users` (`name`, `email`) VALUES (?, ?)"
+        );
+        assert_eq!(
+            Grammar::Postgres.compile_insert("users", &columns, "id"),
+            "INSERT INTO \"users\" (\"name\", \"email\") VALUES ($1, $2) RETURNING \"id\""
+        );
+    }
+
+    #[test]
+    fn test_compile_insert_without_columns() {
+        assert_eq!(Grammar::MySql.compile_insert("users", &[], "id"), "INSERT INTO `users` () VALUES ()");
+        assert_eq!(
+            Grammar::Postgres.compile_insert("users", &[], "id"),
+            "INSERT INTO \"users\" DEFAULT VALUES RETURNING \"id\""
+        );
+        assert!(Grammar::Sqlite.compile_insert("users", &[], "id").starts_with("INSERT INTO \"users\" DEFAULT VALUES"));
+        assert_eq!(Grammar::MySql.compile_insert_many("users", &[], 2), "INSERT INTO `users` () VALUES (), ()");
+        assert_eq!(Grammar::Sqlite.compile_insert_many("users", &[], 1), "INSERT INTO \"users\" DEFAULT VALUES");
+    }
+
+    #[test]
+    fn test_compile_update() {
+        let columns = vec!["name".to_string()];
+        assert_eq!(
+            Grammar::Postgres.compile_update("users", &columns, "id"),
+            "UPDATE \"users\" SET \"name\" = $1 WHERE \"id\" = $2"
+        );
+    }
+
+    #[test]
+    fn test_compile_insert_many() {
+        let columns = vec!["name".to_string(), "email".to_string()];
+        assert_eq!(
+            Grammar::Postgres.compile_insert_many("users", &columns, 2),
+            "INSERT INTO \"users\" (\"name\", \"email\") VALUES ($1, $2), ($3, $4)"
+        );
+    }
+
+    #[test]
+    fn test_compile_upsert() {
+        let columns = vec!["email".to_string(), "name".to_string()];
+        let unique = vec!["email".to_string()];
+        let update = vec!["name".to_string()];
+
+        assert_eq!(
+            Grammar::MySql.compile_upsert("users", &columns, 1, &unique, &update),
+            "INSERT INTO `users` (`email`, `name`) VALUES (?, ?) ON DUPLICATE KEY UPDATE `name` = VALUES(`name`)"
+        );
+        assert_eq!(
+            Grammar::Postgres.compile_upsert("users", &columns, 1, &unique, &update),
+            "INSERT INTO \"users\" (\"email\", \"name\") VALUES ($1, $2) ON CONFLICT (\"email\") DO UPDATE SET \"name\" = excluded.\"name\""
+        );
+        assert_eq!(
+            Grammar::Sqlite.compile_upsert("users", &columns, 1, &unique, &[]),
+            "INSERT INTO \"users\" (\"email\", \"name\") VALUES (?, ?) ON CONFLICT (\"email\") DO NOTHING"
+        );
+        assert!(Grammar::MySql.compile_upsert("users", &columns, 1, &unique, &[]).starts_with("INSERT IGNORE INTO"));
+    }
+}
@@ -1,9 +1,182 @@
 use async_trait::async_trait;
-use crate::database::{Db, DbArguments, DbRow, DbPool, DatabaseManager};
-use sqlx::{FromRow, Arguments, Execute};
+use crate::database::{db_datetime, ConnectionResolver, DbArguments, DbRow};
+use crate::models::observer::{self, ModelEvent, Observer};
+use sqlx::{FromRow, Arguments, Row};
 
 pub mod builder;
+pub mod casts;
+pub mod column;
+pub mod grammar;
+pub mod pagination;
+pub mod relations;
 pub mod schema;
+pub mod scope;
+
+pub use casts::Cast;
+pub use column::Column;
+pub use grammar::Grammar;
+pub use orbit_derive::Orbit;
+pub use pagination::Paginator;
+pub use relations::Relation;
+pub use scope::Scope;
+
+/// Bind a JSON attribute value onto the argument list of a write query
+pub(crate) fn bind_json_value(args: &mut DbArguments, value: &serde_json::Value) {
+    match value {
+        serde_json::Value::String(s) => args.add(s.clone()),
+        serde_json::Value::Number(n) => {
+            if let Some(i) = n.as_i64() { args.add(i); }
+            else if let Some(f) = n.as_f64() { args.add(f); }
+        },
+        serde_json::Value::Bool(b) => args.add(*b),
+        serde_json::Value::Null => args.add(Option::<String>::None),
+        _ => args.add(value.to_string()),
+    }
+}
+
+/// The attributes a write may set: `data` as a JSON object without the keys the model
+/// doesn't allow mass assignment of, with casts applied
+pub(crate) fn fill<M: Orbit, D: serde::Serialize>(data: D) -> Result<serde_json::Map<String, serde_json::Value>, sqlx::Error> {
+    let object = match serde_json::to_value(data).map_err(|e| sqlx::Error::Protocol(e.to_string()))? {
+        serde_json::Value::Object(object) => object,
+        other => return Err(sqlx::Error::Protocol(format!("Data must be an object, got {}", other))),
+    };
+    let (fillable, guarded, casts) = (M::fillable(), M::guarded(), M::casts());
+
+    let mut attributes = serde_json::Map::new();
+    for (key, value) in object {
+        let allowed = (fillable.is_empty() || fillable.contains(&key.as_str()))
+            && !guarded.contains(&key.as_str())
+            && !guarded.contains(&"*");
+        if !allowed {
+            continue;
+        }
+
+        let value = match casts::cast_for(&casts, &key) {
+            Some(cast) => cast.set(&key, value)?,
+            None => value,
+        };
+        attributes.insert(key, value);
+    }
+
+    Ok(attributes)
+}
+
+/// Flatten a batch of JSON objects into one column list and the values of each row.
+/// Columns follow the order they are first seen in; attributes a row lacks are bound as NULL.
+fn collect_rows<M: Orbit, D: serde::Serialize>(rows: &[D]) -> Result<(Vec<String>, Vec<Vec<serde_json::Value>>), sqlx::Error> {
+    let mut objects = rows.iter().map(fill::<M, _>).collect::<Result<Vec<_>, _>>()?;
+    if M::TIMESTAMPS {
+        for object in &mut objects {
+            object.remove("created_at");
+            object.remove("updated_at");
+        }
+    }
+
+    let mut columns: Vec<String> = Vec::new();
+    for object in &objects {
+        for key in object.keys() {
+            if !columns.contains(key) {
+                columns.push(key.clone());
+            }
+        }
+    }
+
+    let values = objects
+        .iter()
+        .map(|object| columns.iter().map(|c| object.get(c).cloned().unwrap_or(serde_json::Value::Null)).collect())
+        .collect();
+
+    Ok((columns, values))
+}
+
+/// Bind a chunk of rows produced by `collect_rows`, stamping created_at/updated_at when enabled
+fn bind_rows(args: &mut DbArguments, columns: &[Option<Cast>], rows: &[Vec<serde_json::Value>], timestamps: bool) {
+    let now = chrono::Local::now().naive_local();
+    for row in rows {
+        for (value, cast) in row.iter().zip(columns) {
+            casts::bind_attribute(args, value, *cast);
+        }
+        if timestamps {
+            args.add(db_datetime(now));
+            args.add(db_datetime(now));
+        }
+    }
+}
+
+/// Notify a model's observers. An error from a before-event (creating, updating, ...)
+/// aborts the write; errors after the write has happened are only logged.
+async fn fire(observers: &[Box<dyn Observer>], event: ModelEvent, table: &str, model: &serde_json::Value) -> Result<(), sqlx::Error> {
+    if observers.is_empty() {
+        return Ok(());
+    }
+
+    let result = observer::dispatch(observers, event, model).await.map_err(|e| e.to_string());
+    match result {
+        Ok(()) => Ok(()),
+        Err(message) if event.is_before() => {
+            Err(sqlx::Error::Protocol(format!("{} {} aborted by an observer: {}", table, event, message)))
+        }
+        Err(message) => {
+            tracing::error!("{} observer failed on {}: {}", event, table, message);
+            Ok(())
+        }
+    }
+}
+
+/// Batched INSERT (or upsert when `unique_by` is given), chunked below the backend's parameter limit
+async fn insert_rows<M, D, C: ConnectionResolver>(db: &C, rows: &[D], unique_by: &[&str], update: &[&str]) -> Result<u64, sqlx::Error>
+where
+    M: Orbit,
+    D: serde::Serialize,
+{
+    if rows.is_empty() {
+        return Ok(0);
+    }
+
+    let conn = db.resolve(M::connection())?;
+    let grammar = conn.grammar();
+    let (mut columns, values) = collect_rows::<M, D>(rows)?;
+    let model_casts = M::casts();
+    let column_casts: Vec<Option<Cast>> = columns.iter().map(|c| casts::cast_for(&model_casts, c)).collect();
+
+    let unique_by: Vec<String> = unique_by.iter().map(|c| c.to_string()).collect();
+    let mut update: Vec<String> = if unique_by.is_empty() || !update.is_empty() {
+        update.iter().map(|c| c.to_string()).collect()
+    } else {
+        // Like Laravel: with no explicit columns, every inserted column is updated
+        columns.iter().filter(|c| !unique_by.contains(c)).cloned().collect()
+    };
+
+    if M::TIMESTAMPS {
+        columns.push("created_at".to_string());
+        columns.push("updated_at".to_string());
+        if !unique_by.is_empty() && !update.is_empty() {
+            update.push("updated_at".to_string());
+        }
+    }
+
+    // Postgres and SQLite insert a row of only defaults with DEFAULT VALUES, one row per statement
+    let per_statement = match columns.is_empty() && grammar != Grammar::MySql {
+        true => 1,
+        false => (grammar.max_parameters() / columns.len().max(1)).max(1),
+    };
+    let mut affected = 0;
+
+    for chunk in values.chunks(per_statement) {
+        let sql = if unique_by.is_empty() {
+            grammar.compile_insert_many(M::table_name(), &columns, chunk.len())
+        } else {
+            grammar.compile_upsert(M::table_name(), &columns, chunk.len(), &unique_by, &update)
+        };
+
+        let mut args = DbArguments::default();
+        bind_rows(&mut args, &column_casts, chunk, M::TIMESTAMPS);
+        affected += conn.execute(sqlx::query_with(&sql, args)).await?.rows_affected();
+    }
+
+    Ok(affected)
+}
 
 #[async_trait]
 pub trait Orbit: Sized + Send + Unpin + for<'r> FromRow<'r, DbRow> {
@@ -26,171 +199,278 @@ pub trait Orbit: Sized + Send + Unpin + for<'r> FromRow<'r, DbRow> {
     // Required to support update/delete on instance
     fn id(&self) -> i64;
 
+    /// The model as JSON without its `hidden()` attributes
+    fn to_json(&self) -> serde_json::Value
+    where Self: serde::Serialize
+    {
+        let mut value = serde_json::to_value(self).unwrap_or(serde_json::Value::Null);
+        if let serde_json::Value::Object(map) = &mut value {
+            for key in Self::hidden() {
+                map.remove(*key);
+            }
+        }
+        value
+    }
+
+    /// Attributes left out of `to_json`, e.g. `&["password_hash"]`
+    fn hidden() -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Attributes `create`/`update` may set; empty allows all but the guarded ones.
+    /// Other keys in the data are silently dropped.
+    fn fillable() -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Attributes `create`/`update` never set, e.g. `&["is_admin"]`; `&["*"]` guards everything
+    fn guarded() -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Column casts applied on write, e.g. `vec![("settings", Cast::Json)]`.
+    /// Derived models also apply them when loading.
+    fn casts() -> Vec<(&'static str, Cast)> {
+        Vec::new()
+    }
+
+    /// Observers notified by create/update/delete/restore, usually
+    /// `<Self as Observable>::observers()` (`#[orbit(observable)]` with the derive).
+    /// Bulk writes through the query builder don't fire events.
+    fn observers() -> Vec<Box<dyn Observer>> {
+        Vec::new()
+    }
+
+    /// What observers receive as the model. Derived models send `to_json()`.
+    fn attributes(&self) -> serde_json::Value {
+        serde_json::json!({ Self::primary_key(): self.id() })
+    }
+
     // Lifecycle hooks
     fn boot() {}
 
+    /// Relationships available to eager loading (`Builder::with`), by name
+    fn relation(_name: &str) -> Option<Relation<Self>> {
+        None
+    }
+
+    /// Scopes applied to every `query()`, by name; remove one with `without_global_scope(name)`.
+    /// fn global_scopes() -> Vec<(&'static str, Box<dyn Scope<Self>>)> {
+    ///     vec![("tenant", Box::new(|q: Builder<Self>| q.where_eq("tenant_id", current_tenant())))]
+    /// }
+    fn global_scopes() -> Vec<(&'static str, Box<dyn Scope<Self>>)> {
+        Vec::new()
+    }
+
     fn query() -> builder::Builder<Self> {
         let mut builder = builder::Builder::new(Self::table_name());
         if Self::SOFT_DELETES {
-            builder = builder.where_null("deleted_at");
+            builder = builder.with_global_scope(scope::SOFT_DELETES, scope::SoftDeletingScope);
+        }
+        for (name, global) in Self::global_scopes() {
+            builder = builder.with_global_scope(name, global);
         }
         builder
     }
 
+    /// Query including soft deleted rows (other global scopes still apply)
     fn with_trashed() -> builder::Builder<Self> {
-        builder::Builder::new(Self::table_name())
+        Self::query().without_global_scope(scope::SOFT_DELETES)
     }
 
-    async fn all(manager: &DatabaseManager) -> Result<Vec<Self>, sqlx::Error> {
-        Self::query().get(manager).await
+    /// Query only the soft deleted rows
+    fn only_trashed() -> builder::Builder<Self> {
+        Self::with_trashed().where_not_null("deleted_at")
     }
 
-    async fn find(manager: &DatabaseManager, id: i64) -> Result<Option<Self>, sqlx::Error> {
-        Self::query().where_eq(Self::primary_key(), id).first(manager).await
+    async fn all<C: ConnectionResolver>(db: &C) -> Result<Vec<Self>, sqlx::Error> {
+        Self::query().get(db).await
     }
 
-    async fn find_or_fail(manager: &DatabaseManager, id: i64) -> Result<Self, sqlx::Error> {
-        match Self::find(manager, id).await? {
+    async fn find<C: ConnectionResolver>(db: &C, id: i64) -> Result<Option<Self>, sqlx::Error> {
+        Self::query().where_eq(Self::primary_key(), id).first(db).await
+    }
+
+    async fn find_or_fail<C: ConnectionResolver>(db: &C, id: i64) -> Result<Self, sqlx::Error> {
+        match Self::find(db, id).await? {
             Some(model) => Ok(model),
             None => Err(sqlx::Error::RowNotFound),
         }
     }
 
-    async fn create<D>(manager: &DatabaseManager, data: D) -> Result<u64, sqlx::Error>
+    async fn create<D, C: ConnectionResolver>(db: &C, data: D) -> Result<u64, sqlx::Error>
     where D: serde::Serialize + Send + Sync
     {
-        let pool = manager.connection(Self::connection()).ok_or(sqlx::Error::Configuration("No database connection found".into()))?;
-        let mut value = serde_json::to_value(data).unwrap();
-
-        if let Some(obj) = value.as_object_mut() {
-            if Self::TIMESTAMPS {
-                let now = chrono::Local::now().naive_local().to_string();
-                obj.insert("created_at".to_string(), serde_json::Value::String(now.clone()));
-                obj.insert("updated_at".to_string(), serde_json::Value::String(now));
-            }
+        let conn = db.resolve(Self::connection())?;
+        let grammar = conn.grammar();
+        let mut attributes = fill::<Self, D>(data)?;
+        let model_casts = Self::casts();
+
+        if Self::TIMESTAMPS {
+            attributes.remove("created_at");
+            attributes.remove("updated_at");
         }
 
-        let object = value.as_object().expect("Data must be an object");
+        let observers = Self::observers();
+        let mut payload = serde_json::Value::Object(attributes.clone());
+        fire(&observers, ModelEvent::Saving, Self::table_name(), &payload).await?;
+        fire(&observers, ModelEvent::Creating, Self::table_name(), &payload).await?;
 
         let mut keys = Vec::new();
-        let mut placeholders = Vec::new();
         let mut args = DbArguments::default();
 
-        for (k, v) in object {
+        for (k, v) in attributes.iter() {
             keys.push(k.clone());
-            placeholders.push("?");
-
-            match v {
-                serde_json::Value::String(s) => args.add(s),
-                serde_json::Value::Number(n) => {
-                    if n.is_i64() { args.add(n.as_i64()); }
-                    else if n.is_u64() { args.add(n.as_u64()); }
-                    else if n.is_f64() { args.add(n.as_f64()); }
-                },
-                serde_json::Value::Bool(b) => args.add(b),
-                serde_json::Value::Null => args.add(Option::<String>::None),
-                _ => args.add(v.to_string()),
-            }
+            casts::bind_attribute(&mut args, v, casts::cast_for(&model_casts, k));
         }
 
-        let sql = format!("INSERT INTO {} ({}) VALUES ({})",
-            Self::table_name(),
-            keys.join(", "),
-            placeholders.join(", ")
-        );
+        if Self::TIMESTAMPS {
+            let now = chrono::Local::now().naive_local();
+            keys.push("created_at".to_string());
+            args.add(db_datetime(now));
+            keys.push("updated_at".to_string());
+            args.add(db_datetime(now));
+        }
+
+        let sql = grammar.compile_insert(Self::table_name(), &keys, Self::primary_key());
+
+        let id = if grammar.uses_returning() {
+            let row = conn.fetch_one_row(sqlx::query_with(&sql, args)).await?;
+            let id: i64 = row.try_get(0)?;
+            id as u64
+        } else {
+            let res = conn.execute(sqlx::query_with(&sql, args)).await?;
+            crate::database::last_insert_id(&res)
+        };
 
-        let res = sqlx::query_with(&sql, args).execute(pool).await?;
-        Ok(res.last_insert_id())
+        payload[Self::primary_key()] = serde_json::json!(id);
+        fire(&observers, ModelEvent::Created, Self::table_name(), &payload).await?;
+        fire(&observers, ModelEvent::Saved, Self::table_name(), &payload).await?;
+        Ok(id)
     }
 
-    async fn update<D>(&self, manager: &DatabaseManager, data: D) -> Result<u64, sqlx::Error>
+    /// Insert many rows using as few statements as the backend's parameter limit allows.
+    /// Returns the number of inserted rows.
+    /// User::insert_many(&db, &[json!({ "name": "Ada" }), json!({ "name": "Bob" })]).await?
+    async fn insert_many<D, C: ConnectionResolver>(db: &C, rows: &[D]) -> Result<u64, sqlx::Error>
     where D: serde::Serialize + Send + Sync
     {
-        let pool = manager.connection(Self::connection()).ok_or(sqlx::Error::Configuration("No database connection found".into()))?;
-        let mut value = serde_json::to_value(data).unwrap();
+        insert_rows::<Self, D, C>(db, rows, &[], &[]).await
+    }
 
-        if let Some(obj) = value.as_object_mut() {
-            if Self::TIMESTAMPS {
-                let now = chrono::Local::now().naive_local().to_string();
-                obj.insert("updated_at".to_string(), serde_json::Value::String(now));
-            }
+    /// Insert rows, updating the `update` columns of rows that already exist on `unique_by`.
+    /// An empty `update` refreshes every inserted column. Postgres and SQLite need a unique
+    /// index on `unique_by`; MySQL matches on any unique key of the table.
+    /// Note: MySQL counts an updated row as 2 affected rows.
+    /// Product::upsert(&db, &rows, &["sku"], &["price", "stock"]).await?
+    async fn upsert<D, C: ConnectionResolver>(db: &C, rows: &[D], unique_by: &[&str], update: &[&str]) -> Result<u64, sqlx::Error>
+    where D: serde::Serialize + Send + Sync
+    {
+        insert_rows::<Self, D, C>(db, rows, unique_by, update).await
+    }
+
+    async fn update<D, C: ConnectionResolver>(&self, db: &C, data: D) -> Result<u64, sqlx::Error>
+    where D: serde::Serialize + Send + Sync
+    {
+        let conn = db.resolve(Self::connection())?;
+        let mut attributes = fill::<Self, D>(data)?;
+        let model_casts = Self::casts();
+
+        if Self::TIMESTAMPS {
+            attributes.remove("updated_at");
+        } else if attributes.is_empty() {
+            // Every key was filtered out and there's no timestamp to touch
+            return Ok(0);
         }
 
-        let object = value.as_object().expect("Data must be an object");
+        // Observers see the model with the changes applied
+        let observers = Self::observers();
+        let mut payload = self.attributes();
+        if let serde_json::Value::Object(model) = &mut payload {
+            model.extend(attributes.clone());
+        }
+        fire(&observers, ModelEvent::Saving, Self::table_name(), &payload).await?;
+        fire(&observers, ModelEvent::Updating, Self::table_name(), &payload).await?;
 
         let mut updates = Vec::new();
         let mut args = DbArguments::default();
 
-        for (k, v) in object {
-            updates.push(format!("{} = ?", k));
-            match v {
-                serde_json::Value::String(s) => args.add(s),
-                serde_json::Value::Number(n) => {
-                    if n.is_i64() { args.add(n.as_i64()); }
-                    else if n.is_u64() { args.add(n.as_u64()); }
-                    else if n.is_f64() { args.add(n.as_f64()); }
-                },
-                serde_json::Value::Bool(b) => args.add(b),
-                serde_json::Value::Null => args.add(Option::<String>::None),
-                _ => args.add(v.to_string()),
-            }
+        for (k, v) in attributes.iter() {
+            updates.push(k.clone());
+            casts::bind_attribute(&mut args, v, casts::cast_for(&model_casts, k));
+        }
+
+        if Self::TIMESTAMPS {
+            updates.push("updated_at".to_string());
+            args.add(crate::database::now());
         }
 
         // Add ID for WHERE clause
         args.add(self.id());
 
-        let sql = format!("UPDATE {} SET {} WHERE {} = ?",
-            Self::table_name(),
-            updates.join(", "),
-            Self::primary_key()
-        );
+        let sql = conn.grammar().compile_update(Self::table_name(), &updates, Self::primary_key());
 
-        let res = sqlx::query_with(&sql, args).execute(pool).await?;
+        let res = conn.execute(sqlx::query_with(&sql, args)).await?;
+        fire(&observers, ModelEvent::Updated, Self::table_name(), &payload).await?;
+        fire(&observers, ModelEvent::Saved, Self::table_name(), &payload).await?;
         Ok(res.rows_affected())
     }
 
-    async fn delete(&self, manager: &DatabaseManager) -> Result<u64, sqlx::Error> {
-        let pool = manager.connection(Self::connection()).ok_or(sqlx::Error::Configuration("No database connection found".into()))?;
+    async fn delete<C: ConnectionResolver>(&self, db: &C) -> Result<u64, sqlx::Error> {
+        let conn = db.resolve(Self::connection())?;
+        let grammar = conn.grammar();
+        let observers = Self::observers();
+        let payload = self.attributes();
+        fire(&observers, ModelEvent::Deleting, Self::table_name(), &payload).await?;
 
-        if Self::SOFT_DELETES {
-            let now = chrono::Local::now().naive_local().to_string();
-            let sql = format!("UPDATE {} SET deleted_at = ? WHERE {} = ?", Self::table_name(), Self::primary_key());
-            let res = sqlx::query(&sql)
-                .bind(now)
-                .bind(self.id())
-                .execute(pool)
-                .await?;
-            Ok(res.rows_affected())
+        let res = if Self::SOFT_DELETES {
+            let now = crate::database::now();
+            let sql = grammar.compile_update(Self::table_name(), &["deleted_at".to_string()], Self::primary_key());
+            conn.execute(sqlx::query(&sql).bind(now).bind(self.id())).await?
         } else {
-            let sql = format!("DELETE FROM {} WHERE {} = ?", Self::table_name(), Self::primary_key());
-            let res = sqlx::query(&sql)
-                .bind(self.id())
-                .execute(pool)
-                .await?;
-            Ok(res.rows_affected())
-        }
+            let sql = grammar.compile_delete(Self::table_name(), Self::primary_key());
+            conn.execute(sqlx::query(&sql).bind(self.id())).await?
+        };
+
+        fire(&observers, ModelEvent::Deleted, Self::table_name(), &payload).await?;
+        Ok(res.rows_affected())
     }
 
-    async fn force_delete(&self, manager: &DatabaseManager) -> Result<u64, sqlx::Error> {
-        let pool = manager.connection(Self::connection()).ok_or(sqlx::Error::Configuration("No database connection found".into()))?;
-        let sql = format!("DELETE FROM {} WHERE {} = ?", Self::table_name(), Self::primary_key());
-        let res = sqlx::query(&sql)
-            .bind(self.id())
-            .execute(pool)
-            .await?;
+    async fn force_delete<C: ConnectionResolver>(&self, db: &C) -> Result<u64, sqlx::Error> {
+        let conn = db.resolve(Self::connection())?;
+        let observers = Self::observers();
+        let payload = self.attributes();
+        fire(&observers, ModelEvent::Deleting, Self::table_name(), &payload).await?;
+
+        let sql = conn.grammar().compile_delete(Self::table_name(), Self::primary_key());
+        let res = conn.execute(sqlx::query(&sql).bind(self.id())).await?;
+
+        fire(&observers, ModelEvent::Deleted, Self::table_name(), &payload).await?;
         Ok(res.rows_affected())
     }
 
-    async fn restore(&self, manager: &DatabaseManager) -> Result<u64, sqlx::Error> {
+    async fn restore<C: ConnectionResolver>(&self, db: &C) -> Result<u64, sqlx::Error> {
         if !Self::SOFT_DELETES {
             return Ok(0);
         }
-        let pool = manager.connection(Self::connection()).ok_or(sqlx::Error::Configuration("No database connection found".into()))?;
-        let sql = format!("UPDATE {} SET deleted_at = NULL WHERE {} = ?", Self::table_name(), Self::primary_key());
-        let res = sqlx::query(&sql)
-            .bind(self.id())
-            .execute(pool)
-            .await?;
+        let conn = db.resolve(Self::connection())?;
+        let observers = Self::observers();
+        let mut payload = self.attributes();
+        fire(&observers, ModelEvent::Restoring, Self::table_name(), &payload).await?;
+
+        let grammar = conn.grammar();
+        let sql = grammar.parameterize(&format!(
+            "UPDATE {} SET {} = NULL WHERE {} = ?",
+            grammar.wrap_table(Self::table_name()),
+            grammar.wrap("deleted_at"),
+            grammar.wrap(Self::primary_key())
+        ));
+        let res = conn.execute(sqlx::query(&sql).bind(self.id())).await?;
+
+        if let serde_json::Value::Object(model) = &mut payload {
+            model.insert("deleted_at".to_string(), serde_json::Value::Null);
+        }
+        fire(&observers, ModelEvent::Restored, Self::table_name(), &payload).await?;
         Ok(res.rows_affected())
     }
 
@@ -208,10 +488,10 @@ pub trait Orbit: Sized + Send + Unpin + for<'r> FromRow<'r, DbRow> {
     /// Belongs To Relationship
     /// Example: Post belongs to User
     /// post.belongs_to::<User>(&pool, post.user_id).await
-    async fn belongs_to<R>(manager: &DatabaseManager, foreign_key_value: i64) -> Result<Option<R>, sqlx::Error>
+    async fn belongs_to<R, C: ConnectionResolver>(db: &C, foreign_key_value: i64) -> Result<Option<R>, sqlx::Error>
     where R: Orbit + Send + Unpin + for<'r> FromRow<'r, DbRow>
     {
-        R::find(manager, foreign_key_value).await
+        R::find(db, foreign_key_value).await
     }
 
     /// Has One Relationship
@@ -248,7 +528,7 @@ pub trait Orbit: Sized + Send + Unpin + for<'r> FromRow<'r, DbRow> {
     {
         R::query()
             .where_eq(id_column, self.id())
-            .where_eq(type_column, Self::table_name())
+            .where_eq(type_column, Self::table_name().to_string())
     }
 
     /// Morph Many Relationship
@@ -259,6 +539,873 @@ pub trait Orbit: Sized + Send + Unpin + for<'r> FromRow<'r, DbRow> {
     {
         R::query()
             .where_eq(id_column, self.id())
-            .where_eq(type_column, Self::table_name())
+            .where_eq(type_column, Self::table_name().to_string())
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::database::DatabaseManager;
+    use crate::orbit::schema::Schema;
+    use crate::support::testing::memory_database;
+    use serde_json::json;
+
+    #[derive(Debug, sqlx::FromRow, serde::Serialize)]
+    struct Post {
+        id: i64,
+        title: String,
+        views: i64,
+    }
+
+    impl Orbit for Post {
+        fn table_name() -> &'static str {
+            "posts"
+        }
+
+        fn id(&self) -> i64 {
+            self.id
+        }
+
+        const TIMESTAMPS: bool = false;
+
+        fn relation(name: &str) -> Option<Relation<Self>> {
+            match name {
+                "comments" => Some(Relation::has_many::<Comment>("post_id")),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Debug, sqlx::FromRow, serde::Serialize)]
+    struct User {
+        id: i64,
+        name: String,
+    }
+
+    impl Orbit for User {
+        fn table_name() -> &'static str {
+            "users"
+        }
+
+        fn id(&self) -> i64 {
+            self.id
+        }
+
+        const TIMESTAMPS: bool = false;
+
+        fn relation(name: &str) -> Option<Relation<Self>> {
+            match name {
+                "posts" => Some(Relation::has_many::<Post>("user_id")),
+                "roles" => Some(Relation::belongs_to_many::<Role>("role_user", "user_id", "role_id")),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Debug, sqlx::FromRow, serde::Serialize)]
+    struct Comment {
+        id: i64,
+        post_id: i64,
+        body: String,
+    }
+
+    impl Orbit for Comment {
+        fn table_name() -> &'static str {
+            "comments"
+        }
+
+        fn id(&self) -> i64 {
+            self.id
+        }
+
+        const TIMESTAMPS: bool = false;
+
+        fn relation(name: &str) -> Option<Relation<Self>> {
+            match name {
+                "post" => Some(Relation::belongs_to::<Post>(|comment| comment.post_id)),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Debug, sqlx::FromRow, serde::Serialize)]
+    struct Role {
+        id: i64,
+        name: String,
+    }
+
+    impl Orbit for Role {
+        fn table_name() -> &'static str {
+            "roles"
+        }
+
+        fn id(&self) -> i64 {
+            self.id
+        }
+
+        const TIMESTAMPS: bool = false;
+    }
+
+    #[derive(Debug, sqlx::FromRow)]
+    struct Draft {
+        id: i64,
+    }
+
+    impl Orbit for Draft {
+        fn table_name() -> &'static str {
+            "drafts"
+        }
+
+        fn id(&self) -> i64 {
+            self.id
+        }
+
+        const TIMESTAMPS: bool = false;
+        const SOFT_DELETES: bool = true;
+    }
+
+    /// Scoped to tenant 1 and soft deleting, like a multi-tenant app would
+    #[derive(Debug, sqlx::FromRow)]
+    struct Invoice {
+        id: i64,
+        tenant_id: i64,
+    }
+
+    impl Orbit for Invoice {
+        fn table_name() -> &'static str {
+            "invoices"
+        }
+
+        fn id(&self) -> i64 {
+            self.id
+        }
+
+        fn global_scopes() -> Vec<(&'static str, Box<dyn Scope<Self>>)> {
+            vec![("tenant", Box::new(|q: builder::Builder<Self>| q.where_eq("tenant_id", 1)))]
+        }
+
+        const TIMESTAMPS: bool = false;
+        const SOFT_DELETES: bool = true;
+    }
+
+    #[derive(Debug, Orbit)]
+    #[orbit(table = "accounts", soft_deletes, timestamps = false, connection = "reporting")]
+    struct Account {
+        #[orbit(primary_key)]
+        account_id: i64,
+        email: String,
+        #[orbit(hidden)]
+        api_token: String,
+        #[orbit(skip)]
+        unread: i64,
+    }
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Plan {
+        Free,
+        Pro,
+    }
+
+    #[derive(Debug, Orbit)]
+    #[orbit(table = "members", timestamps = false)]
+    struct Member {
+        id: i64,
+        #[orbit(fillable)]
+        name: String,
+        #[orbit(fillable, cast = "json")]
+        settings: serde_json::Value,
+        #[orbit(fillable, cast = "bool")]
+        active: bool,
+        #[orbit(fillable, cast = "datetime")]
+        joined_at: Option<chrono::DateTime<chrono::Utc>>,
+        #[orbit(fillable, cast = "enum")]
+        plan: Plan,
+        #[orbit(fillable, cast = "encrypted", hidden)]
+        ssn: String,
+        is_admin: bool,
+    }
+
+    static NOTE_EVENTS: std::sync::Mutex<Vec<(String, serde_json::Value)>> = std::sync::Mutex::new(Vec::new());
+
+    struct NoteObserver;
+
+    #[async_trait]
+    impl crate::models::Observer for NoteObserver {
+        async fn creating(&self, model: &serde_json::Value) -> Result<(), Box<dyn std::error::Error>> {
+            if model["body"] == "spam" {
+                return Err("spam is not allowed".into());
+            }
+            NOTE_EVENTS.lock().unwrap().push(("creating".to_string(), model.clone()));
+            Ok(())
+        }
+
+        async fn created(&self, model: &serde_json::Value) -> Result<(), Box<dyn std::error::Error>> {
+            NOTE_EVENTS.lock().unwrap().push(("created".to_string(), model.clone()));
+            Ok(())
+        }
+
+        async fn updated(&self, model: &serde_json::Value) -> Result<(), Box<dyn std::error::Error>> {
+            NOTE_EVENTS.lock().unwrap().push(("updated".to_string(), model.clone()));
+            Ok(())
+        }
+
+        async fn deleting(&self, model: &serde_json::Value) -> Result<(), Box<dyn std::error::Error>> {
+            if model["pinned"] == true {
+                return Err("pinned notes can't be deleted".into());
+            }
+            Ok(())
+        }
+
+        async fn restored(&self, model: &serde_json::Value) -> Result<(), Box<dyn std::error::Error>> {
+            NOTE_EVENTS.lock().unwrap().push(("restored".to_string(), model.clone()));
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Orbit)]
+    #[orbit(table = "notes", timestamps = false, soft_deletes, observable)]
+    struct Note {
+        id: i64,
+        body: String,
+        #[orbit(cast = "bool")]
+        pinned: bool,
+    }
+
+    impl crate::models::Observable for Note {
+        fn observers() -> Vec<Box<dyn crate::models::Observer>> {
+            vec![Box::new(NoteObserver)]
+        }
+    }
+
+    struct Large;
+
+    impl Scope<Invoice> for Large {
+        fn apply(&self, query: builder::Builder<Invoice>) -> builder::Builder<Invoice> {
+            query.r#where("total", ">", 100)
+        }
+    }
+
+    async fn execute(db: &DatabaseManager, sql: &str) {
+        sqlx::query(sql).execute(db.default_connection().unwrap()).await.unwrap();
+    }
+
+    async fn setup() -> DatabaseManager {
+        let manager = memory_database().await;
+        let sql = Schema::new().create("posts", |table| {
+            table.id();
+            table.integer("user_id").nullable();
+            table.string("title");
+            table.integer("views").default("0");
+        }).to_sql().unwrap();
+        execute(&manager, &sql).await;
+        manager
+    }
+
+    /// Two users with posts, comments and roles for the eager loading tests
+    async fn setup_relations() -> DatabaseManager {
+        let db = setup().await;
+        execute(&db, &Schema::new().create("users", |table| {
+            table.id();
+            table.string("name");
+        }).to_sql().unwrap()).await;
+        execute(&db, &Schema::new().create("comments", |table| {
+            table.id();
+            table.integer("post_id");
+            table.string("body");
+        }).to_sql().unwrap()).await;
+        execute(&db, &Schema::new().create("roles", |table| {
+            table.id();
+            table.string("name");
+        }).to_sql().unwrap()).await;
+        execute(&db, &Schema::new().create("role_user", |table| {
+            table.integer("user_id");
+            table.integer("role_id");
+        }).to_sql().unwrap()).await;
+
+        for name in ["ada", "bob"] {
+            User::create(&db, json!({ "name": name })).await.unwrap();
+        }
+        for (user_id, title) in [(1, "first"), (1, "second"), (2, "third")] {
+            Post::create(&db, json!({ "user_id": user_id, "title": title })).await.unwrap();
+        }
+        for (post_id, body) in [(1, "nice"), (1, "great"), (3, "meh")] {
+            Comment::create(&db, json!({ "post_id": post_id, "body": body })).await.unwrap();
+        }
+        for name in ["admin", "editor"] {
+            Role::create(&db, json!({ "name": name })).await.unwrap();
+        }
+        execute(&db, "INSERT INTO role_user (user_id, role_id) VALUES (1, 1), (1, 2), (2, 2)").await;
+        db
+    }
+
+    #[tokio::test]
+    async fn test_create_and_find() {
+        let db = setup().await;
+
+        let id = Post::create(&db, json!({ "title": "Hello", "views": 3 })).await.unwrap();
+        let post = Post::find_or_fail(&db, id as i64).await.unwrap();
+
+        assert_eq!(post.title, "Hello");
+        assert_eq!(post.views, 3);
+    }
+
+    #[tokio::test]
+    async fn test_update_and_delete() {
+        let db = setup().await;
+
+        let id = Post::create(&db, json!({ "title": "Draft" })).await.unwrap();
+        let post = Post::find_or_fail(&db, id as i64).await.unwrap();
+
+        post.update(&db, json!({ "title": "Published" })).await.unwrap();
+        let posts = Post::query().where_eq("title", "Published".to_string()).get(&db).await.unwrap();
+        assert_eq!(posts.len(), 1);
+
+        post.delete(&db).await.unwrap();
+        assert!(Post::find(&db, id as i64).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_aggregates() {
+        let db = setup().await;
+        for (title, views) in [("a", 10), ("b", 20), ("c", 30)] {
+            Post::create(&db, json!({ "title": title, "views": views })).await.unwrap();
+        }
+
+        assert_eq!(Post::query().count(&db).await.unwrap(), 3);
+        assert_eq!(Post::query().r#where("views", ">", 15_i64).count(&db).await.unwrap(), 2);
+        assert_eq!(Post::query().sum(&db, "views").await.unwrap(), 60.0);
+        assert_eq!(Post::query().avg(&db, "views").await.unwrap(), Some(20.0));
+        assert_eq!(Post::query().min::<i64>(&db, "views").await.unwrap(), Some(10));
+        assert_eq!(Post::query().max::<i64>(&db, "views").await.unwrap(), Some(30));
+        assert_eq!(Post::query().where_eq("title", "z".to_string()).sum(&db, "views").await.unwrap(), 0.0);
+        assert!(Post::query().where_eq("title", "b".to_string()).exists(&db).await.unwrap());
+        assert!(Post::query().where_eq("title", "z".to_string()).doesnt_exist(&db).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_paginate_with_huge_values() {
+        let db = setup().await;
+        Post::create(&db, json!({ "title": "a", "views": 1 })).await.unwrap();
+
+        let page = Post::query().paginate(&db, i64::MAX, i64::MAX).await.unwrap();
+        assert!(page.data.is_empty());
+        assert_eq!((page.total, page.last_page), (Some(1), Some(1)));
+
+        let page = Post::query().cursor_paginate::<i64>(&db, "id", None, i64::MAX).await.unwrap();
+        assert_eq!(page.data.len(), 1);
+        assert!(!page.has_more);
+    }
+
+    #[tokio::test]
+    async fn test_grouped_count() {
+        let db = setup().await;
+        for (title, views) in [("a", 1), ("a", 2), ("b", 3)] {
+            Post::create(&db, json!({ "title": title, "views": views })).await.unwrap();
+        }
+
+        let groups = Post::query()
+            .select(&["title"])
+            .group_by(&["title"])
+            .having("COUNT(*)", ">", 1_i64)
+            .count(&db)
+            .await
+            .unwrap();
+        assert_eq!(groups, 1);
+    }
+
+    #[tokio::test]
+    async fn test_eager_load_nested_has_many() {
+        let db = setup_relations().await;
+
+        let users = User::query().with("posts.comments").oldest("id").get_loaded(&db).await.unwrap();
+        assert_eq!(users.len(), 2);
+
+        let posts = users[0].many::<Post>("posts");
+        assert_eq!(posts.iter().map(|p| p.title.as_str()).collect::<Vec<_>>(), vec!["first", "second"]);
+        assert_eq!(posts[0].many::<Comment>("comments").len(), 2);
+        assert!(posts[1].relation_loaded("comments"));
+        assert!(posts[1].many::<Comment>("comments").is_empty());
+
+        let posts = users[1].many::<Post>("posts");
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].many::<Comment>("comments")[0].body, "meh");
+    }
+
+    #[tokio::test]
+    async fn test_eager_load_splits_keys_over_the_parameter_limit() {
+        let db = setup_relations().await;
+        // More parents than SQLite accepts bind parameters in one statement
+        execute(&db, "WITH RECURSIVE n(i) AS (SELECT 3 UNION ALL SELECT i + 1 FROM n WHERE i < 33000) \
+                      INSERT INTO users (id, name) SELECT i, 'user' FROM n;").await;
+        execute(&db, "INSERT INTO posts (user_id, title, views) VALUES (33000, 'last', 0);").await;
+
+        let users = User::query().with("posts").oldest("id").get_loaded(&db).await.unwrap();
+        assert_eq!(users.len(), 33000);
+        assert_eq!(users[0].many::<Post>("posts").len(), 2);
+        assert_eq!(users[32999].many::<Post>("posts")[0].title, "last");
+    }
+
+    #[tokio::test]
+    async fn test_eager_load_belongs_to_and_pivot() {
+        let db = setup_relations().await;
+
+        let comment = Comment::query().with("post").where_eq("body", "meh".to_string()).first_loaded(&db).await.unwrap().unwrap();
+        assert_eq!(comment.one::<Post>("post").unwrap().title, "third");
+
+        let users = User::query().with("roles").oldest("id").get_loaded(&db).await.unwrap();
+        let roles: Vec<_> = users[0].many::<Role>("roles").iter().map(|r| r.name.clone()).collect();
+        assert_eq!(roles.len(), 2);
+        assert!(roles.contains(&"admin".to_string()));
+        assert_eq!(users[1].many::<Role>("roles")[0].name, "editor");
+    }
+
+    #[tokio::test]
+    async fn test_eager_load_serializes_relations() {
+        let db = setup_relations().await;
+
+        let user = User::query().with("posts").oldest("id").first_loaded(&db).await.unwrap().unwrap();
+        let json = serde_json::to_value(&user).unwrap();
+        assert_eq!(json["name"], "ada");
+        assert_eq!(json["posts"].as_array().unwrap().len(), 2);
+        assert_eq!(json["posts"][0]["title"], "first");
+
+        let missing = User::query().with("avatar").get_loaded(&db).await;
+        assert!(missing.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_eager_load_pages_and_rejects_plain_fetches() {
+        let db = setup_relations().await;
+
+        let page = User::query().with("posts").oldest("id").paginate_loaded(&db, 1, 1).await.unwrap();
+        assert_eq!(page.total, Some(2));
+        assert_eq!(page.data[0].many::<Post>("posts").len(), 2);
+
+        let page = User::query().with("posts").cursor_paginate_loaded(&db, "id", Some(1_i64), 10).await.unwrap();
+        assert_eq!(page.data.len(), 1);
+        assert_eq!(page.data[0].many::<Post>("posts")[0].title, "third");
+
+        assert!(User::query().with("posts").get(&db).await.is_err());
+        assert!(User::query().with("posts").paginate(&db, 1, 10).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bulk_update_and_increment() {
+        let db = setup().await;
+        let rows = vec![json!({ "title": "a", "views": 1 }), json!({ "title": "b", "views": 2 }), json!({ "title": "c", "views": 0 })];
+        assert_eq!(Post::insert_many(&db, &rows).await.unwrap(), 3);
+
+        let updated = Post::query().where_in("title", vec!["a".to_string(), "b".to_string()]).update(&db, json!({ "views": 10 })).await.unwrap();
+        assert_eq!(updated, 2);
+
+        assert_eq!(Post::query().where_eq("views", 10_i64).increment(&db, "views", 5_i64).await.unwrap(), 2);
+        assert_eq!(Post::query().where_eq("title", "c".to_string()).decrement(&db, "views", 1_i64).await.unwrap(), 1);
+        assert_eq!(Post::query().sum(&db, "views").await.unwrap(), 29.0);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_delete_respects_soft_deletes() {
+        let db = setup().await;
+        execute(&db, &Schema::new().create("drafts", |table| {
+            table.id();
+            table.string("title");
+            table.timestamp("deleted_at").nullable();
+        }).to_sql().unwrap()).await;
+        let rows = vec![json!({ "title": "a" }), json!({ "title": "a" }), json!({ "title": "b" })];
+        Draft::insert_many(&db, &rows).await.unwrap();
+
+        assert_eq!(Draft::query().where_eq("title", "a".to_string()).delete(&db).await.unwrap(), 2);
+        assert_eq!(Draft::query().count(&db).await.unwrap(), 1);
+        assert_eq!(Draft::with_trashed().count(&db).await.unwrap(), 3);
+
+        assert_eq!(Draft::with_trashed().where_not_null("deleted_at").force_delete(&db).await.unwrap(), 2);
+        assert_eq!(Draft::with_trashed().count(&db).await.unwrap(), 1);
+
+        // Limited deletes go through a key subquery
+        Post::insert_many(&db, &[json!({ "title": "x" }), json!({ "title": "y" })]).await.unwrap();
+        assert_eq!(Post::query().oldest("id").limit(1).delete(&db).await.unwrap(), 1);
+        assert_eq!(Post::query().first(&db).await.unwrap().unwrap().title, "y");
+    }
+
+    #[tokio::test]
+    async fn test_global_and_local_scopes() {
+        let db = setup().await;
+        execute(&db, &Schema::new().create("invoices", |table| {
+            table.id();
+            table.integer("tenant_id");
+            table.integer("total");
+            table.timestamp("deleted_at").nullable();
+        }).to_sql().unwrap()).await;
+        execute(&db, "INSERT INTO invoices (tenant_id, total) VALUES (1, 50), (1, 150), (1, 500), (2, 900)").await;
+
+        assert_eq!(Invoice::query().count(&db).await.unwrap(), 3);
+        assert!(Invoice::find(&db, 4).await.unwrap().is_none());
+        assert_eq!(Invoice::query().without_global_scope("tenant").count(&db).await.unwrap(), 4);
+        assert_eq!(Invoice::query().scope(Large).count(&db).await.unwrap(), 2);
+
+        // Soft deletes are a global scope like any other
+        Invoice::query().scope(Large).delete(&db).await.unwrap();
+        assert_eq!(Invoice::query().count(&db).await.unwrap(), 1);
+        assert_eq!(Invoice::with_trashed().count(&db).await.unwrap(), 3);
+        assert_eq!(Invoice::only_trashed().count(&db).await.unwrap(), 2);
+        assert_eq!(Invoice::query().without_global_scopes().count(&db).await.unwrap(), 4);
+
+        let other = Invoice::query()
+            .without_global_scope("tenant")
+            .scope(|q: builder::Builder<Invoice>| q.where_eq("tenant_id", 2))
+            .first(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((other.id, other.tenant_id), (4, 2));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(feature = "any", ignore = "declares BOOLEAN/TIMESTAMP columns, which the any driver can't read")]
+    async fn test_derived_model() {
+        assert_eq!(Account::table_name(), "accounts");
+        assert_eq!(Account::primary_key(), "account_id");
+        assert_eq!(Account::connection(), Some("reporting"));
+        assert!(Account::SOFT_DELETES && !Account::TIMESTAMPS);
+        assert_eq!(Account::EMAIL.name(), "email");
+
+        let manager = memory_database().await;
+        let db = manager.default_connection().unwrap().clone();
+        sqlx::query("CREATE TABLE accounts (account_id INTEGER PRIMARY KEY AUTOINCREMENT, email TEXT, api_token TEXT, deleted_at TIMESTAMP NULL)")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let id = Account::create(&db, json!({ "email": "ada@example.com", "api_token": "secret" })).await.unwrap();
+        let account = Account::query().where_eq(&Account::EMAIL, "ada@example.com".to_string()).first(&db).await.unwrap().unwrap();
+        assert_eq!((account.id(), account.unread), (id as i64, 0));
+        assert_eq!(account.to_json(), json!({ "account_id": id, "email": "ada@example.com", "unread": 0 }));
+
+        Account::query().delete(&db).await.unwrap();
+        assert!(Account::find(&db, id as i64).await.unwrap().is_none());
+        assert_eq!(Account::with_trashed().count(&db).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(feature = "any", ignore = "declares BOOLEAN/TIMESTAMP columns, which the any driver can't read")]
+    async fn test_fillable_casts_and_hidden() {
+        std::env::set_var("APP_KEY", "orbit-test-key");
+        let db = setup().await;
+        execute(&db, "CREATE TABLE members (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT, settings TEXT, active BOOLEAN, \
+                      joined_at TIMESTAMP NULL, plan TEXT, ssn TEXT, is_admin BOOLEAN NOT NULL DEFAULT 0)").await;
+
+        let id = Member::create(&db, json!({
+            "name": "ada",
+            "settings": { "theme": "dark" },
+            "active": "yes",
+            "joined_at": "2024-05-01T12:00:00+02:00",
+            "plan": "pro",
+            "ssn": "123-45-6789",
+            "is_admin": true,
+        })).await.unwrap() as i64;
+
+        let member = Member::find_or_fail(&db, id).await.unwrap();
+        assert_eq!(member.settings, json!({ "theme": "dark" }));
+        assert!(member.active && !member.is_admin);
+        assert_eq!(member.joined_at.unwrap().to_rfc3339(), "2024-05-01T10:00:00+00:00");
+        assert_eq!((member.plan, member.ssn.as_str()), (Plan::Pro, "123-45-6789"));
+
+        let stored: String = sqlx::query_scalar("SELECT ssn FROM members")
+            .fetch_one(db.default_connection().unwrap())
+            .await
+            .unwrap();
+        assert_ne!(stored, "123-45-6789");
+
+        let member = Member::find_or_fail(&db, id).await.unwrap();
+        let json = serde_json::to_value(&member).unwrap();
+        assert_eq!(json["plan"], "pro");
+        assert!(json.get("ssn").is_none());
+
+        let invalid = Member::create(&db, json!({ "name": "bob", "plan": "gold" })).await;
+        assert!(invalid.is_err());
+        assert!(Member::create(&db, json!(["bob"])).await.is_err());
+
+        assert_eq!(member.update(&db, json!({ "is_admin": true })).await.unwrap(), 0);
+        member.update(&db, json!({ "is_admin": true, "active": 0 })).await.unwrap();
+        Member::query().update(&db, json!({ "is_admin": true })).await.unwrap();
+        let member = Member::find_or_fail(&db, id).await.unwrap();
+        assert!(!member.active && !member.is_admin);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(feature = "any", ignore = "declares BOOLEAN/TIMESTAMP columns, which the any driver can't read")]
+    async fn test_observers_receive_models_and_can_abort() {
+        let db = setup().await;
+        execute(&db, "CREATE TABLE notes (id INTEGER PRIMARY KEY AUTOINCREMENT, body TEXT, pinned BOOLEAN NOT NULL DEFAULT 0, deleted_at TIMESTAMP NULL)").await;
+
+        let id = Note::create(&db, json!({ "body": "hello", "pinned": false })).await.unwrap() as i64;
+        assert!(Note::create(&db, json!({ "body": "spam" })).await.is_err());
+        assert_eq!(Note::query().count(&db).await.unwrap(), 1);
+
+        let note = Note::find_or_fail(&db, id).await.unwrap();
+        note.update(&db, json!({ "pinned": true })).await.unwrap();
+        let note = Note::find_or_fail(&db, id).await.unwrap();
+        assert!(note.delete(&db).await.is_err());
+        assert_eq!(Note::query().count(&db).await.unwrap(), 1);
+
+        note.update(&db, json!({ "pinned": false })).await.unwrap();
+        let note = Note::find_or_fail(&db, id).await.unwrap();
+        note.delete(&db).await.unwrap();
+        note.restore(&db).await.unwrap();
+
+        let events = NOTE_EVENTS.lock().unwrap().clone();
+        let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["creating", "created", "updated", "updated", "restored"]);
+        assert_eq!(events[0].1, json!({ "body": "hello", "pinned": false }));
+        assert_eq!(events[1].1, json!({ "id": id, "body": "hello", "pinned": false }));
+        assert_eq!(events[2].1, json!({ "id": id, "body": "hello", "pinned": true }));
+        assert_eq!(events[4].1["id"], id);
+    }
+
+    #[tokio::test]
+    async fn test_upsert() {
+        let db = setup().await;
+        execute(&db, &Schema::new().create("roles", |table| {
+            table.id();
+            table.string("name").unique();
+            table.integer("level").default("0");
+        }).to_sql().unwrap()).await;
+        Role::insert_many(&db, &[json!({ "name": "admin", "level": 1 }), json!({ "name": "editor", "level": 2 })]).await.unwrap();
+
+        let rows = vec![json!({ "name": "admin", "level": 9 }), json!({ "name": "guest", "level": 1 })];
+        Role::upsert(&db, &rows, &["name"], &["level"]).await.unwrap();
+
+        assert_eq!(Role::query().count(&db).await.unwrap(), 3);
+        assert_eq!(Role::query().where_eq("name", "admin".to_string()).max::<i64>(&db, "level").await.unwrap(), Some(9));
+    }
+
+    #[tokio::test]
+    async fn test_transaction_commits_and_rolls_back() {
+        let db = setup().await;
+
+        let id = db
+            .transaction(|tx| async move {
+                let id = Post::create(&tx, json!({ "title": "kept" })).await?;
+                Post::query().where_eq("id", id as i64).increment(&tx, "views", 1_i64).await?;
+                Ok::<_, sqlx::Error>(id)
+            })
+            .await
+            .unwrap();
+        assert_eq!(Post::find_or_fail(&db, id as i64).await.unwrap().views, 1);
+
+        let failed = db
+            .transaction(|tx| async move {
+                Post::create(&tx, json!({ "title": "discarded" })).await?;
+                Err::<(), _>(sqlx::Error::RowNotFound)
+            })
+            .await;
+        assert!(failed.is_err());
+
+        // A pool works wherever the manager does
+        let pool = db.default_connection().unwrap();
+        assert_eq!(Post::query().count(pool).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_nested_transaction_uses_savepoints() {
+        let db = setup().await;
+
+        db.transaction(|tx| async move {
+            Post::create(&tx, json!({ "title": "outer" })).await?;
+
+            let inner = tx
+                .transaction(|tx| async move {
+                    Post::create(&tx, json!({ "title": "inner" })).await?;
+                    Err::<(), _>(sqlx::Error::RowNotFound)
+                })
+                .await;
+            assert!(inner.is_err());
+
+            tx.transaction(|tx| async move {
+                Post::create(&tx, json!({ "title": "second inner" })).await.map(|_| ())
+            })
+            .await?;
+
+            assert_eq!(Post::query().count(&tx).await?, 2);
+            Ok::<_, sqlx::Error>(())
+        })
+        .await
+        .unwrap();
+
+        let titles: Vec<String> = Post::query().oldest("id").get(&db).await.unwrap().into_iter().map(|p| p.title).collect();
+        assert_eq!(titles, vec!["outer", "second inner"]);
+    }
+
+    #[tokio::test]
+    async fn test_paginate_counts_filtered_rows() {
+        let db = setup().await;
+        let rows: Vec<_> = (1..=7).map(|i| json!({ "title": if i % 2 == 0 { "even" } else { "odd" }, "views": i })).collect();
+        Post::insert_many(&db, &rows).await.unwrap();
+
+        let page = Post::query().where_eq("title", "odd".to_string()).oldest("id").paginate(&db, 2, 3).await.unwrap();
+        assert_eq!(page.total, Some(4));
+        assert_eq!(page.last_page, Some(2));
+        assert!(!page.has_more);
+        assert_eq!(page.data.iter().map(|p| p.views).collect::<Vec<_>>(), vec![7]);
+    }
+
+    #[tokio::test]
+    async fn test_cursor_paginate() {
+        use crate::orbit::pagination::decode_cursor;
+
+        let db = setup().await;
+        let rows: Vec<_> = (1..=5).map(|i| json!({ "title": "post", "views": i })).collect();
+        Post::insert_many(&db, &rows).await.unwrap();
+
+        let first = Post::query().cursor_paginate(&db, "id", None::<i64>, 2).await.unwrap();
+        assert_eq!(first.data.iter().map(|p| p.id).collect::<Vec<_>>(), vec![1, 2]);
+
+        let after = first.next_cursor.as_deref().and_then(decode_cursor::<i64>);
+        assert_eq!(after, Some(2));
+        let second = Post::query()
+            .where_eq("title", "post".to_string())
+            .or_where("views", ">", 100_i64)
+            .cursor_paginate(&db, "id", after, 2)
+            .await
+            .unwrap();
+        assert_eq!(second.data.iter().map(|p| p.id).collect::<Vec<_>>(), vec![3, 4]);
+
+        let last = Post::query().cursor_paginate(&db, "id", Some(4_i64), 2).await.unwrap();
+        assert_eq!(last.data.len(), 1);
+        assert!(!last.has_more);
+        assert_eq!(last.next_cursor, None);
+
+        let backwards = Post::query().latest("id").cursor_paginate(&db, "id", Some(3_i64), 5).await.unwrap();
+        assert_eq!(backwards.data.iter().map(|p| p.id).collect::<Vec<_>>(), vec![2, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_chunk_and_chunk_by_id() {
+        let db = setup().await;
+        let rows: Vec<_> = (1..=5).map(|i| json!({ "title": "post", "views": i })).collect();
+        Post::insert_many(&db, &rows).await.unwrap();
+
+        let mut sizes = Vec::new();
+        Post::query()
+            .chunk(&db, 2, |batch| {
+                sizes.push(batch.len());
+                async { Ok::<_, sqlx::Error>(()) }
+            })
+            .await
+            .unwrap();
+        assert_eq!(sizes, vec![2, 2, 1]);
+
+        // Renaming the rows being filtered on must not skip any of them
+        let mut seen = Vec::new();
+        Post::query()
+            .where_eq("title", "post".to_string())
+            .or_where("title", "=", "other".to_string())
+            .chunk_by_id(&db, 2, |batch| {
+                seen.extend(batch.iter().map(|p| p.id));
+                let db = db.clone();
+                async move {
+                    let ids: Vec<i64> = batch.iter().map(|p| p.id).collect();
+                    Post::query().where_in("id", ids).update(&db, json!({ "title": "done" })).await?;
+                    Ok::<_, sqlx::Error>(())
+                }
+            })
+            .await
+            .unwrap();
+        assert_eq!(seen, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        use futures::StreamExt;
+
+        let db = setup().await;
+        let rows: Vec<_> = (1..=100).map(|i| json!({ "title": "post", "views": i })).collect();
+        Post::insert_many(&db, &rows).await.unwrap();
+
+        let mut stream = Post::query().r#where("views", ">", 10_i64).oldest("id").stream(&db);
+        let mut total = 0;
+        while let Some(post) = stream.next().await {
+            total += post.unwrap().views;
+        }
+        assert_eq!(total, (11..=100).sum::<i64>());
+
+        // Dropping a stream early releases the connection
+        let first = Post::query().stream(&db).next().await.unwrap().unwrap();
+        assert_eq!(first.id, 1);
+        assert_eq!(Post::query().count(&db).await.unwrap(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_stream_inside_a_transaction() {
+        use futures::StreamExt;
+
+        let db = setup().await;
+        // More rows than the stream buffer, so the reader can't finish ahead of the loop
+        let rows: Vec<_> = (1..=100).map(|i| json!({ "title": "post", "views": i })).collect();
+        Post::insert_many(&db, &rows).await.unwrap();
+
+        let run = db.transaction(|tx| async move {
+            let mut stream = Post::query().oldest("id").stream(&tx);
+            while let Some(post) = stream.next().await {
+                // Querying the same transaction mid-stream must not wait on the stream
+                Post::query().where_eq("id", post?.id).increment(&tx, "views", 10_i64).await?;
+            }
+            Ok::<_, sqlx::Error>(())
+        });
+        tokio::time::timeout(std::time::Duration::from_secs(5), run).await.expect("deadlocked").unwrap();
+
+        assert_eq!(Post::query().sum(&db, "views").await.unwrap(), (5050 + 100 * 10) as f64);
+    }
+
+    #[cfg(feature = "any")]
+    #[tokio::test]
+    async fn test_queries_follow_the_connection_grammar() {
+        use crate::orbit::Grammar;
+
+        let db = setup().await;
+        let pool = db.default_connection().unwrap();
+        // Read from the URL, and made the default by adding the default connection
+        assert_eq!(Grammar::of(pool), Grammar::Sqlite);
+        assert_eq!(Grammar::current(), Grammar::Sqlite);
+
+        // SQLite only hands back inserted keys through RETURNING here
+        let id = Post::create(&db, json!({ "title": "Hello" })).await.unwrap();
+        assert_eq!(Post::find_or_fail(&db, id as i64).await.unwrap().title, "Hello");
+
+        let tx = crate::database::Transaction::begin(pool).await.unwrap();
+        assert_eq!(crate::database::Connection::Transaction(tx.clone()).grammar(), Grammar::Sqlite);
+        tx.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_replicas_and_sticky_reads() {
+        let mut db = setup().await;
+        let replica = memory_database().await;
+        execute(&replica, &Schema::new().create("posts", |table| {
+            table.id();
+            table.integer("user_id").nullable();
+            table.string("title");
+            table.integer("views").default("0");
+        }).to_sql().unwrap()).await;
+        Post::create(&replica, json!({ "title": "from replica" })).await.unwrap();
+        db.add_read_replicas("sqlite", vec![replica.default_connection().unwrap().clone()], true);
+
+        // Reads go to the replica, writes to the primary
+        Post::create(&db, json!({ "title": "from primary" })).await.unwrap();
+        let posts = Post::query().get(&db).await.unwrap();
+        assert_eq!(posts[0].title, "from replica");
+
+        // After a write in the same scope, reads stick to the primary
+        let titles = crate::database::sticky_reads(async {
+            assert_eq!(Post::query().first(&db).await.unwrap().unwrap().title, "from replica");
+            Post::create(&db, json!({ "title": "second" })).await.unwrap();
+            Post::query().oldest("id").get(&db).await.unwrap()
+        }).await;
+        assert_eq!(titles.len(), 2);
+        assert_eq!(titles[0].title, "from primary");
+
+        assert_eq!(Post::query().count(&db).await.unwrap(), 1);
     }
 }
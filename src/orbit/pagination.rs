@@ -0,0 +1,158 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// A page of results from `Builder::paginate` or `Builder::cursor_paginate`.
+///
+/// Offset pages carry `current_page`, `total` and `last_page`; cursor pages
+/// carry an opaque `next_cursor` instead. Render it with `http::response::paginated`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Paginator<T> {
+    pub data: Vec<T>,
+    pub per_page: i64,
+    pub current_page: Option<i64>,
+    pub total: Option<i64>,
+    pub last_page: Option<i64>,
+    pub next_cursor: Option<String>,
+    pub has_more: bool,
+    #[serde(skip)]
+    path: String,
+}
+
+impl<T> Paginator<T> {
+    /// A length-aware page (`?page=N`)
+    pub fn new(data: Vec<T>, total: i64, per_page: i64, current_page: i64) -> Self {
+        let per_page = per_page.max(1);
+        // Rounded up without `total + per_page - 1`, which overflows for huge per_page values
+        let last_page = (total / per_page + i64::from(total % per_page != 0)).max(1);
+        Self {
+            data,
+            per_page,
+            current_page: Some(current_page),
+            total: Some(total),
+            last_page: Some(last_page),
+            next_cursor: None,
+            has_more: current_page < last_page,
+            path: String::new(),
+        }
+    }
+
+    /// A keyset page (`?cursor=...`); there is no total and no way back
+    pub fn cursor(data: Vec<T>, per_page: i64, next_cursor: Option<String>) -> Self {
+        Self {
+            data,
+            per_page,
+            current_page: None,
+            total: None,
+            last_page: None,
+            has_more: next_cursor.is_some(),
+            next_cursor,
+            path: String::new(),
+        }
+    }
+
+    /// The URL the page links are built on, e.g. "/api/posts".
+    /// Without it links are relative ("?page=2").
+    pub fn with_path(mut self, path: &str) -> Self {
+        self.path = path.to_string();
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn next_page_url(&self) -> Option<String> {
+        if let Some(cursor) = &self.next_cursor {
+            return Some(self.url("cursor", cursor));
+        }
+        match (self.current_page, self.has_more) {
+            (Some(page), true) => Some(self.url("page", &(page + 1).to_string())),
+            _ => None,
+        }
+    }
+
+    pub fn prev_page_url(&self) -> Option<String> {
+        match self.current_page {
+            Some(page) if page > 1 => Some(self.url("page", &(page - 1).to_string())),
+            _ => None,
+        }
+    }
+
+    /// Transform the items while keeping the page information
+    pub fn map<U, F: FnMut(T) -> U>(self, f: F) -> Paginator<U> {
+        Paginator {
+            data: self.data.into_iter().map(f).collect(),
+            per_page: self.per_page,
+            current_page: self.current_page,
+            total: self.total,
+            last_page: self.last_page,
+            next_cursor: self.next_cursor,
+            has_more: self.has_more,
+            path: self.path,
+        }
+    }
+
+    fn url(&self, key: &str, value: &str) -> String {
+        let separator = if self.path.contains('?') { '&' } else { '?' };
+        format!("{}{}{}={}", self.path, separator, key, value)
+    }
+}
+
+/// Encode a cursor value as an opaque, URL-safe string
+pub fn encode_cursor<V: Serialize>(value: &V) -> String {
+    let json = serde_json::to_string(value).unwrap_or_default();
+    json.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decode a `?cursor=` parameter back into the value to pass as `after`
+/// let after = params.cursor.as_deref().and_then(decode_cursor::<i64>);
+pub fn decode_cursor<V: DeserializeOwned>(cursor: &str) -> Option<V> {
+    if !cursor.len().is_multiple_of(2) || !cursor.is_ascii() {
+        return None;
+    }
+    let bytes: Option<Vec<u8>> = (0..cursor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16).ok())
+        .collect();
+    serde_json::from_slice(&bytes?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_links() {
+        let page = Paginator::new(vec![1, 2], 45, 20, 2).with_path("/api/posts");
+        assert_eq!(page.last_page, Some(3));
+        assert!(page.has_more);
+        assert_eq!(page.next_page_url().as_deref(), Some("/api/posts?page=3"));
+        assert_eq!(page.prev_page_url().as_deref(), Some("/api/posts?page=1"));
+
+        let last = Paginator::new(vec![1], 45, 20, 3).with_path("/api/posts?sort=new");
+        assert_eq!(last.next_page_url(), None);
+        assert_eq!(last.prev_page_url().as_deref(), Some("/api/posts?sort=new&page=2"));
+    }
+
+    #[test]
+    fn test_huge_page_sizes_do_not_overflow() {
+        let page = Paginator::new(vec![1], 45, i64::MAX, i64::MAX);
+        assert_eq!(page.last_page, Some(1));
+        assert!(!page.has_more);
+        assert_eq!(page.next_page_url(), None);
+        assert_eq!(Paginator::<i64>::new(vec![], i64::MAX, 1, 1).last_page, Some(i64::MAX));
+    }
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursor = encode_cursor(&"2024-01-01 10:00".to_string());
+        assert!(cursor.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(decode_cursor::<String>(&cursor).as_deref(), Some("2024-01-01 10:00"));
+        assert_eq!(decode_cursor::<i64>(&encode_cursor(&42)), Some(42));
+        assert_eq!(decode_cursor::<i64>("zz"), None);
+
+        let page = Paginator::cursor(vec![1], 1, Some(encode_cursor(&42)));
+        assert_eq!(page.next_page_url(), Some(format!("?cursor={}", encode_cursor(&42))));
+        assert_eq!(page.prev_page_url(), None);
+    }
+}
@@ -0,0 +1,362 @@
+use crate::database::{ConnectionResolver, DbRow};
+use crate::orbit::builder::Builder;
+use crate::orbit::Orbit;
+use serde::{Serialize, Serializer};
+use sqlx::{FromRow, Row};
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::Arc;
+
+type BoxFuture<'a, O> = Pin<Box<dyn Future<Output = O> + Send + 'a>>;
+
+/// Related models grouped by the parent key they belong to
+type Fetched = HashMap<i64, Vec<Arc<dyn LoadedModel>>>;
+
+type Fetcher = Box<dyn for<'a> Fn(&'a dyn ConnectionResolver, Vec<i64>, Vec<String>) -> BoxFuture<'a, Result<Fetched, sqlx::Error>> + Send + Sync>;
+
+/// Column alias used to carry the parent key through a pivot join
+const PIVOT_KEY: &str = "orbit_pivot_key";
+
+/// A relationship that can be eager loaded with `Builder::with`.
+///
+/// Declare them in `Orbit::relation`:
+///
+/// fn relation(name: &str) -> Option<Relation<Self>> {
+///     match name {
+///         "posts" => Some(Relation::has_many::<Post>("user_id")),
+///         "roles" => Some(Relation::belongs_to_many::<Role>("role_user", "user_id", "role_id")),
+///         _ => None,
+///     }
+/// }
+pub struct Relation<P> {
+    many: bool,
+    parent_key: Box<dyn Fn(&P) -> i64 + Send + Sync>,
+    fetch: Fetcher,
+}
+
+impl<P> Relation<P>
+where P: Orbit + Send + Unpin + for<'r> FromRow<'r, DbRow> + 'static
+{
+    /// User has many Posts: Relation::has_many::<Post>("user_id")
+    pub fn has_many<R>(foreign_key: &str) -> Self
+    where R: Orbit + Serialize + Sync + 'static
+    {
+        let fk = foreign_key.to_string();
+        Self::build::<R, _>(true, |parent| parent.id(), foreign_key, move |keys| R::query().where_in(&fk, keys))
+    }
+
+    /// User has one Profile: Relation::has_one::<Profile>("user_id")
+    pub fn has_one<R>(foreign_key: &str) -> Self
+    where R: Orbit + Serialize + Sync + 'static
+    {
+        let fk = foreign_key.to_string();
+        Self::build::<R, _>(false, |parent| parent.id(), foreign_key, move |keys| R::query().where_in(&fk, keys))
+    }
+
+    /// Post belongs to User: Relation::belongs_to::<User>(|post| post.user_id)
+    pub fn belongs_to<R>(foreign_key: fn(&P) -> i64) -> Self
+    where R: Orbit + Serialize + Sync + 'static
+    {
+        Self::build::<R, _>(false, foreign_key, R::primary_key(), |keys| R::query().where_in(R::primary_key(), keys))
+    }
+
+    /// User belongs to many Roles: Relation::belongs_to_many::<Role>("role_user", "user_id", "role_id")
+    pub fn belongs_to_many<R>(pivot_table: &str, foreign_key: &str, related_key: &str) -> Self
+    where R: Orbit + Serialize + Sync + 'static
+    {
+        let pivot_table = pivot_table.to_string();
+        let foreign_key = foreign_key.to_string();
+        let related_key = related_key.to_string();
+
+        Self::build::<R, _>(true, |parent| parent.id(), PIVOT_KEY, move |keys| {
+            let related_table = R::table_name();
+            R::query()
+                .select(&[
+                    &format!("{}.*", related_table),
+                    &format!("{}.{} as {}", pivot_table, foreign_key, PIVOT_KEY),
+                ])
+                .join(
+                    &pivot_table,
+                    &format!("{}.{}", related_table, R::primary_key()),
+                    "=",
+                    &format!("{}.{}", pivot_table, related_key),
+                )
+                .where_in(&format!("{}.{}", pivot_table, foreign_key), keys)
+        })
+    }
+
+    /// Post has one Image: Relation::morph_one::<Image>("imageable_id", "imageable_type")
+    pub fn morph_one<R>(id_column: &str, type_column: &str) -> Self
+    where R: Orbit + Serialize + Sync + 'static
+    {
+        Self::morph::<R>(false, id_column, type_column)
+    }
+
+    /// Post has many Comments: Relation::morph_many::<Comment>("commentable_id", "commentable_type")
+    pub fn morph_many<R>(id_column: &str, type_column: &str) -> Self
+    where R: Orbit + Serialize + Sync + 'static
+    {
+        Self::morph::<R>(true, id_column, type_column)
+    }
+
+    fn morph<R>(many: bool, id_column: &str, type_column: &str) -> Self
+    where R: Orbit + Serialize + Sync + 'static
+    {
+        let id_col = id_column.to_string();
+        let type_col = type_column.to_string();
+        Self::build::<R, _>(many, |parent| parent.id(), id_column, move |keys| {
+            R::query()
+                .where_in(&id_col, keys)
+                .where_eq(&type_col, P::table_name().to_string())
+        })
+    }
+
+    /// `query` builds the batched query for a set of parent keys; `key_column`
+    /// is the column of each fetched row that holds the parent key.
+    fn build<R, Q>(many: bool, parent_key: impl Fn(&P) -> i64 + Send + Sync + 'static, key_column: &str, query: Q) -> Self
+    where
+        R: Orbit + Serialize + Sync + 'static,
+        Q: Fn(Vec<i64>) -> Builder<R> + Send + Sync + 'static,
+    {
+        let key_column = key_column.to_string();
+
+        let fetch: Fetcher = Box::new(move |db, keys, nested| {
+            let key_column = key_column.clone();
+            let batches = db.resolve_read(R::connection()).map(|conn| {
+                // Stay under the driver's bind parameter limit, leaving room for the query's own bindings
+                let per_query = conn.grammar().max_parameters().saturating_sub(query(Vec::new()).binding_count()).max(1);
+                let builders: Vec<Builder<R>> = keys.chunks(per_query).map(|chunk| query(chunk.to_vec())).collect();
+                (conn, builders)
+            });
+
+            Box::pin(async move {
+                let (conn, builders) = batches?;
+                let mut rows = Vec::new();
+                for builder in builders {
+                    rows.extend(builder.fetch_rows(&conn).await?);
+                }
+
+                let mut owners = Vec::with_capacity(rows.len());
+                let mut models = Vec::with_capacity(rows.len());
+                for row in rows {
+                    owners.push(read_key(&row, &key_column)?);
+                    models.push(Loaded::new(R::from_row(&row)?));
+                }
+
+                eager_load(db, &mut models, &nested).await?;
+
+                let mut grouped: Fetched = HashMap::new();
+                for (owner, model) in owners.into_iter().zip(models) {
+                    grouped.entry(owner).or_default().push(Arc::new(model));
+                }
+                Ok(grouped)
+            })
+        });
+
+        Self {
+            many,
+            parent_key: Box::new(parent_key),
+            fetch,
+        }
+    }
+}
+
+/// A key column as i64, whatever integer type the schema gave it
+/// (Postgres INTEGER/SERIAL keys are int4, MySQL ones are often unsigned)
+fn read_key(row: &DbRow, column: &str) -> Result<i64, sqlx::Error> {
+    let error = match row.try_get::<i64, _>(column) {
+        Ok(key) => return Ok(key),
+        Err(e) => e,
+    };
+    if let Ok(key) = row.try_get::<i32, _>(column) {
+        return Ok(key.into());
+    }
+    #[cfg(all(feature = "mysql", not(any(feature = "postgres", feature = "sqlite"))))]
+    if let Ok(key) = row.try_get::<u64, _>(column) {
+        return i64::try_from(key).map_err(|e| sqlx::Error::Decode(Box::new(e)));
+    }
+    Err(error)
+}
+
+/// A type-erased eager loaded model
+trait LoadedModel: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn to_json(&self) -> serde_json::Value;
+}
+
+impl<R> LoadedModel for Loaded<R>
+where R: Orbit + Serialize + Send + Sync + 'static
+{
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or(serde_json::Value::Null)
+    }
+}
+
+enum Related {
+    Many(Vec<Arc<dyn LoadedModel>>),
+    One(Option<Arc<dyn LoadedModel>>),
+}
+
+/// A model together with its eager loaded relationships.
+/// Derefs to the model; serializes as the model (without its hidden attributes) with each
+/// relation added as a field.
+pub struct Loaded<T> {
+    pub model: T,
+    relations: HashMap<String, Related>,
+}
+
+impl<T> Loaded<T> {
+    pub fn new(model: T) -> Self {
+        Self {
+            model,
+            relations: HashMap::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.model
+    }
+
+    /// Whether the relation was eager loaded
+    pub fn relation_loaded(&self, name: &str) -> bool {
+        self.relations.contains_key(name)
+    }
+
+    /// The models of a "many" relation (has_many, belongs_to_many, morph_many)
+    /// user.many::<Post>("posts")
+    pub fn many<R: 'static>(&self, name: &str) -> Vec<&Loaded<R>> {
+        match self.relations.get(name) {
+            Some(Related::Many(items)) => items
+                .iter()
+                .filter_map(|item| item.as_any().downcast_ref::<Loaded<R>>())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The model of a "one" relation (has_one, belongs_to, morph_one)
+    /// post.one::<User>("author")
+    pub fn one<R: 'static>(&self, name: &str) -> Option<&Loaded<R>> {
+        match self.relations.get(name) {
+            Some(Related::One(Some(item))) => item.as_any().downcast_ref::<Loaded<R>>(),
+            _ => None,
+        }
+    }
+}
+
+impl<T> Deref for Loaded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.model
+    }
+}
+
+impl<T: Orbit + Serialize> Serialize for Loaded<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut value = self.model.to_json();
+
+        if let Some(object) = value.as_object_mut() {
+            for (name, related) in &self.relations {
+                let json = match related {
+                    Related::Many(items) => serde_json::Value::Array(items.iter().map(|i| i.to_json()).collect()),
+                    Related::One(item) => item.as_ref().map(|i| i.to_json()).unwrap_or(serde_json::Value::Null),
+                };
+                object.insert(name.clone(), json);
+            }
+        }
+
+        value.serialize(serializer)
+    }
+}
+
+/// Split "posts", "posts.comments", "roles" into { posts: [comments], roles: [] }
+fn parse_relations(relations: &[String]) -> Vec<(String, Vec<String>)> {
+    let mut tree: Vec<(String, Vec<String>)> = Vec::new();
+
+    for relation in relations {
+        let (name, nested) = match relation.split_once('.') {
+            Some((name, rest)) => (name, Some(rest.to_string())),
+            None => (relation.as_str(), None),
+        };
+
+        let index = match tree.iter().position(|(n, _)| n == name) {
+            Some(index) => index,
+            None => {
+                tree.push((name.to_string(), Vec::new()));
+                tree.len() - 1
+            }
+        };
+
+        if let Some(nested) = nested {
+            if !tree[index].1.contains(&nested) {
+                tree[index].1.push(nested);
+            }
+        }
+    }
+
+    tree
+}
+
+/// Load the given relations onto the models, one query per relation (and per nesting level)
+pub async fn eager_load<M>(db: &dyn ConnectionResolver, models: &mut [Loaded<M>], relations: &[String]) -> Result<(), sqlx::Error>
+where M: Orbit + Send + Unpin + for<'r> FromRow<'r, DbRow>
+{
+    if models.is_empty() {
+        return Ok(());
+    }
+
+    for (name, nested) in parse_relations(relations) {
+        let relation = M::relation(&name).ok_or_else(|| {
+            sqlx::Error::Configuration(format!("Relation '{}' is not defined on '{}'", name, M::table_name()).into())
+        })?;
+
+        let mut keys: Vec<i64> = models.iter().map(|m| (relation.parent_key)(&m.model)).collect();
+        keys.sort_unstable();
+        keys.dedup();
+
+        let fetched = (relation.fetch)(db, keys, nested).await?;
+
+        for model in models.iter_mut() {
+            let items = fetched.get(&(relation.parent_key)(&model.model)).cloned().unwrap_or_default();
+            let related = if relation.many {
+                Related::Many(items)
+            } else {
+                Related::One(items.into_iter().next())
+            };
+            model.relations.insert(name.clone(), related);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_relations() {
+        let relations = vec![
+            "posts".to_string(),
+            "posts.comments".to_string(),
+            "posts.comments.author".to_string(),
+            "roles".to_string(),
+        ];
+
+        assert_eq!(
+            parse_relations(&relations),
+            vec![
+                ("posts".to_string(), vec!["comments".to_string(), "comments.author".to_string()]),
+                ("roles".to_string(), vec![]),
+            ]
+        );
+    }
+}
@@ -1,126 +1,542 @@
-use std::fmt;
+use crate::orbit::grammar::Grammar;
+use std::sync::Mutex;
+use thiserror::Error;
 
-pub struct Schema;
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum SchemaError {
+    #[error("SQLite can't {0}; create a new table and copy the rows instead")]
+    UnsupportedBySqlite(&'static str),
+}
+
+/// Records schema statements for the grammar it was created with.
+///
+/// Migrations receive one in `up`/`down`; outside of migrations use `to_sql()`
+/// to get the statements back. A change the grammar can't express is kept
+/// and returned by `statements()`/`to_sql()`, so `up` itself stays infallible.
+pub struct Schema {
+    grammar: Grammar,
+    statements: Mutex<Vec<String>>,
+    error: Mutex<Option<SchemaError>>,
+}
+
+impl Default for Schema {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Schema {
+    /// A schema for the compiled backend's grammar
+    pub fn new() -> Self {
+        Self::for_grammar(Grammar::current())
+    }
+
+    pub fn for_grammar(grammar: Grammar) -> Self {
+        Self { grammar, statements: Mutex::new(Vec::new()), error: Mutex::new(None) }
+    }
+
     /// Create a new table with a blueprint closure
     /// Note: In Rust we can't easily pass a closure that modifies a struct in the same way as PHP,
     /// but we can use a builder pattern.
     ///
     /// Usage:
-    /// let sql = Schema::create("users", |table| {
+    /// let sql = Schema::new().create("users", |table| {
     ///     table.id();
     ///     table.string("name");
     ///     table.string("email").unique();
     ///     table.timestamps();
-    /// });
-    pub fn create<F>(table_name: &str, callback: F) -> String
+    /// }).to_sql()?;
+    pub fn create<F>(&self, table_name: &str, callback: F) -> &Self
     where F: FnOnce(&mut Blueprint)
     {
         let mut blueprint = Blueprint::new(table_name);
         callback(&mut blueprint);
-        blueprint.to_sql()
+        self.push_blueprint(&blueprint)
+    }
+
+    /// Change an existing table. Records one statement per change.
+    ///
+    /// schema.table("posts", |table| {
+    ///     table.string("slug").nullable();
+    ///     table.text("body").nullable().change();
+    ///     table.rename_column("title", "headline");
+    ///     table.drop_column("legacy_id");
+    ///     table.unique(&["user_id", "slug"]);
+    ///     table.foreign("user_id").references("id").on("users").on_delete("cascade");
+    /// });
+    pub fn table<F>(&self, table_name: &str, callback: F) -> &Self
+    where F: FnOnce(&mut Blueprint)
+    {
+        let mut blueprint = Blueprint::alter(table_name);
+        callback(&mut blueprint);
+        self.push_blueprint(&blueprint)
+    }
+
+    pub fn rename(&self, from: &str, to: &str) -> &Self {
+        let sql = format!("ALTER TABLE {} RENAME TO {};", self.grammar.wrap_table(from), self.grammar.wrap_table(to));
+        self.push(vec![sql])
+    }
+
+    pub fn drop(&self, table_name: &str) -> &Self {
+        self.push(vec![format!("DROP TABLE {};", self.grammar.wrap_table(table_name))])
+    }
+
+    pub fn drop_if_exists(&self, table_name: &str) -> &Self {
+        self.push(vec![format!("DROP TABLE IF EXISTS {};", self.grammar.wrap_table(table_name))])
+    }
+
+    /// A raw statement the blueprint can't express
+    pub fn statement(&self, sql: &str) -> &Self {
+        self.push(vec![sql.to_string()])
+    }
+
+    pub fn grammar(&self) -> Grammar {
+        self.grammar
+    }
+
+    /// The statements recorded so far, in order, or the first change that couldn't be compiled
+    pub fn statements(&self) -> Result<Vec<String>, SchemaError> {
+        match self.error.lock().unwrap().clone() {
+            Some(error) => Err(error),
+            None => Ok(self.statements.lock().unwrap().clone()),
+        }
     }
 
-    pub fn drop_if_exists(table_name: &str) -> String {
-        format!("DROP TABLE IF EXISTS {};", table_name)
+    /// The recorded statements, one per line
+    pub fn to_sql(&self) -> Result<String, SchemaError> {
+        Ok(self.statements()?.join("\n"))
     }
+
+    fn push_blueprint(&self, blueprint: &Blueprint) -> &Self {
+        match blueprint.statements_for(&self.grammar) {
+            Ok(statements) => self.push(statements),
+            Err(error) => {
+                self.error.lock().unwrap().get_or_insert(error);
+                self
+            }
+        }
+    }
+
+    fn push(&self, statements: Vec<String>) -> &Self {
+        self.statements.lock().unwrap().extend(statements);
+        self
+    }
+}
+
+/// Column types understood by the blueprint, rendered per grammar
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnType {
+    Id,
+    String(u32),
+    Text,
+    Integer,
+    BigInteger,
+    Boolean,
+    Timestamp,
+    Date,
+    Decimal(u8, u8),
+    Float,
+    Json,
+    Uuid,
+    Enum(Vec<String>),
+    Binary,
+}
+
+impl ColumnType {
+    fn to_sql(&self, grammar: &Grammar) -> String {
+        if cfg!(feature = "any") {
+            if let Some(sql) = self.any_driver_sql(grammar) {
+                return sql;
+            }
+        }
+
+        match (self, grammar) {
+            (ColumnType::Id, Grammar::MySql) => "BIGINT".to_string(),
+            (ColumnType::Id, Grammar::Postgres) => "BIGSERIAL".to_string(),
+            (ColumnType::Id, Grammar::Sqlite) => "INTEGER".to_string(),
+            (ColumnType::String(length), _) => format!("VARCHAR({})", length),
+            (ColumnType::Text, _) => "TEXT".to_string(),
+            (ColumnType::Integer, Grammar::MySql) => "INT".to_string(),
+            (ColumnType::Integer, _) => "INTEGER".to_string(),
+            (ColumnType::BigInteger, Grammar::Sqlite) => "INTEGER".to_string(),
+            (ColumnType::BigInteger, _) => "BIGINT".to_string(),
+            (ColumnType::Boolean, _) => "BOOLEAN".to_string(),
+            (ColumnType::Timestamp, Grammar::Postgres) => "TIMESTAMP".to_string(),
+            (ColumnType::Timestamp, _) => "DATETIME".to_string(),
+            (ColumnType::Date, _) => "DATE".to_string(),
+            (ColumnType::Decimal(precision, scale), _) => format!("DECIMAL({}, {})", precision, scale),
+            // DOUBLE rather than FLOAT so values decode as f64 everywhere
+            (ColumnType::Float, Grammar::MySql) => "DOUBLE".to_string(),
+            (ColumnType::Float, Grammar::Postgres) => "DOUBLE PRECISION".to_string(),
+            (ColumnType::Float, Grammar::Sqlite) => "REAL".to_string(),
+            (ColumnType::Json, Grammar::MySql) => "JSON".to_string(),
+            (ColumnType::Json, Grammar::Postgres) => "JSONB".to_string(),
+            (ColumnType::Json, Grammar::Sqlite) => "TEXT".to_string(),
+            (ColumnType::Uuid, Grammar::Postgres) => "UUID".to_string(),
+            (ColumnType::Uuid, _) => "CHAR(36)".to_string(),
+            (ColumnType::Enum(values), Grammar::MySql) => format!("ENUM({})", quote_list(values)),
+            // Postgres and SQLite get a CHECK constraint instead, see ColumnDefinition::to_sql_for
+            (ColumnType::Enum(_), _) => "VARCHAR(255)".to_string(),
+            (ColumnType::Binary, Grammar::Postgres) => "BYTEA".to_string(),
+            (ColumnType::Binary, _) => "BLOB".to_string(),
+        }
+    }
+
+    /// sqlx's `any` driver only reads integer, float, text and blob columns,
+    /// so that build stores the other types as one of those
+    fn any_driver_sql(&self, grammar: &Grammar) -> Option<String> {
+        match (self, grammar) {
+            (ColumnType::Boolean, Grammar::Sqlite) => Some("INTEGER".to_string()),
+            (ColumnType::Boolean, _) => Some("SMALLINT".to_string()),
+            (ColumnType::Timestamp, _) => Some("VARCHAR(32)".to_string()),
+            (ColumnType::Date, _) => Some("VARCHAR(10)".to_string()),
+            (ColumnType::Decimal(..), _) => Some(ColumnType::Float.to_sql(grammar)),
+            (ColumnType::Json, _) => Some("TEXT".to_string()),
+            (ColumnType::Uuid, _) => Some("VARCHAR(36)".to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// A change to an existing table that isn't a column definition
+enum Command {
+    Index(IndexDefinition),
+    DropIndex(String),
+    Foreign(ForeignKeyDefinition),
+    DropForeign(String),
+    RenameColumn(String, String),
+    DropColumn(String),
 }
 
 pub struct Blueprint {
     table: String,
+    creating: bool,
     columns: Vec<ColumnDefinition>,
-    primary_key: Option<String>,
+    primary_key: Vec<String>,
+    commands: Vec<Command>,
 }
 
 impl Blueprint {
+    /// A blueprint for a new table
     pub fn new(table: &str) -> Self {
         Self {
             table: table.to_string(),
+            creating: true,
             columns: Vec::new(),
-            primary_key: None,
+            primary_key: Vec::new(),
+            commands: Vec::new(),
         }
     }
 
+    /// A blueprint for changes to an existing table
+    pub fn alter(table: &str) -> Self {
+        Self { creating: false, ..Self::new(table) }
+    }
+
     pub fn id(&mut self) {
         self.columns.push(ColumnDefinition {
-            name: "id".to_string(),
-            data_type: "BIGINT".to_string(),
             auto_increment: true,
-            nullable: false,
-            unique: false,
-            default: None,
+            ..ColumnDefinition::new("id", ColumnType::Id)
         });
-        self.primary_key = Some("id".to_string());
+        self.primary_key = vec!["id".to_string()];
     }
 
     pub fn string(&mut self, name: &str) -> &mut ColumnDefinition {
-        self.add_column(name, "VARCHAR(255)")
+        self.add_column(name, ColumnType::String(255))
     }
 
     pub fn text(&mut self, name: &str) -> &mut ColumnDefinition {
-        self.add_column(name, "TEXT")
+        self.add_column(name, ColumnType::Text)
     }
 
     pub fn integer(&mut self, name: &str) -> &mut ColumnDefinition {
-        self.add_column(name, "INT")
+        self.add_column(name, ColumnType::Integer)
+    }
+
+    pub fn big_integer(&mut self, name: &str) -> &mut ColumnDefinition {
+        self.add_column(name, ColumnType::BigInteger)
+    }
+
+    /// A BIGINT column for a foreign key, e.g. `foreign_id("user_id")`
+    pub fn foreign_id(&mut self, name: &str) -> &mut ColumnDefinition {
+        self.big_integer(name)
     }
 
     pub fn boolean(&mut self, name: &str) -> &mut ColumnDefinition {
-        self.add_column(name, "BOOLEAN")
+        self.add_column(name, ColumnType::Boolean)
     }
 
     pub fn timestamp(&mut self, name: &str) -> &mut ColumnDefinition {
-        self.add_column(name, "DATETIME")
+        self.add_column(name, ColumnType::Timestamp)
+    }
+
+    pub fn date(&mut self, name: &str) -> &mut ColumnDefinition {
+        self.add_column(name, ColumnType::Date)
+    }
+
+    /// decimal("price", 8, 2)
+    pub fn decimal(&mut self, name: &str, precision: u8, scale: u8) -> &mut ColumnDefinition {
+        self.add_column(name, ColumnType::Decimal(precision, scale))
+    }
+
+    pub fn float(&mut self, name: &str) -> &mut ColumnDefinition {
+        self.add_column(name, ColumnType::Float)
+    }
+
+    pub fn json(&mut self, name: &str) -> &mut ColumnDefinition {
+        self.add_column(name, ColumnType::Json)
+    }
+
+    pub fn uuid(&mut self, name: &str) -> &mut ColumnDefinition {
+        self.add_column(name, ColumnType::Uuid)
+    }
+
+    /// enumeration("status", &["draft", "published"])
+    pub fn enumeration(&mut self, name: &str, values: &[&str]) -> &mut ColumnDefinition {
+        self.add_column(name, ColumnType::Enum(values.iter().map(|v| v.to_string()).collect()))
+    }
+
+    pub fn binary(&mut self, name: &str) -> &mut ColumnDefinition {
+        self.add_column(name, ColumnType::Binary)
     }
 
     pub fn timestamps(&mut self) {
         self.timestamp("created_at").default("CURRENT_TIMESTAMP");
-        self.timestamp("updated_at").default("CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP");
+        self.timestamp("updated_at").default("CURRENT_TIMESTAMP").use_current_on_update();
     }
 
-    fn add_column(&mut self, name: &str, data_type: &str) -> &mut ColumnDefinition {
-        let col = ColumnDefinition {
-            name: name.to_string(),
-            data_type: data_type.to_string(),
-            auto_increment: false,
-            nullable: false,
-            unique: false,
-            default: None,
-        };
-        self.columns.push(col);
+    /// The nullable `deleted_at` column used by `SOFT_DELETES` models
+    pub fn soft_deletes(&mut self) {
+        self.timestamp("deleted_at").nullable();
+    }
+
+    /// `{name}_id` and `{name}_type` columns for a polymorphic relation, indexed together
+    pub fn morphs(&mut self, name: &str) {
+        let (id, kind) = (format!("{}_id", name), format!("{}_type", name));
+        self.big_integer(&id);
+        self.string(&kind);
+        self.index(&[kind.as_str(), id.as_str()]);
+    }
+
+    /// A composite primary key (new tables only)
+    pub fn primary(&mut self, columns: &[&str]) {
+        self.primary_key = columns.iter().map(|c| c.to_string()).collect();
+    }
+
+    pub fn index(&mut self, columns: &[&str]) -> &mut IndexDefinition {
+        self.add_index(columns, false)
+    }
+
+    pub fn unique(&mut self, columns: &[&str]) -> &mut IndexDefinition {
+        self.add_index(columns, true)
+    }
+
+    pub fn drop_index(&mut self, name: &str) {
+        self.commands.push(Command::DropIndex(name.to_string()));
+    }
+
+    pub fn drop_unique(&mut self, name: &str) {
+        self.drop_index(name);
+    }
+
+    /// foreign("user_id").references("id").on("users").on_delete("cascade")
+    pub fn foreign(&mut self, column: &str) -> &mut ForeignKeyDefinition {
+        self.commands.push(Command::Foreign(ForeignKeyDefinition {
+            name: format!("{}_{}_foreign", self.table, column),
+            column: column.to_string(),
+            references: "id".to_string(),
+            on: String::new(),
+            on_delete: None,
+            on_update: None,
+        }));
+        match self.commands.last_mut() {
+            Some(Command::Foreign(foreign)) => foreign,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn drop_foreign(&mut self, name: &str) {
+        self.commands.push(Command::DropForeign(name.to_string()));
+    }
+
+    pub fn rename_column(&mut self, from: &str, to: &str) {
+        self.commands.push(Command::RenameColumn(from.to_string(), to.to_string()));
+    }
+
+    pub fn drop_column(&mut self, name: &str) {
+        self.commands.push(Command::DropColumn(name.to_string()));
+    }
+
+    pub fn drop_columns(&mut self, names: &[&str]) {
+        for name in names {
+            self.drop_column(name);
+        }
+    }
+
+    fn add_column(&mut self, name: &str, data_type: ColumnType) -> &mut ColumnDefinition {
+        self.columns.push(ColumnDefinition::new(name, data_type));
         self.columns.last_mut().unwrap()
     }
 
-    pub fn to_sql(&self) -> String {
+    fn add_index(&mut self, columns: &[&str], unique: bool) -> &mut IndexDefinition {
+        let suffix = if unique { "unique" } else { "index" };
+        self.commands.push(Command::Index(IndexDefinition {
+            name: format!("{}_{}_{}", self.table, columns.join("_"), suffix).replace(['.', '-'], "_").to_lowercase(),
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            unique,
+        }));
+        match self.commands.last_mut() {
+            Some(Command::Index(index)) => index,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn to_sql(&self) -> Result<String, SchemaError> {
+        self.to_sql_for(&Grammar::current())
+    }
+
+    /// Render the statements for a specific grammar, one per line
+    pub fn to_sql_for(&self, grammar: &Grammar) -> Result<String, SchemaError> {
+        Ok(self.statements_for(grammar)?.join("\n"))
+    }
+
+    /// The CREATE TABLE (or ALTER TABLE) statements followed by any index statements.
+    /// Fails on changes SQLite can't make without rebuilding the table.
+    pub fn statements_for(&self, grammar: &Grammar) -> Result<Vec<String>, SchemaError> {
+        let mut statements = match self.creating {
+            true => vec![self.compile_create(grammar)],
+            false => self.compile_alter(grammar)?,
+        };
+
+        for column in &self.columns {
+            if column.index {
+                statements.push(IndexDefinition::for_column(&self.table, &column.name, false).to_sql_for(grammar, &self.table));
+            }
+            // SQLite can't ADD COLUMN ... UNIQUE, so altered columns get a separate index
+            if column.unique && !self.creating {
+                statements.push(IndexDefinition::for_column(&self.table, &column.name, true).to_sql_for(grammar, &self.table));
+            }
+        }
+
+        for command in &self.commands {
+            let table = grammar.wrap_table(&self.table);
+            let sql = match command {
+                Command::Index(index) => index.to_sql_for(grammar, &self.table),
+                Command::Foreign(_) if self.creating => continue,
+                Command::Foreign(_) if *grammar == Grammar::Sqlite => {
+                    return Err(SchemaError::UnsupportedBySqlite("add foreign keys to an existing table"));
+                }
+                Command::Foreign(foreign) => {
+                    format!("ALTER TABLE {} ADD {};", table, foreign.to_sql_for(grammar))
+                }
+                Command::DropIndex(name) => match grammar {
+                    Grammar::MySql => format!("DROP INDEX {} ON {};", grammar.wrap(name), table),
+                    Grammar::Postgres | Grammar::Sqlite => format!("DROP INDEX {};", grammar.wrap(name)),
+                },
+                Command::DropForeign(name) => match grammar {
+                    Grammar::MySql => format!("ALTER TABLE {} DROP FOREIGN KEY {};", table, grammar.wrap(name)),
+                    Grammar::Postgres => format!("ALTER TABLE {} DROP CONSTRAINT {};", table, grammar.wrap(name)),
+                    Grammar::Sqlite => return Err(SchemaError::UnsupportedBySqlite("drop foreign keys")),
+                },
+                Command::RenameColumn(from, to) => {
+                    format!("ALTER TABLE {} RENAME COLUMN {} TO {};", table, grammar.wrap(from), grammar.wrap(to))
+                }
+                Command::DropColumn(name) => format!("ALTER TABLE {} DROP COLUMN {};", table, grammar.wrap(name)),
+            };
+            statements.push(sql);
+        }
+
+        Ok(statements)
+    }
+
+    fn compile_create(&self, grammar: &Grammar) -> String {
         let mut lines = Vec::new();
 
         for col in &self.columns {
-            lines.push(col.to_sql());
+            lines.push(col.to_sql_for(grammar));
         }
 
-        if let Some(pk) = &self.primary_key {
-            lines.push(format!("PRIMARY KEY ({})", pk));
+        // Postgres and SQLite declare auto-incrementing keys inline
+        let inline_key = self.primary_key.len() == 1
+            && self.columns.iter().any(|c| c.auto_increment && c.name == self.primary_key[0]);
+        if !self.primary_key.is_empty() && (*grammar == Grammar::MySql || !inline_key) {
+            let columns: Vec<String> = self.primary_key.iter().map(|c| grammar.wrap(c)).collect();
+            lines.push(format!("PRIMARY KEY ({})", columns.join(", ")));
         }
 
+        for command in &self.commands {
+            if let Command::Foreign(foreign) = command {
+                lines.push(foreign.to_sql_for(grammar));
+            }
+        }
+
+        let options = match grammar {
+            Grammar::MySql => " ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci",
+            Grammar::Postgres | Grammar::Sqlite => "",
+        };
+
         format!(
-            "CREATE TABLE {} (\n    {}\n) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;",
-            self.table,
-            lines.join(",\n    ")
+            "CREATE TABLE {} (\n    {}\n){};",
+            grammar.wrap_table(&self.table),
+            lines.join(",\n    "),
+            options
         )
     }
+
+    fn compile_alter(&self, grammar: &Grammar) -> Result<Vec<String>, SchemaError> {
+        let table = grammar.wrap_table(&self.table);
+
+        self.columns
+            .iter()
+            .map(|column| match (column.change, grammar) {
+                (false, _) => Ok(format!("ALTER TABLE {} ADD COLUMN {};", table, column.compile(grammar, false))),
+                (true, Grammar::MySql) => Ok(format!("ALTER TABLE {} MODIFY COLUMN {};", table, column.compile(grammar, false))),
+                (true, Grammar::Postgres) => {
+                    let name = grammar.wrap(&column.name);
+                    let mut changes = vec![format!("ALTER COLUMN {} TYPE {}", name, column.data_type.to_sql(grammar))];
+                    changes.push(match column.nullable {
+                        true => format!("ALTER COLUMN {} DROP NOT NULL", name),
+                        false => format!("ALTER COLUMN {} SET NOT NULL", name),
+                    });
+                    changes.push(match &column.default {
+                        Some(default) => format!("ALTER COLUMN {} SET DEFAULT {}", name, default),
+                        None => format!("ALTER COLUMN {} DROP DEFAULT", name),
+                    });
+                    Ok(format!("ALTER TABLE {} {};", table, changes.join(", ")))
+                }
+                (true, Grammar::Sqlite) => Err(SchemaError::UnsupportedBySqlite("modify columns")),
+            })
+            .collect()
+    }
 }
 
 pub struct ColumnDefinition {
     name: String,
-    data_type: String,
+    data_type: ColumnType,
     auto_increment: bool,
     nullable: bool,
     unique: bool,
+    index: bool,
     default: Option<String>,
+    on_update_current: bool,
+    change: bool,
 }
 
 impl ColumnDefinition {
+    fn new(name: &str, data_type: ColumnType) -> Self {
+        Self {
+            name: name.to_string(),
+            data_type,
+            auto_increment: false,
+            nullable: false,
+            unique: false,
+            index: false,
+            default: None,
+            on_update_current: false,
+            change: false,
+        }
+    }
+
     pub fn nullable(&mut self) -> &mut Self {
         self.nullable = true;
         self
@@ -131,30 +547,392 @@ impl ColumnDefinition {
         self
     }
 
+    /// Add a plain index on this column
+    pub fn index(&mut self) -> &mut Self {
+        self.index = true;
+        self
+    }
+
     pub fn default(&mut self, value: &str) -> &mut Self {
         self.default = Some(value.to_string());
         self
     }
 
+    /// Refresh the column with CURRENT_TIMESTAMP on every update (MySQL only)
+    pub fn use_current_on_update(&mut self) -> &mut Self {
+        self.on_update_current = true;
+        self
+    }
+
+    /// Modify the existing column to match this definition instead of adding it
+    /// (`schema.table(...)` only; not supported by SQLite)
+    pub fn change(&mut self) -> &mut Self {
+        self.change = true;
+        self
+    }
+
     pub fn to_sql(&self) -> String {
-        let mut parts = vec![self.name.clone(), self.data_type.clone()];
+        self.to_sql_for(&Grammar::current())
+    }
 
-        if !self.nullable {
-            parts.push("NOT NULL".to_string());
-        }
+    pub fn to_sql_for(&self, grammar: &Grammar) -> String {
+        self.compile(grammar, true)
+    }
+
+    fn compile(&self, grammar: &Grammar, inline_unique: bool) -> String {
+        let mut parts = vec![grammar.wrap(&self.name), self.data_type.to_sql(grammar)];
+
+        // The any build keeps timestamps as text, which CURRENT_TIMESTAMP can't fill in
+        // the format Orbit reads, so those columns are left nullable for Orbit to write
+        let text_timestamp = cfg!(feature = "any") && self.data_type == ColumnType::Timestamp;
+        let default = self.default.as_ref()
+            .filter(|default| !(text_timestamp && default.eq_ignore_ascii_case("CURRENT_TIMESTAMP")));
+        let nullable = self.nullable || (self.default.is_some() && default.is_none());
 
         if self.auto_increment {
-            parts.push("AUTO_INCREMENT".to_string());
+            match grammar {
+                Grammar::MySql => parts.push("NOT NULL AUTO_INCREMENT".to_string()),
+                Grammar::Postgres => parts.push("PRIMARY KEY".to_string()),
+                Grammar::Sqlite => parts.push("PRIMARY KEY AUTOINCREMENT".to_string()),
+            }
+        } else if !nullable {
+            parts.push("NOT NULL".to_string());
         }
 
-        if let Some(default) = &self.default {
+        if let Some(default) = default {
             parts.push(format!("DEFAULT {}", default));
         }
 
-        if self.unique {
+        if self.on_update_current && *grammar == Grammar::MySql && !text_timestamp {
+            parts.push("ON UPDATE CURRENT_TIMESTAMP".to_string());
+        }
+
+        if self.unique && inline_unique {
             parts.push("UNIQUE".to_string());
         }
 
+        if let (ColumnType::Enum(values), Grammar::Postgres | Grammar::Sqlite) = (&self.data_type, grammar) {
+            parts.push(format!("CHECK ({} IN ({}))", grammar.wrap(&self.name), quote_list(values)));
+        }
+
         parts.join(" ")
     }
 }
+
+pub struct IndexDefinition {
+    name: String,
+    columns: Vec<String>,
+    unique: bool,
+}
+
+impl IndexDefinition {
+    fn for_column(table: &str, column: &str, unique: bool) -> Self {
+        let suffix = if unique { "unique" } else { "index" };
+        Self {
+            name: format!("{}_{}_{}", table, column, suffix),
+            columns: vec![column.to_string()],
+            unique,
+        }
+    }
+
+    /// Override the generated `{table}_{columns}_index` name
+    pub fn name(&mut self, name: &str) -> &mut Self {
+        self.name = name.to_string();
+        self
+    }
+
+    fn to_sql_for(&self, grammar: &Grammar, table: &str) -> String {
+        let columns: Vec<String> = self.columns.iter().map(|c| grammar.wrap(c)).collect();
+        format!(
+            "CREATE {}INDEX {} ON {} ({});",
+            if self.unique { "UNIQUE " } else { "" },
+            grammar.wrap(&self.name),
+            grammar.wrap_table(table),
+            columns.join(", ")
+        )
+    }
+}
+
+pub struct ForeignKeyDefinition {
+    name: String,
+    column: String,
+    references: String,
+    on: String,
+    on_delete: Option<String>,
+    on_update: Option<String>,
+}
+
+impl ForeignKeyDefinition {
+    /// The referenced column (defaults to "id")
+    pub fn references(&mut self, column: &str) -> &mut Self {
+        self.references = column.to_string();
+        self
+    }
+
+    /// The referenced table
+    pub fn on(&mut self, table: &str) -> &mut Self {
+        self.on = table.to_string();
+        self
+    }
+
+    /// "cascade", "set null", "restrict", "no action" or "set default"
+    pub fn on_delete(&mut self, action: &str) -> &mut Self {
+        self.on_delete = Some(action.to_uppercase());
+        self
+    }
+
+    pub fn on_update(&mut self, action: &str) -> &mut Self {
+        self.on_update = Some(action.to_uppercase());
+        self
+    }
+
+    pub fn cascade_on_delete(&mut self) -> &mut Self {
+        self.on_delete("cascade")
+    }
+
+    pub fn null_on_delete(&mut self) -> &mut Self {
+        self.on_delete("set null")
+    }
+
+    /// Override the generated `{table}_{column}_foreign` name
+    pub fn name(&mut self, name: &str) -> &mut Self {
+        self.name = name.to_string();
+        self
+    }
+
+    fn to_sql_for(&self, grammar: &Grammar) -> String {
+        let mut sql = format!(
+            "CONSTRAINT {} FOREIGN KEY ({}) REFERENCES {} ({})",
+            grammar.wrap(&self.name),
+            grammar.wrap(&self.column),
+            grammar.wrap_table(&self.on),
+            grammar.wrap(&self.references)
+        );
+        if let Some(action) = &self.on_delete {
+            sql.push_str(&format!(" ON DELETE {}", action));
+        }
+        if let Some(action) = &self.on_update {
+            sql.push_str(&format!(" ON UPDATE {}", action));
+        }
+        sql
+    }
+}
+
+fn quote_list(values: &[String]) -> String {
+    values
+        .iter()
+        .map(|v| format!("'{}'", v.replace('\'', "''")))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn users() -> Blueprint {
+        let mut table = Blueprint::new("users");
+        table.id();
+        table.string("email").unique();
+        table.timestamps();
+        table
+    }
+
+    fn posts() -> Blueprint {
+        let mut table = Blueprint::new("posts");
+        table.id();
+        table.foreign_id("user_id");
+        table.decimal("price", 8, 2);
+        table.enumeration("status", &["draft", "published"]).default("'draft'");
+        table.json("meta").nullable();
+        table.soft_deletes();
+        table.morphs("commentable");
+        table.foreign("user_id").on("users").on_delete("cascade");
+        table
+    }
+
+    fn alter_posts() -> Blueprint {
+        let mut table = Blueprint::alter("posts");
+        table.string("slug").nullable().unique();
+        table.rename_column("title", "headline");
+        table.drop_column("legacy_id");
+        table.index(&["user_id", "created_at"]);
+        table.drop_index("posts_old_index");
+        table
+    }
+
+    #[test]
+    fn test_create_table_mysql() {
+        let sql = users().to_sql_for(&Grammar::MySql).unwrap();
+        assert!(sql.contains("`id` BIGINT NOT NULL AUTO_INCREMENT"));
+        // The any build's text timestamps are covered by test_column_types_for_the_any_driver
+        #[cfg(not(feature = "any"))]
+        assert!(sql.contains("ON UPDATE CURRENT_TIMESTAMP"));
+        assert!(sql.contains("PRIMARY KEY (`id`)"));
+        assert!(sql.ends_with("ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;"));
+    }
+
+    #[test]
+    fn test_create_table_postgres() {
+        let sql = users().to_sql_for(&Grammar::Postgres).unwrap();
+        assert!(sql.contains("\"id\" BIGSERIAL PRIMARY KEY"));
+        assert!(!sql.contains("ON UPDATE"));
+        assert!(!sql.contains("ENGINE"));
+    }
+
+    #[test]
+    fn test_create_table_sqlite() {
+        let sql = users().to_sql_for(&Grammar::Sqlite).unwrap();
+        assert!(sql.contains("\"id\" INTEGER PRIMARY KEY AUTOINCREMENT"));
+        assert!(sql.contains("\"email\" VARCHAR(255) NOT NULL UNIQUE"));
+    }
+
+    #[test]
+    #[cfg_attr(feature = "any", ignore = "the any build stores these types differently")]
+    fn test_column_types_per_backend() {
+        let mysql = posts().to_sql_for(&Grammar::MySql).unwrap();
+        assert!(mysql.contains("`price` DECIMAL(8, 2) NOT NULL"));
+        assert!(mysql.contains("`status` ENUM('draft', 'published') NOT NULL DEFAULT 'draft'"));
+        assert!(mysql.contains("`meta` JSON"));
+        assert!(mysql.contains("`deleted_at` DATETIME,"));
+
+        let postgres = posts().to_sql_for(&Grammar::Postgres).unwrap();
+        assert!(postgres.contains("\"status\" VARCHAR(255) NOT NULL DEFAULT 'draft' CHECK (\"status\" IN ('draft', 'published'))"));
+        assert!(postgres.contains("\"meta\" JSONB"));
+        assert!(postgres.contains("\"user_id\" BIGINT NOT NULL"));
+
+        let sqlite = posts().to_sql_for(&Grammar::Sqlite).unwrap();
+        assert!(sqlite.contains("\"user_id\" INTEGER NOT NULL"));
+        assert!(sqlite.contains("\"meta\" TEXT"));
+    }
+
+    #[cfg(feature = "any")]
+    #[test]
+    fn test_column_types_for_the_any_driver() {
+        let mysql = posts().to_sql_for(&Grammar::MySql).unwrap();
+        assert!(mysql.contains("`price` DOUBLE NOT NULL"));
+        assert!(mysql.contains("`deleted_at` VARCHAR(32),"));
+
+        let postgres = posts().to_sql_for(&Grammar::Postgres).unwrap();
+        assert!(postgres.contains("\"meta\" TEXT"));
+        assert!(postgres.contains("\"status\" VARCHAR(255) NOT NULL DEFAULT 'draft' CHECK"));
+
+        let mut table = Blueprint::new("events");
+        table.timestamps();
+        let mysql = table.to_sql_for(&Grammar::MySql).unwrap();
+        assert!(mysql.contains("`created_at` VARCHAR(32),\n    `updated_at` VARCHAR(32)\n"), "{}", mysql);
+        let postgres = table.to_sql_for(&Grammar::Postgres).unwrap();
+        assert!(postgres.contains("\"created_at\" VARCHAR(32),\n    \"updated_at\" VARCHAR(32)\n"), "{}", postgres);
+        for sql in [mysql, postgres] {
+            assert!(!sql.contains("CURRENT_TIMESTAMP"), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_create_table_with_foreign_keys_and_indexes() {
+        let sql = posts().to_sql_for(&Grammar::Postgres).unwrap();
+        assert!(sql.contains(
+            "CONSTRAINT \"posts_user_id_foreign\" FOREIGN KEY (\"user_id\") REFERENCES \"users\" (\"id\") ON DELETE CASCADE\n);"
+        ));
+        assert!(sql.ends_with(
+            "CREATE INDEX \"posts_commentable_type_commentable_id_index\" ON \"posts\" (\"commentable_type\", \"commentable_id\");"
+        ));
+
+        let mut pivot = Blueprint::new("role_user");
+        pivot.foreign_id("user_id");
+        pivot.foreign_id("role_id");
+        pivot.primary(&["user_id", "role_id"]);
+        assert!(pivot.to_sql_for(&Grammar::Sqlite).unwrap().contains("PRIMARY KEY (\"user_id\", \"role_id\")"));
+    }
+
+    #[test]
+    fn test_alter_table() {
+        assert_eq!(
+            alter_posts().statements_for(&Grammar::MySql).unwrap(),
+            vec![
+                "ALTER TABLE `posts` ADD COLUMN `slug` VARCHAR(255);",
+                "CREATE UNIQUE INDEX `posts_slug_unique` ON `posts` (`slug`);",
+                "ALTER TABLE `posts` RENAME COLUMN `title` TO `headline`;",
+                "ALTER TABLE `posts` DROP COLUMN `legacy_id`;",
+                "CREATE INDEX `posts_user_id_created_at_index` ON `posts` (`user_id`, `created_at`);",
+                "DROP INDEX `posts_old_index` ON `posts`;",
+            ]
+        );
+        assert!(alter_posts().to_sql_for(&Grammar::Postgres).unwrap().contains("DROP INDEX \"posts_old_index\";"));
+    }
+
+    #[test]
+    fn test_alter_modify_and_foreign_keys() {
+        let mut table = Blueprint::alter("posts");
+        table.text("body").nullable().change();
+        table.foreign("author_id").references("uuid").on("authors").null_on_delete();
+        table.drop_foreign("posts_user_id_foreign");
+
+        assert_eq!(
+            table.statements_for(&Grammar::MySql).unwrap(),
+            vec![
+                "ALTER TABLE `posts` MODIFY COLUMN `body` TEXT;",
+                "ALTER TABLE `posts` ADD CONSTRAINT `posts_author_id_foreign` FOREIGN KEY (`author_id`) \
+                 REFERENCES `authors` (`uuid`) ON DELETE SET NULL;",
+                "ALTER TABLE `posts` DROP FOREIGN KEY `posts_user_id_foreign`;",
+            ]
+        );
+        assert_eq!(
+            table.statements_for(&Grammar::Postgres).unwrap()[0],
+            "ALTER TABLE \"posts\" ALTER COLUMN \"body\" TYPE TEXT, ALTER COLUMN \"body\" DROP NOT NULL, \
+             ALTER COLUMN \"body\" DROP DEFAULT;"
+        );
+    }
+
+    #[test]
+    fn test_sqlite_rejects_column_changes() {
+        let mut table = Blueprint::alter("posts");
+        table.text("body").change();
+        assert_eq!(table.statements_for(&Grammar::Sqlite), Err(SchemaError::UnsupportedBySqlite("modify columns")));
+
+        let mut table = Blueprint::alter("posts");
+        table.drop_foreign("posts_user_id_foreign");
+        assert!(table.to_sql_for(&Grammar::Sqlite).unwrap_err().to_string().starts_with("SQLite can't drop foreign keys"));
+
+        // The schema keeps recording and reports the failure when the statements are read
+        let schema = Schema::for_grammar(Grammar::Sqlite);
+        schema
+            .table("posts", |table| {
+                table.foreign("user_id").references("id").on("users");
+            })
+            .drop_if_exists("tags");
+        assert_eq!(schema.to_sql(), Err(SchemaError::UnsupportedBySqlite("add foreign keys to an existing table")));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_alter_table_runs_on_sqlite() {
+        use sqlx::Executor;
+
+        let manager = crate::support::testing::memory_database().await;
+        let pool = manager.default_connection().unwrap();
+        let create = Schema::new().create("posts", |table| {
+            table.id();
+            table.string("title").index();
+            table.integer("legacy_id").nullable();
+            table.integer("user_id");
+            table.timestamp("created_at").nullable();
+        }).to_sql().unwrap();
+        pool.execute(create.as_str()).await.unwrap();
+
+        let alter = Schema::new().table("posts", |table| {
+            table.string("slug").nullable().unique();
+            table.json("meta").nullable();
+            table.rename_column("title", "headline");
+            table.drop_column("legacy_id");
+            table.unique(&["user_id", "slug"]);
+            table.drop_index("posts_title_index");
+        }).to_sql().unwrap();
+        pool.execute(alter.as_str()).await.unwrap();
+
+        let columns: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info('posts')").fetch_all(pool).await.unwrap();
+        assert_eq!(columns, ["id", "headline", "user_id", "created_at", "slug", "meta"]);
+        let indexes: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_index_list('posts') ORDER BY name").fetch_all(pool).await.unwrap();
+        assert_eq!(indexes, ["posts_slug_unique", "posts_user_id_slug_unique"]);
+    }
+}
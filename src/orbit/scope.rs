@@ -0,0 +1,50 @@
+use crate::database::DbRow;
+use crate::orbit::builder::Builder;
+use crate::orbit::Orbit;
+use sqlx::FromRow;
+
+/// A reusable set of query constraints.
+///
+/// As a local scope it is applied on demand: `Post::query().scope(Published)`.
+/// Returned from `Orbit::global_scopes` it is applied to every `query()` of the
+/// model until removed with `without_global_scope(name)`.
+///
+/// struct Published;
+///
+/// impl Scope<Post> for Published {
+///     fn apply(&self, query: Builder<Post>) -> Builder<Post> {
+///         query.where_eq("published", true).where_not_null("published_at")
+///     }
+/// }
+pub trait Scope<T>: Send + Sync {
+    fn apply(&self, query: Builder<T>) -> Builder<T>;
+}
+
+/// Closures work as scopes too: `.scope(|q: Builder<Post>| q.where_eq("featured", true))`
+impl<T, F> Scope<T> for F
+where F: Fn(Builder<T>) -> Builder<T> + Send + Sync
+{
+    fn apply(&self, query: Builder<T>) -> Builder<T> {
+        self(query)
+    }
+}
+
+impl<T> Scope<T> for Box<dyn Scope<T>> {
+    fn apply(&self, query: Builder<T>) -> Builder<T> {
+        (**self).apply(query)
+    }
+}
+
+/// Name of the global scope every `SOFT_DELETES` model gets
+pub const SOFT_DELETES: &str = "soft_deletes";
+
+/// Hides soft deleted rows (`deleted_at IS NULL`)
+pub struct SoftDeletingScope;
+
+impl<T> Scope<T> for SoftDeletingScope
+where T: Orbit + Send + Unpin + for<'r> FromRow<'r, DbRow>
+{
+    fn apply(&self, query: Builder<T>) -> Builder<T> {
+        query.where_null("deleted_at")
+    }
+}
@@ -21,7 +21,7 @@ pub use crate::http::response::{success, success_message, created, accepted, no_
 pub use crate::http::inertia::Inertia;
 pub use crate::http::ignition::{ErrorContext, StackFrame};
 pub use crate::http::policies::{Policy, Authorizer};
-pub use crate::models::{Observer, Observable};
+pub use crate::models::{ModelEvent, Observer, Observable};
 pub use crate::events::{Event, Listener, EventDispatcher};
 pub use crate::services::{PackageManager, Package, PackageManifest, ServiceProvider, scaffold_package};
 pub use crate::http::rate_limiter::{RateLimiter, RateLimitConfig};
@@ -5,6 +5,7 @@ use axum::{Router, routing::any};
 use tower_http::services::ServeDir;
 use crate::framework::AppState;
 use crate::http::middleware::log_request;
+use crate::http::middleware::sticky_reads;
 use crate::http::middleware::csrf_protection;
 use crate::http::middleware::inertia::share_inertia_data;
 use crate::controllers::error::not_found;
@@ -16,11 +17,11 @@ use tower_sessions::cookie::time::Duration;
 use tower_http::cors::CorsLayer;
 use tower_http::compression::CompressionLayer;
 
-#[cfg(feature = "mysql")]
+#[cfg(all(feature = "mysql", not(any(feature = "postgres", feature = "sqlite"))))]
 use tower_sessions_sqlx_store::MySqlStore as SqlxStore;
-#[cfg(feature = "postgres")]
+#[cfg(all(feature = "postgres", not(feature = "sqlite")))]
 use tower_sessions_sqlx_store::PostgresStore as SqlxStore;
-#[cfg(feature = "sqlite")]
+#[cfg(all(feature = "sqlite", not(feature = "any")))]
 use tower_sessions_sqlx_store::SqliteStore as SqlxStore;
 
 /// Combine all route groups and apply global middleware
@@ -46,6 +47,7 @@ pub async fn router(state: AppState) -> Router {
         .nest_service("/public", static_files)
         .nest_service("/build", build_files)
         .fallback(any(not_found).with_state(state.clone()))
+        .layer(axum::middleware::from_fn(sticky_reads))
         .layer(CatchPanicLayer::custom(handle_panic))
         .layer(axum::middleware::from_fn(log_request))
         .layer(CompressionLayer::new()); // Global compression
@@ -57,6 +59,18 @@ pub async fn router(state: AppState) -> Router {
 
     match state.config.session.driver.as_str() {
         "database" => {
+            #[cfg(feature = "any")]
+            if let Some(pool) = &state.db {
+                match database_sessions(app.clone(), pool, &state.config, lifetime, key.clone()).await {
+                    Ok(app) => return app,
+                    Err(e) => {
+                        tracing::warn!("⚠️ Failed to set up the database session store: {}. Falling back to memory.", e);
+                        return memory_sessions(app, lifetime, key);
+                    }
+                }
+            }
+
+            #[cfg(not(feature = "any"))]
             if let Some(pool) = &state.db {
                 let store = SqlxStore::new(pool.clone());
                 store.migrate().await.expect("Failed to migrate session store");
@@ -66,24 +80,78 @@ pub async fn router(state: AppState) -> Router {
                     .with_expiry(Expiry::OnInactivity(Duration::seconds(lifetime)))
                     .with_signed(key);
 
-                app.layer(session_layer)
-            } else {
-                tracing::warn!("⚠️ Database session driver selected but no database connection available. Falling back to memory.");
-                let store = MemoryStore::default();
-                let session_layer = SessionManagerLayer::new(store)
-                    .with_secure(false)
-                    .with_expiry(Expiry::OnInactivity(Duration::seconds(lifetime)))
-                    .with_signed(key);
-                app.layer(session_layer)
+                return app.layer(session_layer);
             }
+
+            tracing::warn!("⚠️ Database session driver selected but no database connection available. Falling back to memory.");
+            memory_sessions(app, lifetime, key)
         },
-        _ => {
-            let store = MemoryStore::default();
-            let session_layer = SessionManagerLayer::new(store)
-                .with_secure(false)
-                .with_expiry(Expiry::OnInactivity(Duration::seconds(lifetime)))
-                .with_signed(key);
-            app.layer(session_layer)
+        _ => memory_sessions(app, lifetime, key),
+    }
+}
+
+fn memory_sessions(app: Router, lifetime: i64, key: Key) -> Router {
+    let store = MemoryStore::default();
+    let session_layer = SessionManagerLayer::new(store)
+        .with_secure(false)
+        .with_expiry(Expiry::OnInactivity(Duration::seconds(lifetime)))
+        .with_signed(key);
+    app.layer(session_layer)
+}
+
+/// The session stores are typed per driver, so in `any` mode they get a pool
+/// of their own on the default connection's database
+#[cfg(feature = "any")]
+async fn database_sessions(
+    app: Router,
+    pool: &crate::database::DbPool,
+    config: &crate::config::Config,
+    lifetime: i64,
+    key: Key,
+) -> Result<Router, Box<dyn std::error::Error>> {
+    use crate::orbit::Grammar;
+    use tower_sessions_sqlx_store::{MySqlStore, PostgresStore, SqliteStore};
+
+    let url = pool.connect_options().database_url.to_string();
+    let connection = config.database.connections.get(&config.database.default);
+    let expiry = Expiry::OnInactivity(Duration::seconds(lifetime));
+    Ok(match Grammar::of(pool) {
+        Grammar::MySql => {
+            let store = MySqlStore::new(session_pool_options(connection, &url).connect(&url).await?);
+            store.migrate().await?;
+            app.layer(SessionManagerLayer::new(store).with_secure(false).with_expiry(expiry).with_signed(key))
+        }
+        Grammar::Postgres => {
+            let store = PostgresStore::new(session_pool_options(connection, &url).connect(&url).await?);
+            store.migrate().await?;
+            app.layer(SessionManagerLayer::new(store).with_secure(false).with_expiry(expiry).with_signed(key))
         }
+        Grammar::Sqlite => {
+            let store = SqliteStore::new(session_pool_options(connection, &url).connect(&url).await?);
+            store.migrate().await?;
+            app.layer(SessionManagerLayer::new(store).with_secure(false).with_expiry(expiry).with_signed(key))
+        }
+    })
+}
+
+/// The session pool's settings, from the connection's config. An in-memory
+/// SQLite database only exists inside its one connection, so that pool keeps
+/// exactly one open for good.
+#[cfg(feature = "any")]
+fn session_pool_options<DB: sqlx::Database>(
+    connection: Option<&crate::config::database::DatabaseConnectionConfig>,
+    url: &str,
+) -> sqlx::pool::PoolOptions<DB> {
+    use std::time::Duration;
+
+    let connection = connection.cloned().unwrap_or_else(|| crate::config::database::DatabaseConnectionConfig::new(url));
+    let options = sqlx::pool::PoolOptions::new().acquire_timeout(Duration::from_secs(5));
+    if url.contains(":memory:") || url.contains("mode=memory") {
+        return options.max_connections(1).min_connections(1).idle_timeout(None).max_lifetime(None);
     }
+    options
+        .max_connections(connection.max_connections)
+        .min_connections(connection.min_connections)
+        .idle_timeout(connection.idle_timeout.map(Duration::from_secs))
+        .max_lifetime(connection.max_lifetime.map(Duration::from_secs))
 }
@@ -1,6 +1,7 @@
 use serde::{Serialize, Deserialize};
 use crate::config::queue::QueueConfig;
 use crate::database::DatabaseManager;
+use crate::orbit::Grammar;
 use redis::Commands;
 use async_trait::async_trait;
 use std::collections::HashMap;
@@ -150,10 +151,11 @@ impl Queue {
 
     async fn log_failed_job(db_manager: &DatabaseManager, config: &QueueConfig, job_name: &str, payload: &str, exception: &str) {
         if let Some(pool) = db_manager.default_connection() {
-            let sql = "INSERT INTO failed_jobs (connection, queue, payload, exception, failed_at) VALUES (?, ?, ?, ?, ?)";
-            let now = Local::now().naive_local();
+            let sql = Grammar::of(pool)
+                .parameterize("INSERT INTO failed_jobs (connection, queue, payload, exception, failed_at) VALUES (?, ?, ?, ?, ?)");
+            let now = crate::database::db_datetime(Local::now().naive_local());
 
-            let res = sqlx::query(sql)
+            let res = sqlx::query(&sql)
                 .bind(&config.driver)
                 .bind(&config.queue_name)
                 .bind(payload)
@@ -89,10 +89,11 @@ impl<'a> JobBuilder<'a> {
                 } else if without_overlapping {
                     // Try to acquire lock for a long time (expires when job done)
                     // We set a long expiry (e.g. 1 hour) just in case of crash
-                    if let Ok(true) = cache.add(&lock_key, "processing", 3600).await {
+                    let lock = cache.lock(&lock_key, 3600);
+                    if let Ok(true) = lock.get().await {
                         job_fn.await;
-                        // Release lock
-                        let _ = cache.forget(&lock_key).await;
+                        // Release lock (unless it expired and another run holds it now)
+                        let _ = lock.release().await;
                     }
                 } else {
                     // Normal execution
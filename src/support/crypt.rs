@@ -0,0 +1,78 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+const NONCE_LEN: usize = 12;
+
+#[derive(Error, Debug)]
+pub enum CryptError {
+    #[error("APP_KEY is not set")]
+    MissingKey,
+    #[error("The payload is invalid")]
+    InvalidPayload,
+    #[error("The payload could not be decrypted")]
+    DecryptFailed,
+}
+
+/// AES-256-GCM encryption keyed by APP_KEY, like Laravel's `Crypt` facade.
+/// Payloads are base64(nonce + ciphertext), so they fit in a text column.
+pub struct Crypt;
+
+#[allow(dead_code)]
+impl Crypt {
+    pub fn encrypt(plaintext: &str) -> Result<String, CryptError> {
+        Self::encrypt_with(&Self::app_key()?, plaintext)
+    }
+
+    pub fn decrypt(payload: &str) -> Result<String, CryptError> {
+        Self::decrypt_with(&Self::app_key()?, payload)
+    }
+
+    pub fn encrypt_with(key: &str, plaintext: &str) -> Result<String, CryptError> {
+        let cipher = Self::cipher(key);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher.encrypt(&nonce, plaintext.as_bytes()).map_err(|_| CryptError::InvalidPayload)?;
+
+        let mut payload = nonce.to_vec();
+        payload.extend(ciphertext);
+        Ok(STANDARD.encode(payload))
+    }
+
+    pub fn decrypt_with(key: &str, payload: &str) -> Result<String, CryptError> {
+        let bytes = STANDARD.decode(payload).map_err(|_| CryptError::InvalidPayload)?;
+        if bytes.len() <= NONCE_LEN {
+            return Err(CryptError::InvalidPayload);
+        }
+
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let plaintext = Self::cipher(key)
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| CryptError::DecryptFailed)?;
+        String::from_utf8(plaintext).map_err(|_| CryptError::InvalidPayload)
+    }
+
+    fn app_key() -> Result<String, CryptError> {
+        std::env::var("APP_KEY").map_err(|_| CryptError::MissingKey)
+    }
+
+    fn cipher(key: &str) -> Aes256Gcm {
+        let digest = Sha256::digest(key.as_bytes());
+        Aes256Gcm::new_from_slice(&digest).expect("a SHA-256 digest is a valid AES-256 key")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let payload = Crypt::encrypt_with("secret", "4111 1111 1111 1111").unwrap();
+        assert_ne!(payload, Crypt::encrypt_with("secret", "4111 1111 1111 1111").unwrap());
+        assert_eq!(Crypt::decrypt_with("secret", &payload).unwrap(), "4111 1111 1111 1111");
+        assert!(matches!(Crypt::decrypt_with("other", &payload), Err(CryptError::DecryptFailed)));
+        assert!(matches!(Crypt::decrypt_with("secret", "not base64!"), Err(CryptError::InvalidPayload)));
+    }
+}
@@ -1,3 +1,4 @@
 pub mod str;
 pub mod arr;
 pub mod testing;
+pub mod crypt;
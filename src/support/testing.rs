@@ -21,7 +21,7 @@ impl TestClient {
     pub async fn new() -> Self {
         // Setup minimal state for testing
         // In a real scenario, you might want to use a test database
-        let db_manager = build_database_manager().await;
+        let db_manager = build_database_manager().await.expect("Invalid database config");
         let tera = build_tera().unwrap_or_else(|_| tera::Tera::default());
         let cache = Cache::Memory(MemoryCache::new());
 
@@ -182,3 +182,24 @@ impl TestResponse {
         serde_json::from_slice(&self.body).expect("Failed to deserialize JSON response")
     }
 }
+
+/// A `DatabaseManager` backed by a fresh in-memory SQLite database.
+/// Run model tests with `cargo test --features sqlite`.
+#[cfg(feature = "sqlite")]
+pub async fn memory_database() -> crate::database::DatabaseManager {
+    use crate::database::{DatabaseManager, DbPoolOptions};
+
+    #[cfg(feature = "any")]
+    sqlx::any::install_default_drivers();
+
+    // A single connection so every query sees the same in-memory database
+    let pool = DbPoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .expect("Failed to open in-memory SQLite database");
+
+    let mut manager = DatabaseManager::new("sqlite".to_string());
+    manager.add("sqlite", pool);
+    manager
+}